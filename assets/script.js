@@ -104,6 +104,163 @@ function confirmAsync(message) {
     });
 }
 
+// Typing [[ in the editor suggests articles to link to. Choosing one
+// inserts a Markdown link.
+function linkAutocomplete(textarea, list) {
+    let start = null;
+    let requested = null;
+    let titles = [];
+    let selected = 0;
+
+    function close() {
+        start = null;
+        requested = null;
+        titles = [];
+        list.classList.remove("show");
+        while (list.lastChild) list.removeChild(list.lastChild);
+    }
+
+    function pendingLink() {
+        const beforeCursor = textarea.value.substring(0, textarea.selectionStart);
+        const match = /\[\[([^\[\]\n]*)$/.exec(beforeCursor);
+        return match && { start: match.index, prefix: match[1] };
+    }
+
+    function select(index) {
+        if (!titles.length) return;
+        selected = (index + titles.length) % titles.length;
+        for (let i = 0; i < list.children.length; ++i) {
+            list.children[i].classList.toggle("selected", i === selected);
+        }
+    }
+
+    function choose(title) {
+        const text = title.title.replace(/[\\\[\]]/g, "\\$&");
+        const link = "[" + text + "](" + (title.slug || ".") + ")";
+        const end = textarea.selectionStart;
+        textarea.value = textarea.value.substring(0, start) + link + textarea.value.substring(end);
+        textarea.selectionStart = textarea.selectionEnd = start + link.length;
+        close();
+        textarea.focus();
+        textarea.dispatchEvent(new Event('input'));
+    }
+
+    function show(result) {
+        while (list.lastChild) list.removeChild(list.lastChild);
+
+        titles = result.titles;
+        titles.forEach(title => {
+            const item = document.createElement("li");
+            item.textContent = title.title;
+            item.addEventListener("mousedown", ev => {
+                ev.preventDefault();
+                choose(title);
+            });
+            list.appendChild(item);
+        });
+
+        list.classList.toggle("show", titles.length > 0);
+        select(0);
+    }
+
+    function update() {
+        const pending = pendingLink();
+        if (!pending) return close();
+
+        start = pending.start;
+        if (pending.prefix === requested) return;
+        requested = pending.prefix;
+
+        fetch(
+            "_titles?limit=8&prefix=" + encodeURIComponent(pending.prefix),
+            {
+                headers: {
+                    "Accept": "application/json",
+                },
+                credentials: "same-origin",
+            }
+        ).then(response => {
+            if (!response.ok) throw new Error("Unexpected status code (" + response.status + ")");
+
+            return response.json();
+        }).then(result => {
+            // Ignore responses that have been overtaken by further typing
+            if (result.prefix === requested) show(result);
+        }).catch(err => {
+            console.error(err);
+            close();
+        });
+    }
+
+    textarea.addEventListener('input', update);
+    textarea.addEventListener('blur', close);
+    textarea.addEventListener('keydown', ev => {
+        if (!titles.length) return;
+
+        if (ev.key === 'ArrowDown' || ev.key === 'ArrowUp') {
+            select(selected + (ev.key === 'ArrowDown' ? 1 : -1));
+        } else if (ev.key === 'Enter' || ev.key === 'Tab') {
+            choose(titles[selected]);
+        } else if (ev.key === 'Escape') {
+            close();
+        } else {
+            return;
+        }
+
+        ev.preventDefault();
+        ev.stopPropagation();
+    });
+}
+
+// Warn when someone else edits the article being edited, so it does not
+// come as a surprise in the form of a merge conflict when saving. Returns a
+// function that stops watching.
+function liveChangeNotice(container, form) {
+    const articleId = container.dataset.articleId;
+    if (!articleId || !window.EventSource) return () => {};
+
+    const notice = document.createElement("div");
+    notice.className = "notice";
+
+    const source = new EventSource("_changes/stream?article_id=" + encodeURIComponent(articleId));
+    source.addEventListener("change", ev => {
+        const change = JSON.parse(ev.data);
+
+        // Our own saves are reported as well. Wait for them to update the
+        // form before deciding whether the change is news.
+        Promise.resolve(pendingSave).then(() => {
+            if (change.revision <= Number(form.elements.base_revision.value)) return;
+
+            while (notice.lastChild) notice.removeChild(notice.lastChild);
+            notice.appendChild(document.createTextNode(
+                "This article was just edited by " + (change.author || "an anonymous user") + ". "
+            ));
+            const link = document.createElement("a");
+            link.setAttribute("href", change.diff_link || change.link);
+            link.textContent = "See what changed";
+            notice.appendChild(link);
+
+            if (!notice.parentNode) container.parentNode.insertBefore(notice, container);
+        });
+    });
+
+    return () => {
+        source.close();
+        if (notice.parentNode) notice.parentNode.removeChild(notice);
+    };
+}
+
+let pendingSave = null;
+let stopLiveChangeNotice = null;
+function closeEditor(container) {
+    container.classList.remove('edit');
+
+    if (stopLiveChangeNotice) {
+        stopLiveChangeNotice();
+        stopLiveChangeNotice = null;
+    }
+}
+
 let hasBeenOpen = false;
 function openEditor() {
     const container = document.querySelector(".container");
@@ -121,6 +278,8 @@ function openEditor() {
 
     container.classList.add('edit');
 
+    if (!stopLiveChangeNotice) stopLiveChangeNotice = liveChangeNotice(container, form);
+
     autosizeTextarea(textarea, shadow);
 
     textarea.focus();
@@ -131,6 +290,8 @@ function openEditor() {
     textarea.addEventListener('input', () => autosizeTextarea(textarea, shadow));
     window.addEventListener('resize', () => autosizeTextarea(textarea, shadow));
 
+    linkAutocomplete(textarea, editor.querySelector('.link-suggestions'));
+
     form.addEventListener("submit", function (ev) {
         ev.preventDefault();
         ev.stopPropagation();
@@ -139,7 +300,7 @@ function openEditor() {
         textarea.disabled = true;
         // TODO Disable other interaction as well: title editor, cancel and OK buttons
 
-        fetch(
+        pendingSave = fetch(
             form.getAttribute("action"),
             {
                 method: 'PUT',
@@ -184,7 +345,7 @@ function openEditor() {
                     }
 
                     if (!result.conflict) {
-                        container.classList.remove('edit');
+                        closeEditor(container);
                     }
 
                     textarea.disabled = false;
@@ -210,7 +371,7 @@ function openEditor() {
         Promise.resolve(!isEdited(form) || confirmAsync("Discard changes?"))
             .then(doReset => {
                 if (doReset) {
-                    container.classList.remove('edit');
+                    closeEditor(container);
                     form.reset();
                 }
             });
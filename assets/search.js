@@ -12,6 +12,20 @@ function debouncer(interval, callback) {
     };
 }
 
+function setHighlightedText(element, text, highlights) {
+    let last = 0;
+    for (const highlight of highlights) {
+        element.appendChild(document.createTextNode(text.substring(last, highlight.start)));
+
+        const mark = document.createElement("mark");
+        mark.textContent = text.substring(highlight.start, highlight.end);
+        element.appendChild(mark);
+
+        last = highlight.end;
+    }
+    element.appendChild(document.createTextNode(text.substring(last)));
+}
+
 (function () {
     const form = document.querySelector('form.search');
     const input = form.querySelector('input');
@@ -24,11 +38,52 @@ function debouncer(interval, callback) {
         }
     }
 
+    let frontPage = null;
+    function showFrontPage() {
+        if (!frontPage) {
+            frontPage = fetch(
+                "_search?limit=4",
+                {
+                    headers: {
+                        "Accept": "application/json",
+                    },
+                    credentials: "same-origin",
+                }
+            ).then(response => {
+                if (!response.ok) throw new Error("Unexpected status code (" + response.status + ")");
+
+                return response.json();
+            });
+            frontPage.catch(err => {
+                console.error(err);
+                frontPage = null;
+            });
+        }
+
+        frontPage.then(result => {
+            if (input.value !== "") return;
+
+            clearChildren(results);
+
+            result.recent.forEach((article, index) => {
+                const item = resultPrototype.cloneNode(true);
+                item.querySelector('.link').href = article.slug || ".";
+                item.querySelector('.link').setAttribute("data-focusindex", index + 1);
+                item.querySelector('.title').textContent = article.title;
+                item.querySelector('.snippet').textContent = "recently changed";
+                results.appendChild(item);
+            });
+
+            results.classList.toggle("show", result.recent.length > 0);
+        }, () => {});
+    }
+
     let ongoing = false;
     function submit() {
         if (input.value === "") {
             results.classList.remove("show");
             clearChildren(results);
+            showFrontPage();
             return;
         }
 
@@ -55,8 +110,8 @@ function debouncer(interval, callback) {
                 const item = resultPrototype.cloneNode(true);
                 item.querySelector('.link').href = hit.slug || ".";
                 item.querySelector('.link').setAttribute("data-focusindex", index + 1);
-                item.querySelector('.title').textContent = hit.title;
-                item.querySelector('.snippet').textContent = hit.snippet;
+                setHighlightedText(item.querySelector('.title'), hit.title, hit.title_highlights);
+                setHighlightedText(item.querySelector('.snippet'), hit.snippet, hit.snippet_highlights);
                 results.appendChild(item);
             })
 
@@ -93,6 +148,9 @@ function debouncer(interval, callback) {
     input.addEventListener('input', submitter);
 
     form.addEventListener('focusin', () => form.classList.add("focus"));
+    input.addEventListener('focus', () => {
+        if (input.value === "" && !results.lastChild) showFrontPage();
+    });
     form.addEventListener('focusout', function (ev) {
         for (let ancestor = ev.relatedTarget; ancestor; ancestor = ancestor.parentElement) {
             if (ancestor === form) return;
@@ -40,6 +40,41 @@ fn main() {
             infer_table_from_schema!(#db_path, "article_revisions");
         }
         pub use self::__diesel_infer_schema_article_revisions::*;
+
+        mod __diesel_infer_schema_article_includes {
+            infer_table_from_schema!(#db_path, "article_includes");
+        }
+        pub use self::__diesel_infer_schema_article_includes::*;
+
+        mod __diesel_infer_schema_rendered_article_revisions {
+            infer_table_from_schema!(#db_path, "rendered_article_revisions");
+        }
+        pub use self::__diesel_infer_schema_rendered_article_revisions::*;
+
+        mod __diesel_infer_schema_attachments {
+            infer_table_from_schema!(#db_path, "attachments");
+        }
+        pub use self::__diesel_infer_schema_attachments::*;
+
+        mod __diesel_infer_schema_attachment_thumbnails {
+            infer_table_from_schema!(#db_path, "attachment_thumbnails");
+        }
+        pub use self::__diesel_infer_schema_attachment_thumbnails::*;
+
+        mod __diesel_infer_schema_webhook_deliveries {
+            infer_table_from_schema!(#db_path, "webhook_deliveries");
+        }
+        pub use self::__diesel_infer_schema_webhook_deliveries::*;
+
+        mod __diesel_infer_schema_watches {
+            infer_table_from_schema!(#db_path, "watches");
+        }
+        pub use self::__diesel_infer_schema_watches::*;
+
+        mod __diesel_infer_schema_notification_cursors {
+            infer_table_from_schema!(#db_path, "notification_cursors");
+        }
+        pub use self::__diesel_infer_schema_notification_cursors::*;
     }.as_str().as_bytes()).expect("Unable to write to file");
 
     for entry in WalkDir::new("migrations").into_iter().filter_map(|e| e.ok()) {
@@ -0,0 +1,12 @@
+-- Which articles include which other articles with {{include:slug}}, as of
+-- their latest revision. Targets are tracked by slug, because they may not
+-- exist yet.
+CREATE TABLE article_includes (
+    article_id INTEGER NOT NULL,
+    included_slug TEXT NOT NULL,
+
+    PRIMARY KEY (article_id, included_slug),
+    FOREIGN KEY (article_id) REFERENCES articles(id)
+);
+
+CREATE INDEX article_includes_by_slug ON article_includes (included_slug);
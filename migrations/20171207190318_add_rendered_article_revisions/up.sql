@@ -0,0 +1,11 @@
+-- Persisted cache of rendered HTML. Rows are only valid for the
+-- render_options they were rendered with.
+CREATE TABLE rendered_article_revisions (
+    article_id INTEGER NOT NULL,
+    revision INTEGER NOT NULL,
+    render_options TEXT NOT NULL,
+    html TEXT NOT NULL,
+
+    PRIMARY KEY (article_id, revision),
+    FOREIGN KEY (article_id, revision) REFERENCES article_revisions(article_id, revision)
+);
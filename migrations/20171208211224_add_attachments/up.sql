@@ -0,0 +1,25 @@
+-- Files attached to the wiki, versioned by name. Uploading a file with an
+-- existing name adds a new revision.
+CREATE TABLE attachments (
+    sequence_number INTEGER PRIMARY KEY NOT NULL,
+
+    name TEXT NOT NULL,
+    revision INTEGER NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    author TEXT,
+
+    -- The article the file was uploaded from, if any
+    article_id INTEGER,
+
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    data BLOB NOT NULL,
+
+    latest BOOLEAN NOT NULL,
+
+    FOREIGN KEY (article_id) REFERENCES articles(id)
+);
+
+CREATE UNIQUE INDEX unique_revision_per_attachment ON attachments (name, revision);
+CREATE UNIQUE INDEX unique_latest_revision_per_attachment ON attachments (name) WHERE latest=1;
+CREATE INDEX attachments_by_article ON attachments (article_id) WHERE latest=1;
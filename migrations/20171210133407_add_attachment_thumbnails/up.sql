@@ -0,0 +1,13 @@
+-- Scaled down versions of image attachments, generated on demand.
+-- data is NULL when the original is no wider than the requested width and
+-- should be served as is.
+CREATE TABLE attachment_thumbnails (
+    attachment_sequence_number INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+
+    content_type TEXT NOT NULL,
+    data BLOB,
+
+    PRIMARY KEY (attachment_sequence_number, width),
+    FOREIGN KEY (attachment_sequence_number) REFERENCES attachments(sequence_number)
+);
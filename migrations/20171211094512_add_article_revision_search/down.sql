@@ -0,0 +1,4 @@
+DROP TRIGGER article_revision_search_ai;
+DROP TRIGGER article_revision_search_ad;
+
+DROP TABLE article_revision_search;
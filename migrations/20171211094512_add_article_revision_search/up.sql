@@ -0,0 +1,20 @@
+-- Full text index over all revisions, for searching the history. The text
+-- is not duplicated, but read from article_revisions.
+CREATE VIRTUAL TABLE article_revision_search USING fts5(
+    title,
+    body,
+    content = 'article_revisions',
+    content_rowid = 'sequence_number',
+    tokenize = 'porter unicode61 remove_diacritics 1'
+);
+
+INSERT INTO article_revision_search(article_revision_search) VALUES ('rebuild');
+
+-- Revisions are never changed after they are inserted, except for the
+-- latest flag which is not indexed
+CREATE TRIGGER article_revision_search_ai AFTER INSERT ON article_revisions BEGIN
+  INSERT INTO article_revision_search(rowid, title, body) VALUES (new.sequence_number, new.title, new.body);
+END;
+CREATE TRIGGER article_revision_search_ad AFTER DELETE ON article_revisions BEGIN
+  INSERT INTO article_revision_search(article_revision_search, rowid, title, body) VALUES ('delete', old.sequence_number, old.title, old.body);
+END;
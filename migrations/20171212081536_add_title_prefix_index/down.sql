@@ -0,0 +1 @@
+DROP INDEX title_prefix_lookup;
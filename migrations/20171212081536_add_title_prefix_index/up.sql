@@ -0,0 +1,2 @@
+-- For case insensitive prefix matching of titles with LIKE
+CREATE INDEX title_prefix_lookup ON article_revisions (title COLLATE NOCASE) WHERE latest=1;
@@ -0,0 +1,2 @@
+DROP INDEX pending_webhook_deliveries;
+DROP TABLE webhook_deliveries;
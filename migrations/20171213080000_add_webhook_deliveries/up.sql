@@ -0,0 +1,20 @@
+-- Outgoing webhook requests, queued in the same transaction as the change
+-- they announce. Rows are kept after delivery as a log of the outcome.
+CREATE TABLE webhook_deliveries (
+    id INTEGER PRIMARY KEY NOT NULL,
+
+    url TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    attempts INTEGER NOT NULL DEFAULT 0,
+
+    -- NULL when delivered or given up on
+    next_attempt TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    delivered TIMESTAMP,
+
+    -- The response status or error of the latest attempt
+    last_result TEXT
+);
+
+CREATE INDEX pending_webhook_deliveries ON webhook_deliveries (next_attempt) WHERE next_attempt IS NOT NULL;
@@ -0,0 +1,3 @@
+DROP TABLE notification_cursors;
+DROP INDEX watches_by_article;
+DROP TABLE watches;
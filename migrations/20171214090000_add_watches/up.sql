@@ -0,0 +1,18 @@
+-- Articles followed by each identity, as given by X-Identity
+CREATE TABLE watches (
+    identity TEXT NOT NULL,
+    article_id INTEGER NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (identity, article_id),
+    FOREIGN KEY (article_id) REFERENCES articles(id)
+);
+
+CREATE INDEX watches_by_article ON watches (article_id);
+
+-- The sequence_number of the latest change each identity has been notified
+-- about. Changes after it go into the next email digest.
+CREATE TABLE notification_cursors (
+    identity TEXT PRIMARY KEY NOT NULL,
+    sequence_number INTEGER NOT NULL
+);
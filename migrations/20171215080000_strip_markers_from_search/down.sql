@@ -0,0 +1,46 @@
+DROP TRIGGER article_revision_search_ai;
+DROP TRIGGER article_revision_search_ad;
+DROP TABLE article_revision_search;
+DROP VIEW article_revision_search_content;
+
+CREATE VIRTUAL TABLE article_revision_search USING fts5(
+    title,
+    body,
+    content = 'article_revisions',
+    content_rowid = 'sequence_number',
+    tokenize = 'porter unicode61 remove_diacritics 1'
+);
+
+INSERT INTO article_revision_search(article_revision_search) VALUES ('rebuild');
+
+CREATE TRIGGER article_revision_search_ai AFTER INSERT ON article_revisions BEGIN
+  INSERT INTO article_revision_search(rowid, title, body) VALUES (new.sequence_number, new.title, new.body);
+END;
+CREATE TRIGGER article_revision_search_ad AFTER DELETE ON article_revisions BEGIN
+  INSERT INTO article_revision_search(article_revision_search, rowid, title, body) VALUES ('delete', old.sequence_number, old.title, old.body);
+END;
+
+DROP TRIGGER article_revisions_ai;
+DROP TRIGGER article_revisions_ad;
+DROP TRIGGER article_revisions_au_disable;
+DROP TRIGGER article_revisions_au_enable;
+
+CREATE TRIGGER article_revisions_ai AFTER INSERT ON article_revisions WHEN new.latest = 1 BEGIN
+  DELETE FROM article_search WHERE rowid = new.article_id;
+  INSERT INTO article_search(rowid, title, body, slug) VALUES (new.article_id, new.title, new.body, new.slug);
+END;
+CREATE TRIGGER article_revisions_ad AFTER DELETE ON article_revisions WHEN old.latest = 1 BEGIN
+  DELETE FROM article_search WHERE rowid = old.article_id;
+END;
+
+-- Index unique_latest_revision_per_article_id makes sure the following is sufficient:
+CREATE TRIGGER article_revisions_au_disable AFTER UPDATE ON article_revisions WHEN old.latest = 1 AND new.latest = 0 BEGIN
+  DELETE FROM article_search WHERE rowid = old.article_id;
+END;
+CREATE TRIGGER article_revisions_au_enable AFTER UPDATE ON article_revisions WHEN old.latest = 0 AND new.latest = 1 BEGIN
+  INSERT INTO article_search(rowid, title, body, slug) VALUES (new.article_id, new.title, new.body, new.slug);
+END;
+
+DELETE FROM article_search;
+INSERT INTO article_search(rowid, title, body, slug)
+    SELECT article_id, title, body, slug FROM article_revisions WHERE latest = 1;
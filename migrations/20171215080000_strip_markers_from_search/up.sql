@@ -0,0 +1,82 @@
+-- U+E000 and U+E001 mark the matched terms in highlighted search results,
+-- so they are stripped from the indexed text
+
+DROP TRIGGER article_revisions_ai;
+DROP TRIGGER article_revisions_ad;
+DROP TRIGGER article_revisions_au_disable;
+DROP TRIGGER article_revisions_au_enable;
+
+CREATE TRIGGER article_revisions_ai AFTER INSERT ON article_revisions WHEN new.latest = 1 BEGIN
+  DELETE FROM article_search WHERE rowid = new.article_id;
+  INSERT INTO article_search(rowid, title, body, slug) VALUES (
+    new.article_id,
+    replace(replace(new.title, char(57344), ''), char(57345), ''),
+    replace(replace(new.body, char(57344), ''), char(57345), ''),
+    new.slug
+  );
+END;
+CREATE TRIGGER article_revisions_ad AFTER DELETE ON article_revisions WHEN old.latest = 1 BEGIN
+  DELETE FROM article_search WHERE rowid = old.article_id;
+END;
+
+-- Index unique_latest_revision_per_article_id makes sure the following is sufficient:
+CREATE TRIGGER article_revisions_au_disable AFTER UPDATE ON article_revisions WHEN old.latest = 1 AND new.latest = 0 BEGIN
+  DELETE FROM article_search WHERE rowid = old.article_id;
+END;
+CREATE TRIGGER article_revisions_au_enable AFTER UPDATE ON article_revisions WHEN old.latest = 0 AND new.latest = 1 BEGIN
+  INSERT INTO article_search(rowid, title, body, slug) VALUES (
+    new.article_id,
+    replace(replace(new.title, char(57344), ''), char(57345), ''),
+    replace(replace(new.body, char(57344), ''), char(57345), ''),
+    new.slug
+  );
+END;
+
+-- Also gives every row its article_id as rowid, which the refill in
+-- 20171025121618_fix_article_search_triggers did not
+DELETE FROM article_search;
+INSERT INTO article_search(rowid, title, body, slug)
+    SELECT
+        article_id,
+        replace(replace(title, char(57344), ''), char(57345), ''),
+        replace(replace(body, char(57344), ''), char(57345), ''),
+        slug
+    FROM article_revisions WHERE latest = 1;
+
+-- The history index reads its text through a view that strips the markers
+DROP TRIGGER article_revision_search_ai;
+DROP TRIGGER article_revision_search_ad;
+DROP TABLE article_revision_search;
+
+CREATE VIEW article_revision_search_content AS
+    SELECT
+        sequence_number,
+        replace(replace(title, char(57344), ''), char(57345), '') AS title,
+        replace(replace(body, char(57344), ''), char(57345), '') AS body
+    FROM article_revisions;
+
+CREATE VIRTUAL TABLE article_revision_search USING fts5(
+    title,
+    body,
+    content = 'article_revision_search_content',
+    content_rowid = 'sequence_number',
+    tokenize = 'porter unicode61 remove_diacritics 1'
+);
+
+INSERT INTO article_revision_search(article_revision_search) VALUES ('rebuild');
+
+CREATE TRIGGER article_revision_search_ai AFTER INSERT ON article_revisions BEGIN
+  INSERT INTO article_revision_search(rowid, title, body) VALUES (
+    new.sequence_number,
+    replace(replace(new.title, char(57344), ''), char(57345), ''),
+    replace(replace(new.body, char(57344), ''), char(57345), '')
+  );
+END;
+CREATE TRIGGER article_revision_search_ad AFTER DELETE ON article_revisions BEGIN
+  INSERT INTO article_revision_search(article_revision_search, rowid, title, body) VALUES (
+    'delete',
+    old.sequence_number,
+    replace(replace(old.title, char(57344), ''), char(57345), ''),
+    replace(replace(old.body, char(57344), ''), char(57345), '')
+  );
+END;
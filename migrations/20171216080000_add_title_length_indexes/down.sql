@@ -0,0 +1,2 @@
+DROP INDEX slug_length_lookup;
+DROP INDEX title_length_lookup;
@@ -0,0 +1,3 @@
+-- For narrowing down the candidates for title suggestions by length
+CREATE INDEX slug_length_lookup ON article_revisions (length(slug)) WHERE latest=1;
+CREATE INDEX title_length_lookup ON article_revisions (length(title)) WHERE latest=1;
@@ -0,0 +1,76 @@
+use std::fmt::Write;
+
+use rendering::escape_html;
+
+const WIDTH: u32 = 600;
+const HEIGHT: u32 = 150;
+const LABEL_HEIGHT: u32 = 20;
+
+/// Draw the given labelled values as an SVG bar chart, scaled to the
+/// largest value. Every bar carries its label and value as a tooltip, and
+/// the first and last labels are written under the chart.
+pub fn bar_chart(bars: &[(String, i64)]) -> String {
+    let max = bars.iter().map(|&(_, value)| value).max().unwrap_or(0).max(1);
+    let chart_height = HEIGHT - LABEL_HEIGHT;
+    let slot = WIDTH as f64 / bars.len().max(1) as f64;
+
+    let mut svg = String::new();
+    write!(svg,
+        "<svg class=\"chart\" xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" role=\"img\">",
+        WIDTH, HEIGHT
+    ).unwrap();
+
+    for (i, &(ref label, value)) in bars.iter().enumerate() {
+        let height = chart_height as f64 * value.max(0) as f64 / max as f64;
+        write!(svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{}: {}</title></rect>",
+            i as f64 * slot + slot * 0.1,
+            chart_height as f64 - height,
+            slot * 0.8,
+            height,
+            escape_html(label),
+            value
+        ).unwrap();
+    }
+
+    if let (Some(first), Some(last)) = (bars.first(), bars.last()) {
+        write!(svg,
+            "<text x=\"0\" y=\"{0}\">{1}</text><text x=\"{2}\" y=\"{0}\" text-anchor=\"end\">{3}</text>",
+            HEIGHT - 4,
+            escape_html(&first.0),
+            WIDTH,
+            escape_html(&last.0)
+        ).unwrap();
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bars_are_scaled_to_the_largest_value() {
+        let svg = bar_chart(&[("a".to_owned(), 1), ("b".to_owned(), 4), ("c".to_owned(), 0)]);
+
+        assert_eq!(3, svg.matches("<rect ").count());
+        assert!(svg.contains("height=\"130.0\"><title>b: 4</title>"));
+        assert!(svg.contains("height=\"32.5\"><title>a: 1</title>"));
+        assert!(svg.contains("height=\"0.0\"><title>c: 0</title>"));
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        let svg = bar_chart(&[("<a&b>".to_owned(), 1)]);
+
+        assert!(svg.contains("&lt;a&amp;b&gt;"));
+        assert!(!svg.contains("<a&b>"));
+    }
+
+    #[test]
+    fn empty_chart() {
+        assert!(!bar_chart(&[]).contains("<rect "));
+    }
+}
@@ -0,0 +1,370 @@
+use std;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Local, TimeZone};
+use diff;
+use futures::Future;
+
+use build_config;
+use models;
+use state::State;
+
+pub type Error = Box<std::error::Error + Send + Sync>;
+
+/// Changes to watched articles are collected and sent as one email per
+/// identity this often
+const DIGEST_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// The most changes to include in one digest. Further changes go into the
+/// next one.
+const DIGEST_MAX_CHANGES: i32 = 50;
+
+const DIFF_MAX_LINES: usize = 20;
+
+/// Longer lines of a diff are cut, to stay well within the line length
+/// limit of email
+const DIFF_MAX_LINE_LENGTH: usize = 200;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends notification emails through an SMTP server without
+/// authentication or TLS, such as a local relay
+pub struct Mailer {
+    server: String,
+    from: String,
+    domain: Option<String>,
+    base_url: Option<String>,
+}
+
+impl Mailer {
+    pub fn new(server: String, from: String, domain: Option<String>, base_url: Option<String>) -> Mailer {
+        Mailer { server, from, domain, base_url }
+    }
+
+    /// Identities that are email addresses are used as they are. Others
+    /// are combined with the configured domain, if any.
+    pub fn address(&self, identity: &str) -> Option<String> {
+        if identity.contains('@') {
+            Some(identity.to_owned())
+        } else {
+            self.domain.as_ref().map(|domain| format!("{}@{}", identity, domain))
+        }
+    }
+
+    pub fn digest_subject(&self, digest: &models::NotificationDigest) -> String {
+        let count = digest.changes.len();
+        format!("[{}] {} change{} to articles you watch",
+            build_config::PROJECT_NAME, count, if count == 1 { "" } else { "s" })
+    }
+
+    pub fn digest_body(&self, digest: &models::NotificationDigest) -> String {
+        let base_url = self.base_url.as_ref().map(|x| &x[..]).unwrap_or("");
+        let mut body = String::from("These articles you watch have changed:\n");
+
+        for &(ref revision, ref previous) in &digest.changes {
+            let link = match previous {
+                &Some(ref previous) => format!("{}_diff/{}?from={}&to={}",
+                    base_url, revision.article_id, previous.revision, revision.revision),
+                &None => format!("{}_revisions/{}/{}", base_url, revision.article_id, revision.revision),
+            };
+
+            body.push_str(&format!("\n{}\n{} by {}\n{}\n\n{}",
+                revision.title,
+                Local.from_utc_datetime(&revision.created).to_rfc2822(),
+                revision.author.as_ref().map(|x| &x[..]).unwrap_or("Anonymous"),
+                link,
+                text_diff(previous.as_ref().map(|x| &x.body[..]).unwrap_or(""), &revision.body),
+            ));
+        }
+
+        body.push_str(&format!("\nChange which articles you watch at {}_watchlist\n", base_url));
+        body
+    }
+
+    /// Send a plain text email
+    pub fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), Error> {
+        let stream = TcpStream::connect(&self.server[..])?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let mut command = |command: &str, expected: &[u16]| -> Result<(), Error> {
+            if !command.is_empty() {
+                writer.write_all(command.as_bytes())?;
+                writer.write_all(b"\r\n")?;
+            }
+            expect_reply(&mut reader, expected)
+        };
+
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+                Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            self.from, to, subject, Local::now().to_rfc2822()
+        );
+        for line in body.lines() {
+            // Dot-stuffing, so no line of the body ends the message
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push('.');
+
+        command("", &[220])?;
+        command("HELO localhost", &[250])?;
+        command(&format!("MAIL FROM:<{}>", self.from), &[250])?;
+        command(&format!("RCPT TO:<{}>", to), &[250, 251])?;
+        command("DATA", &[354])?;
+        command(&message, &[250])?;
+        command("QUIT", &[221])?;
+
+        Ok(())
+    }
+}
+
+/// Read a possibly multiline SMTP reply and check its code
+fn expect_reply<R: BufRead>(reader: &mut R, expected: &[u16]) -> Result<(), Error> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            Err("SMTP server closed the connection")?;
+        }
+
+        // The last line of a reply has a space after the code
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            let code = line.get(..3).and_then(|x| x.parse::<u16>().ok());
+            return match code {
+                Some(code) if expected.contains(&code) => Ok(()),
+                _ => Err(format!("Unexpected SMTP reply: {}", line.trim_right()).into()),
+            };
+        }
+    }
+}
+
+/// The number of added and removed lines followed by the lines themselves,
+/// as in a unified diff without context
+pub fn text_diff(from: &str, to: &str) -> String {
+    let changes = diff::lines(from, to)
+        .into_iter()
+        .filter_map(|x| match x {
+            diff::Result::Left(x) => Some(('-', x)),
+            diff::Result::Both(..) => None,
+            diff::Result::Right(x) => Some(('+', x)),
+        })
+        .collect::<Vec<_>>();
+
+    let added = changes.iter().filter(|x| x.0 == '+').count();
+    let removed = changes.len() - added;
+
+    let mut text = format!(
+        "{} line{} added, {} line{} removed\n",
+        added, if added == 1 { "" } else { "s" },
+        removed, if removed == 1 { "" } else { "s" }
+    );
+
+    for &(sign, line) in changes.iter().take(DIFF_MAX_LINES) {
+        let line = match line.char_indices().nth(DIFF_MAX_LINE_LENGTH) {
+            Some((end, _)) => format!("{}\u{2026}", &line[..end]),
+            None => line.to_owned(),
+        };
+        text.push_str(&format!("{} {}\n", sign, line));
+    }
+    if changes.len() > DIFF_MAX_LINES {
+        text.push_str("\u{2026}\n");
+    }
+
+    text
+}
+
+/// Start the thread that emails each identity a digest of the changes to
+/// the articles it watches every `DIGEST_INTERVAL`. A digest that cannot be
+/// sent is tried again with the next batch. Identities without an email
+/// address get no digests.
+pub fn spawn(state: State, mailer: Mailer) -> Result<(), Box<std::error::Error>> {
+    thread::Builder::new()
+        .name("email".to_owned())
+        .spawn(move || loop {
+            thread::sleep(DIGEST_INTERVAL);
+
+            let digests = match state.get_notification_digests(DIGEST_MAX_CHANGES).wait() {
+                Ok(x) => x,
+                Err(err) => {
+                    eprintln!("Unable to read notification digests: {}", err);
+                    continue;
+                }
+            };
+
+            for digest in digests {
+                let done = match mailer.address(&digest.identity) {
+                    // Nowhere to send the changes, so skip them instead of
+                    // reading them again with every batch
+                    None => true,
+                    Some(to) => match mailer.send(&to, &mailer.digest_subject(&digest), &mailer.digest_body(&digest)) {
+                        Ok(()) => true,
+                        Err(err) => {
+                            eprintln!("Unable to email {}: {}", to, err);
+                            false
+                        },
+                    },
+                };
+
+                if done {
+                    let identity = digest.identity.clone();
+                    if let Err(err) = state.advance_notification_cursor(digest.identity, digest.up_to).wait() {
+                        eprintln!("Unable to record notification of {}: {}", identity, err);
+                    }
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Accept a single SMTP session on a local port. Returns the address
+    /// to connect to and the commands and message data as received.
+    fn smtp_sink() -> (String, mpsc::Receiver<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut received = vec![];
+            let mut in_data = false;
+
+            stream.write_all(b"220 sink\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                received.push(line.clone());
+
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 Queued\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 Go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    b"221 Bye\r\n"
+                } else if line.starts_with("HELO") {
+                    b"250-sink\r\n250 Hello\r\n"
+                } else {
+                    b"250 OK\r\n"
+                };
+                stream.write_all(reply).unwrap();
+            }
+
+            tx.send(received).unwrap();
+        });
+
+        (address, rx)
+    }
+
+    fn revision(revision: i32, body: &str, author: Option<&str>) -> models::ArticleRevision {
+        models::ArticleRevision {
+            sequence_number: revision,
+            article_id: 1,
+            revision,
+            created: ::chrono::NaiveDate::from_ymd(2017, 12, 14).and_hms(9, 0, 0),
+            slug: "deploys".to_owned(),
+            title: "Deploys".to_owned(),
+            body: body.to_owned(),
+            latest: true,
+            author: author.map(|x| x.to_owned()),
+        }
+    }
+
+    #[test]
+    fn sends_through_smtp() {
+        let (address, received) = smtp_sink();
+        let mailer = Mailer::new(address, "wiki@example.com".to_owned(), None, None);
+
+        mailer.send("alice@example.com", "Subject", "Hello\n.hidden\nBye").unwrap();
+
+        let received = received.recv().unwrap();
+        assert_eq!("MAIL FROM:<wiki@example.com>\r\n", received[1]);
+        assert_eq!("RCPT TO:<alice@example.com>\r\n", received[2]);
+        assert_eq!("DATA\r\n", received[3]);
+        assert!(received.contains(&"Subject: Subject\r\n".to_owned()));
+        assert!(received.contains(&"..hidden\r\n".to_owned()));
+        assert_eq!(vec!["Bye\r\n", ".\r\n", "QUIT\r\n"], &received[received.len() - 3..]);
+    }
+
+    #[test]
+    fn rejected_recipient_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mailer = Mailer::new(listener.local_addr().unwrap().to_string(), "wiki@example.com".to_owned(), None, None);
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"220 sink\r\n").unwrap();
+            for reply in &[&b"250 Hello\r\n"[..], b"250 OK\r\n", b"550 No such user\r\n"] {
+                reader.read_line(&mut String::new()).unwrap();
+                stream.write_all(reply).unwrap();
+            }
+        });
+
+        let err = mailer.send("nobody@example.com", "Subject", "Body").unwrap_err();
+        assert_eq!("Unexpected SMTP reply: 550 No such user", err.to_string());
+    }
+
+    #[test]
+    fn address_of_identity() {
+        let mailer = Mailer::new("".to_owned(), "".to_owned(), Some("example.com".to_owned()), None);
+        assert_eq!(Some("alice@example.com".to_owned()), mailer.address("alice"));
+        assert_eq!(Some("bob@example.org".to_owned()), mailer.address("bob@example.org"));
+
+        let mailer = Mailer::new("".to_owned(), "".to_owned(), None, None);
+        assert_eq!(None, mailer.address("alice"));
+    }
+
+    #[test]
+    fn digest_has_summary_and_diff() {
+        let mailer = Mailer::new("".to_owned(), "".to_owned(), None, Some("https://wiki.example.com/".to_owned()));
+        let digest = models::NotificationDigest {
+            identity: "alice".to_owned(),
+            changes: vec![(revision(2, "a\nc\n", Some("bob")), Some(revision(1, "a\nb\n", None)))],
+            up_to: 2,
+        };
+
+        assert_eq!(
+            format!("[{}] 1 change to articles you watch", build_config::PROJECT_NAME),
+            mailer.digest_subject(&digest)
+        );
+
+        let body = mailer.digest_body(&digest);
+        assert!(body.contains("\nDeploys\n"));
+        assert!(body.contains(" by bob\nhttps://wiki.example.com/_diff/1?from=1&to=2\n"));
+        assert!(body.contains("1 line added, 1 line removed\n- b\n+ c\n"));
+        assert!(body.ends_with("https://wiki.example.com/_watchlist\n"));
+    }
+
+    #[test]
+    fn text_diff_is_limited() {
+        let to = (0..30).map(|x| format!("{}\n", x)).collect::<String>();
+        let diff = text_diff("", &to);
+
+        assert!(diff.starts_with("30 lines added, 0 lines removed\n+ 0\n"));
+        assert!(diff.ends_with("+ 19\n\u{2026}\n"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Approximate string matching, for suggesting existing articles when a
+//! title or slug is misspelled
+
+use std::cmp;
+
+/// How well a candidate matches. Lower is better.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Score {
+    distance: usize,
+    dissimilarity: u32,
+}
+
+const MIN_SIMILARITY: f64 = 0.4;
+
+fn trigrams(src: &str) -> Vec<(char, char, char)> {
+    let chars = "  ".chars().chain(src.chars()).chain(" ".chars()).collect::<Vec<_>>();
+
+    let mut trigrams = chars.windows(3)
+        .map(|x| (x[0], x[1], x[2]))
+        .collect::<Vec<_>>();
+    trigrams.sort();
+    trigrams.dedup();
+    trigrams
+}
+
+/// The number of shared trigrams relative to the number of distinct
+/// trigrams in both strings, in the range [0, 1]
+pub fn trigram_similarity(a: &str, b: &str) -> f64 {
+    let a = trigrams(a);
+    let b = trigrams(b);
+
+    let shared = a.iter().filter(|x| b.binary_search(x).is_ok()).count();
+    let total = a.len() + b.len() - shared;
+
+    if total == 0 {
+        1.
+    } else {
+        shared as f64 / total as f64
+    }
+}
+
+/// The number of insertions, deletions, substitutions and transpositions of
+/// adjacent characters needed to turn `a` into `b`
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+
+    // Rows of the distance matrix for the two previous and the current
+    // character of `a`
+    let mut prev_prev = vec![0; b.len() + 1];
+    let mut prev = (0..b.len() + 1).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for i in 1..a.len() + 1 {
+        current[0] = i;
+        for j in 1..b.len() + 1 {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            current[j] = cmp::min(
+                cmp::min(prev[j] + 1, current[j - 1] + 1),
+                prev[j - 1] + cost
+            );
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = cmp::min(current[j], prev_prev[j - 2] + 1);
+            }
+        }
+
+        ::std::mem::swap(&mut prev_prev, &mut prev);
+        ::std::mem::swap(&mut prev, &mut current);
+    }
+
+    prev[b.len()]
+}
+
+fn max_distance(len: usize) -> usize {
+    cmp::max(1, len / 4)
+}
+
+/// The range of lengths, in characters, of candidates that `score` may
+/// accept for `query`, for narrowing down the candidates before scoring.
+///
+/// A string of length n has at most n + 1 trigrams, and the similarity of
+/// two strings is at most the ratio of their numbers of trigrams. Longer
+/// candidates with many repeated trigrams are therefore missed.
+pub fn candidate_lengths(query: &str) -> (usize, usize) {
+    let len = query.chars().count();
+    let max_distance = max_distance(len);
+    let trigrams = trigrams(query).len() as f64;
+
+    // Rounded outwards, to stay clear of rounding errors
+    let shortest = (trigrams * MIN_SIMILARITY).floor() as usize;
+    let longest = (trigrams / MIN_SIMILARITY).ceil() as usize;
+
+    (
+        cmp::min(len.saturating_sub(max_distance), shortest.saturating_sub(1)),
+        cmp::max(len + max_distance, longest),
+    )
+}
+
+/// Score `candidate` against `query`, or `None` if it is too different to
+/// be what the user meant. Both should be normalized, for example as slugs.
+pub fn score(query: &str, candidate: &str) -> Option<Score> {
+    let len = query.chars().count();
+    let max_distance = max_distance(len);
+
+    let similarity = trigram_similarity(query, candidate);
+    let distance = edit_distance(query, candidate);
+
+    if distance <= max_distance || similarity >= MIN_SIMILARITY {
+        Some(Score {
+            distance,
+            dissimilarity: ((1. - similarity) * 1000.) as u32,
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edit_distance_counts_transpositions_once() {
+        assert_eq!(0, edit_distance("kubernetes", "kubernetes"));
+        assert_eq!(1, edit_distance("kuberentes", "kubernetes"));
+        assert_eq!(1, edit_distance("kubernetes", "kubernete"));
+        assert_eq!(3, edit_distance("", "abc"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+    }
+
+    #[test]
+    fn trigram_similarity_range() {
+        assert_eq!(1., trigram_similarity("abc", "abc"));
+        assert_eq!(0., trigram_similarity("abc", "xyz"));
+        let x = trigram_similarity("kuberentes", "kubernetes");
+        assert!(0. < x && x < 1.);
+    }
+
+    #[test]
+    fn score_ranks_closer_matches_first() {
+        let close = score("kuberentes", "kubernetes").unwrap();
+        let far = score("kuberentes", "kubernetis").unwrap();
+        assert!(close < far);
+
+        assert!(score("kuberentes", "postgres").is_none());
+        assert!(score("a", "xyz").is_none());
+    }
+
+    #[test]
+    fn candidate_lengths_cover_all_scored() {
+        let queries = ["a", "ab", "kuberentes", "postgres-backup-schedule"];
+        let candidates = ["b", "abc", "abcdef", "kubernetes", "kubernetes-cluster",
+            "kub", "postgres", "postgres-backups", "postgres-backup-schedule-for-staging"];
+
+        for query in &queries {
+            let (shortest, longest) = candidate_lengths(query);
+            for candidate in &candidates {
+                let len = candidate.chars().count();
+                if score(query, candidate).is_some() {
+                    assert!(shortest <= len && len <= longest, "{} {}", query, candidate);
+                }
+            }
+        }
+
+        assert_eq!((3, 28), candidate_lengths("kuberentes"));
+    }
+}
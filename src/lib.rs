@@ -15,6 +15,9 @@ extern crate chrono;
 extern crate diff;
 extern crate futures_cpupool;
 extern crate futures;
+extern crate hmac;
+extern crate image;
+extern crate lru_cache;
 extern crate percent_encoding;
 extern crate pulldown_cmark;
 extern crate r2d2_diesel;
@@ -22,31 +25,63 @@ extern crate r2d2;
 extern crate serde_json;
 extern crate serde_urlencoded;
 extern crate serde;
+extern crate sha2;
 extern crate slug;
 extern crate titlecase;
+extern crate tokio_core;
 
 use std::net::{IpAddr, SocketAddr};
 
 mod assets;
 mod build_config;
+mod chart;
 mod db;
+mod email;
+mod fuzzy;
+mod live_changes;
 mod merge;
 mod mimes;
 mod models;
+mod render_cache;
 mod rendering;
 mod resources;
 mod schema;
+mod search_query;
 mod site;
 mod state;
+mod thumbnail;
 mod web;
+mod webhooks;
 mod wiki_lookup;
 
-pub fn main(db_file: String, bind_host: IpAddr, bind_port: u16, trust_identity: bool) -> Result<(), Box<std::error::Error>> {
+pub fn main(db_file: String, bind_host: IpAddr, bind_port: u16, trust_identity: bool, allow_html: bool, render_cache_size: usize, persist_render_cache: bool, title_boost: f64, base_url: Option<String>, webhook_urls: Vec<String>, webhook_secret: Option<String>, smtp_server: Option<String>, email_from: Option<String>, email_domain: Option<String>) -> Result<(), Box<std::error::Error>> {
     let db_pool = db::create_pool(db_file)?;
     let cpu_pool = futures_cpupool::CpuPool::new_num_cpus();
+    let render_options = rendering::RenderOptions { allow_html };
+    let render_cache = render_cache::RenderCache::new(render_cache_size, persist_render_cache);
 
-    let state = state::State::new(db_pool, cpu_pool);
-    let lookup = wiki_lookup::WikiLookup::new(state, trust_identity);
+    let base_url = base_url.map(|x| if x.ends_with('/') { x } else { x + "/" });
+    let webhooks = std::sync::Arc::new(webhooks::Webhooks::new(
+        webhook_urls,
+        webhook_secret.unwrap_or_default(),
+        base_url.clone(),
+    ));
+
+    let change_listeners = std::sync::Arc::new(live_changes::ChangeListeners::default());
+    live_changes::spawn_keep_alive(change_listeners.clone())?;
+
+    let state = state::State::new(db_pool, cpu_pool, render_options, render_cache, title_boost, webhooks.clone(), change_listeners);
+
+    if !webhooks.urls.is_empty() {
+        webhooks::spawn(state.clone(), webhooks)?;
+    }
+
+    if let Some(smtp_server) = smtp_server {
+        let email_from = email_from.ok_or("Sending email requires a from address")?;
+        email::spawn(state.clone(), email::Mailer::new(smtp_server, email_from, email_domain, base_url.clone()))?;
+    }
+
+    let lookup = wiki_lookup::WikiLookup::new(state, trust_identity, base_url);
 
     let server =
         hyper::server::Http::new()
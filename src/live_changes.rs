@@ -0,0 +1,92 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// How often the open change streams are poked to keep idle connections
+/// from timing out, which is also when streams to clients that have gone
+/// away are closed
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    /// New revisions have been saved
+    Changed,
+    KeepAlive,
+}
+
+/// The open change streams, each of which is signalled whenever an article
+/// changes
+#[derive(Default)]
+pub struct ChangeListeners {
+    listeners: Mutex<Vec<UnboundedSender<Signal>>>,
+}
+
+impl ChangeListeners {
+    pub fn listen(&self) -> UnboundedReceiver<Signal> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.listeners.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn notify(&self) {
+        self.send(Signal::Changed);
+    }
+
+    /// Signal every listener, forgetting the ones that have stopped
+    /// listening
+    fn send(&self, signal: Signal) {
+        self.listeners.lock().unwrap()
+            .retain(|x| x.unbounded_send(signal).is_ok());
+    }
+}
+
+/// Start the thread sending `Signal::KeepAlive` every `KEEP_ALIVE_INTERVAL`
+pub fn spawn_keep_alive(listeners: Arc<ChangeListeners>) -> Result<(), Box<::std::error::Error>> {
+    thread::Builder::new()
+        .name("keep-alive".to_owned())
+        .spawn(move || loop {
+            thread::sleep(KEEP_ALIVE_INTERVAL);
+            listeners.send(Signal::KeepAlive);
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use futures::Stream;
+
+    #[test]
+    fn notify_signals_every_listener() {
+        let listeners = ChangeListeners::default();
+        let a = listeners.listen();
+        let b = listeners.listen();
+
+        listeners.notify();
+        listeners.send(Signal::KeepAlive);
+        drop(listeners);
+
+        let expected = vec![Signal::Changed, Signal::KeepAlive];
+        assert_eq!(expected, a.wait().collect::<Result<Vec<_>, _>>().unwrap());
+        assert_eq!(expected, b.wait().collect::<Result<Vec<_>, _>>().unwrap());
+    }
+
+    #[test]
+    fn closed_listeners_are_forgotten() {
+        let listeners = ChangeListeners::default();
+        let a = listeners.listen();
+        let b = listeners.listen();
+
+        drop(a);
+        listeners.notify();
+        assert_eq!(1, listeners.listeners.lock().unwrap().len());
+
+        drop(b);
+        listeners.notify();
+        assert_eq!(0, listeners.listeners.lock().unwrap().len());
+    }
+}
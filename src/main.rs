@@ -11,6 +11,16 @@ const DATABASE: &str = "DATABASE";
 const TRUST_IDENTITY: &str = "trust-identity";
 const ADDRESS: &str = "address";
 const PORT: &str = "port";
+const ALLOW_HTML: &str = "allow-html";
+const RENDER_CACHE_SIZE: &str = "render-cache-size";
+const PERSIST_RENDER_CACHE: &str = "persist-render-cache";
+const TITLE_BOOST: &str = "title-boost";
+const BASE_URL: &str = "base-url";
+const WEBHOOK: &str = "webhook";
+const WEBHOOK_SECRET: &str = "webhook-secret";
+const SMTP_SERVER: &str = "smtp-server";
+const EMAIL_FROM: &str = "email-from";
+const EMAIL_DOMAIN: &str = "email-domain";
 
 fn args<'a>() -> clap::ArgMatches<'a> {
     use clap::{App, Arg};
@@ -46,6 +56,81 @@ fn args<'a>() -> clap::ArgMatches<'a> {
                 authenticated username. This only makes sense when Sausagewiki \
                 runs behind a reverse proxy which sets this header.")
             .long(TRUST_IDENTITY))
+        .arg(Arg::with_name(ALLOW_HTML)
+            .help("Allow a safe subset of HTML in articles, such as <kbd>, \
+                <details> and <sup>. Scripts, event handlers and other unsafe \
+                markup are always removed. Without this flag, HTML in \
+                articles is displayed as text.")
+            .long(ALLOW_HTML))
+        .arg(Arg::with_name(RENDER_CACHE_SIZE)
+            .help("Sets the number of rendered article revisions to keep in memory")
+            .long(RENDER_CACHE_SIZE)
+            .default_value("1000")
+            .validator(|x| match x.parse::<usize>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err("Must be a non-negative integer".into())
+            })
+            .takes_value(true))
+        .arg(Arg::with_name(PERSIST_RENDER_CACHE)
+            .help("Also store rendered article revisions in the database, \
+                so they survive restarts")
+            .long(PERSIST_RENDER_CACHE))
+        .arg(Arg::with_name(TITLE_BOOST)
+            .help("Sets how much more matches in article titles count than \
+                matches in the body when ranking search results")
+            .long(TITLE_BOOST)
+            .default_value("10")
+            .validator(|x| match x.parse::<f64>() {
+                Ok(x) if x > 0. => Ok(()),
+                _ => Err("Must be a positive number".into())
+            })
+            .takes_value(true))
+        .arg(Arg::with_name(BASE_URL)
+            .help("Sets the public URL of the wiki, such as \
+                https://wiki.example.com/, for absolute links. The Atom \
+                feed is only available with this set.")
+            .long(BASE_URL)
+            .validator(|x| match x.starts_with("http://") || x.starts_with("https://") {
+                true => Ok(()),
+                false => Err("Must be an http:// or https:// URL".into())
+            })
+            .takes_value(true))
+        .arg(Arg::with_name(WEBHOOK)
+            .help("Adds an endpoint to receive a signed JSON POST request about \
+                each change to an article. Can be given several times. Only \
+                http:// is supported, so HTTPS endpoints must be reached \
+                through a proxy that adds TLS.")
+            .long(WEBHOOK)
+            .multiple(true)
+            .number_of_values(1)
+            .requires(WEBHOOK_SECRET)
+            .requires(BASE_URL)
+            .validator(|x| match x.starts_with("http://") {
+                true => Ok(()),
+                false => Err("Must be an http:// URL".into())
+            })
+            .takes_value(true))
+        .arg(Arg::with_name(WEBHOOK_SECRET)
+            .help("Sets the secret to sign webhook requests with")
+            .long(WEBHOOK_SECRET)
+            .takes_value(true))
+        .arg(Arg::with_name(SMTP_SERVER)
+            .help("Sets the SMTP server, as host:port, for emailing digests of \
+                changes to the articles each user watches. It must accept mail \
+                without authentication or TLS, like a local relay.")
+            .long(SMTP_SERVER)
+            .requires(EMAIL_FROM)
+            .takes_value(true))
+        .arg(Arg::with_name(EMAIL_FROM)
+            .help("Sets the sender address of emails")
+            .long(EMAIL_FROM)
+            .takes_value(true))
+        .arg(Arg::with_name(EMAIL_DOMAIN)
+            .help("Sets the domain of the email addresses of users whose \
+                identity is not an email address, making alice into \
+                alice@<email-domain>")
+            .long(EMAIL_DOMAIN)
+            .takes_value(true))
         .get_matches()
 }
 
@@ -59,12 +144,34 @@ fn core_main() -> Result<(), Box<std::error::Error>> {
     let bind_port = args.value_of(PORT).expect(CLAP).parse().expect(VALIDATOR);
 
     let trust_identity = args.is_present(TRUST_IDENTITY);
+    let allow_html = args.is_present(ALLOW_HTML);
+    let render_cache_size = args.value_of(RENDER_CACHE_SIZE).expect(CLAP).parse().expect(VALIDATOR);
+    let persist_render_cache = args.is_present(PERSIST_RENDER_CACHE);
+    let title_boost = args.value_of(TITLE_BOOST).expect(CLAP).parse().expect(VALIDATOR);
+    let base_url = args.value_of(BASE_URL).map(|x| x.to_owned());
+    let webhook_urls = args.values_of(WEBHOOK)
+        .map(|x| x.map(|x| x.to_owned()).collect())
+        .unwrap_or_default();
+    let webhook_secret = args.value_of(WEBHOOK_SECRET).map(|x| x.to_owned());
+    let smtp_server = args.value_of(SMTP_SERVER).map(|x| x.to_owned());
+    let email_from = args.value_of(EMAIL_FROM).map(|x| x.to_owned());
+    let email_domain = args.value_of(EMAIL_DOMAIN).map(|x| x.to_owned());
 
     sausagewiki::main(
         db_file,
         bind_host,
         bind_port,
         trust_identity,
+        allow_html,
+        render_cache_size,
+        persist_render_cache,
+        title_boost,
+        base_url,
+        webhook_urls,
+        webhook_secret,
+        smtp_server,
+        email_from,
+        email_domain,
     )
 }
 
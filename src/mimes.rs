@@ -3,5 +3,48 @@ use hyper::mime;
 lazy_static! {
     pub static ref TEXT_HTML: mime::Mime = "text/html;charset=utf-8".parse().unwrap();
     pub static ref TEXT_PLAIN: mime::Mime = "text/plain;charset=utf-8".parse().unwrap();
+    pub static ref TEXT_EVENT_STREAM: mime::Mime = "text/event-stream;charset=utf-8".parse().unwrap();
     pub static ref APPLICATION_JSON: mime::Mime = "application/json".parse().unwrap();
+    pub static ref APPLICATION_ATOM_XML: mime::Mime = "application/atom+xml;charset=utf-8".parse().unwrap();
+    pub static ref APPLICATION_X_SUGGESTIONS_JSON: mime::Mime = "application/x-suggestions+json".parse().unwrap();
+    pub static ref APPLICATION_OPENSEARCHDESCRIPTION_XML: mime::Mime =
+        "application/opensearchdescription+xml;charset=utf-8".parse().unwrap();
+}
+
+/// Guess the content type of a file from the extension of its name
+pub fn from_extension(name: &str) -> &'static str {
+    let extension = match name.rfind('.') {
+        Some(x) => name[x + 1..].to_lowercase(),
+        None => String::new(),
+    };
+
+    match &extension[..] {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain;charset=utf-8",
+        "csv" => "text/csv;charset=utf-8",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether a file of the given content type is safe to display inline on
+/// the wiki domain. Others are served as downloads.
+pub fn is_inline_safe(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_lowercase();
+
+    match &essence[..] {
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp" |
+        "application/pdf" | "text/plain" | "text/csv" |
+        "audio/mpeg" | "audio/ogg" | "video/mp4" | "video/webm" => true,
+        _ => false,
+    }
 }
@@ -49,13 +49,339 @@ impl ArticleRevisionStub {
     pub fn link(&self) -> &str { slug_link(&self.slug) }
 }
 
-#[derive(Debug, Queryable, Serialize)]
+#[derive(Debug, PartialEq, Queryable, Serialize)]
+pub struct ArticleTitle {
+    pub title: String,
+    pub slug: String,
+}
+
+impl ArticleTitle {
+    pub fn link(&self) -> &str { slug_link(&self.slug) }
+}
+
+#[derive(Debug, PartialEq, Queryable, Serialize)]
+pub struct EditedArticle {
+    pub title: String,
+    pub slug: String,
+    pub revisions: i64,
+}
+
+impl EditedArticle {
+    pub fn link(&self) -> &str { slug_link(&self.slug) }
+}
+
+/// What an author has contributed, for their page
+#[derive(Debug)]
+pub struct UserContributions {
+    pub edits: i64,
+    pub first_edit: Option<chrono::NaiveDateTime>,
+    pub last_edit: Option<chrono::NaiveDateTime>,
+    pub most_edited: Vec<EditedArticle>,
+    pub recent: Vec<ArticleRevisionStub>,
+
+    /// The latest revision of the article the author can write about
+    /// themselves, see `profile_title`
+    pub profile: Option<ArticleRevision>,
+}
+
+/// The title of the profile article of an author. Titles are compared
+/// exactly, as identities that only differ in case or punctuation belong
+/// to different authors
+pub fn profile_title(identity: &str) -> String {
+    format!("User: {}", identity)
+}
+
+#[derive(Debug, PartialEq, Queryable)]
+pub struct AuthorEdits {
+    pub author: String,
+    pub edits: i64,
+}
+
+/// An article with a size in bytes, or a change in size
+#[derive(Debug, PartialEq, Queryable)]
+pub struct ArticleSize {
+    pub title: String,
+    pub slug: String,
+    pub bytes: i64,
+}
+
+impl ArticleSize {
+    pub fn link(&self) -> &str { slug_link(&self.slug) }
+}
+
+#[derive(Debug, PartialEq, Queryable)]
+pub struct WatchedArticle {
+    pub article_id: i32,
+    pub title: String,
+    pub slug: String,
+}
+
+impl WatchedArticle {
+    pub fn link(&self) -> &str { slug_link(&self.slug) }
+}
+
+/// Changes to the articles an identity watches that are yet to be emailed,
+/// each with its previous revision
+#[derive(Debug)]
+pub struct NotificationDigest {
+    pub identity: String,
+    pub changes: Vec<(ArticleRevision, Option<ArticleRevision>)>,
+
+    /// The sequence_number to advance the notification cursor to once the
+    /// digest is sent
+    pub up_to: i32,
+}
+
+/// A queued or completed webhook request
+#[derive(Debug, Queryable)]
+pub struct WebhookDelivery {
+    pub id: i32,
+
+    pub url: String,
+    pub payload: String,
+    pub created: chrono::NaiveDateTime,
+
+    pub attempts: i32,
+    pub next_attempt: Option<chrono::NaiveDateTime>,
+    pub delivered: Option<chrono::NaiveDateTime>,
+    pub last_result: Option<String>,
+}
+
+/// Aggregates over all of `article_revisions`, for the statistics page
+#[derive(Debug)]
+pub struct Stats {
+    pub articles: i64,
+    pub revisions: i64,
+
+    /// The number of edits each day, oldest first, including days without edits
+    pub edits_per_day: Vec<(chrono::NaiveDate, i64)>,
+
+    /// The number of edits each week, by the Monday it starts on
+    pub edits_per_week: Vec<(chrono::NaiveDate, i64)>,
+
+    pub top_contributors: Vec<AuthorEdits>,
+    pub largest: Vec<ArticleSize>,
+
+    /// Articles by how much they have grown during the last month
+    pub fastest_growing: Vec<ArticleSize>,
+}
+
+/// What the search page shows when there is no query
+#[derive(Debug, Serialize)]
+pub struct SearchFrontPage {
+    pub recent: Vec<ArticleTitle>,
+    pub most_edited: Vec<EditedArticle>,
+    pub random: Vec<ArticleTitle>,
+}
+
+/// Marks the start of a matched term in text from the search index
+pub const HIGHLIGHT_START: char = ::rendering::MARKER_START;
+pub const HIGHLIGHT_END: char = ::rendering::MARKER_END;
+
+/// A highlighted range of text, in UTF-16 code units for the convenience of
+/// JavaScript
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Highlight {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Remove highlight markers from `src`, returning the plain text and the
+/// highlighted ranges
+fn split_highlights(src: &str) -> (String, Vec<Highlight>) {
+    let mut text = String::with_capacity(src.len());
+    let mut highlights = Vec::new();
+    let mut pos = 0;
+    let mut start = None;
+
+    for c in src.chars() {
+        if c == HIGHLIGHT_START {
+            start = start.or(Some(pos));
+        } else if c == HIGHLIGHT_END {
+            if let Some(start) = start.take() {
+                highlights.push(Highlight { start, end: pos });
+            }
+        } else {
+            text.push(c);
+            pos += c.len_utf16();
+        }
+    }
+
+    if let Some(start) = start {
+        highlights.push(Highlight { start, end: pos });
+    }
+
+    (text, highlights)
+}
+
+fn byte_offset(text: &str, utf16_offset: usize) -> usize {
+    let mut pos = 0;
+    for (i, c) in text.char_indices() {
+        if pos >= utf16_offset {
+            return i;
+        }
+        pos += c.len_utf16();
+    }
+    text.len()
+}
+
+fn highlight_html(text: &str, highlights: &[Highlight]) -> String {
+    use rendering::escape_html;
+
+    let mut buf = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for highlight in highlights {
+        let start = byte_offset(text, highlight.start);
+        let end = byte_offset(text, highlight.end);
+
+        buf.push_str(&escape_html(&text[last..start]));
+        buf.push_str("<mark>");
+        buf.push_str(&escape_html(&text[start..end]));
+        buf.push_str("</mark>");
+        last = end;
+    }
+    buf.push_str(&escape_html(&text[last..]));
+
+    buf
+}
+
+#[derive(Debug, Serialize)]
 pub struct SearchResult {
     pub title: String,
+    pub title_highlights: Vec<Highlight>,
     pub snippet: String,
+    pub snippet_highlights: Vec<Highlight>,
     pub slug: String,
 }
 
 impl SearchResult {
+    /// Construct from a title and snippet with highlight markers
+    pub fn from_marked(title: &str, snippet: &str, slug: String) -> SearchResult {
+        let (title, title_highlights) = split_highlights(title);
+        let (snippet, snippet_highlights) = split_highlights(snippet);
+
+        SearchResult { title, title_highlights, snippet, snippet_highlights, slug }
+    }
+
     pub fn link(&self) -> &str { slug_link(&self.slug) }
+
+    pub fn title_html(&self) -> String { highlight_html(&self.title, &self.title_highlights) }
+
+    pub fn snippet_html(&self) -> String { highlight_html(&self.snippet, &self.snippet_highlights) }
+}
+
+/// A match in any revision of an article
+#[derive(Debug)]
+pub struct RevisionSearchResult {
+    pub article_id: i32,
+    pub revision: i32,
+    pub created: chrono::NaiveDateTime,
+    pub author: Option<String>,
+
+    pub title: String,
+    pub title_highlights: Vec<Highlight>,
+    pub snippet: String,
+    pub snippet_highlights: Vec<Highlight>,
+}
+
+impl RevisionSearchResult {
+    /// Construct from a title and snippet with highlight markers
+    pub fn from_marked(article_id: i32, revision: i32, created: chrono::NaiveDateTime, author: Option<String>, title: &str, snippet: &str)
+        -> RevisionSearchResult
+    {
+        let (title, title_highlights) = split_highlights(title);
+        let (snippet, snippet_highlights) = split_highlights(snippet);
+
+        RevisionSearchResult {
+            article_id,
+            revision,
+            created,
+            author,
+            title,
+            title_highlights,
+            snippet,
+            snippet_highlights,
+        }
+    }
+
+    pub fn link(&self) -> String { format!("_revisions/{}/{}", self.article_id, self.revision) }
+
+    pub fn title_html(&self) -> String { highlight_html(&self.title, &self.title_highlights) }
+
+    pub fn snippet_html(&self) -> String { highlight_html(&self.snippet, &self.snippet_highlights) }
+}
+
+/// The namespace of an article is the part of its title before a colon, as
+/// in "Ops: Deploy checklist". Articles without one are in no namespace.
+pub fn namespace(title: &str) -> Option<&str> {
+    title.find(':')
+        .map(|x| title[..x].trim_matches(' '))
+        .and_then(|x| if x.is_empty() { None } else { Some(x) })
+}
+
+/// The number of search hits with a given value of a facet
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// Counts of search hits by author and namespace of the latest revision,
+/// and by the year it was made. Most common first.
+#[derive(Debug, Default, Serialize)]
+pub struct SearchFacets {
+    pub authors: Vec<FacetCount>,
+    pub namespaces: Vec<FacetCount>,
+    pub years: Vec<FacetCount>,
+}
+
+#[derive(Debug, Queryable)]
+pub struct Attachment {
+    pub sequence_number: i32,
+
+    pub name: String,
+    pub revision: i32,
+    pub created: chrono::NaiveDateTime,
+    pub author: Option<String>,
+
+    pub article_id: Option<i32>,
+
+    pub content_type: String,
+    pub size: i32,
+    pub data: Vec<u8>,
+
+    pub latest: bool,
+}
+
+#[derive(Debug, PartialEq, Queryable)]
+pub struct AttachmentStub {
+    pub sequence_number: i32,
+
+    pub name: String,
+    pub revision: i32,
+    pub created: chrono::NaiveDateTime,
+    pub author: Option<String>,
+
+    pub article_id: Option<i32>,
+
+    pub content_type: String,
+    pub size: i32,
+
+    pub latest: bool,
+}
+
+impl AttachmentStub {
+    pub fn link(&self) -> String { format!("_files/{}", self.name) }
+
+    pub fn is_image(&self) -> bool { self.content_type.starts_with("image/") }
+
+    /// Markdown for embedding or linking to the attachment in an article
+    pub fn markdown(&self) -> String {
+        if self.is_image() {
+            format!("![{}]({})", self.name, self.link())
+        } else {
+            format!("[{}]({})", self.name, self.link())
+        }
+    }
 }
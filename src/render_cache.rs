@@ -0,0 +1,128 @@
+use std::sync::Mutex;
+
+use lru_cache::LruCache;
+
+struct Memory {
+    entries: LruCache<(i32, i32), String>,
+    generation: u64,
+}
+
+/// Rendered HTML of article revisions, keyed by `(article_id, revision)`.
+///
+/// Revisions are immutable, but their rendering still depends on the
+/// rendering options and on the articles they include. Entries for
+/// articles that include a changed article must be invalidated.
+///
+/// A rendering that was started before an invalidation may be based on the
+/// old version of an included article. Every invalidation therefore starts
+/// a new generation, and renderings from an earlier generation are not
+/// cached.
+pub struct RenderCache {
+    memory: Mutex<Memory>,
+    persist: bool,
+}
+
+impl RenderCache {
+    pub fn new(capacity: usize, persist: bool) -> RenderCache {
+        RenderCache {
+            memory: Mutex::new(Memory {
+                entries: LruCache::new(capacity),
+                generation: 0,
+            }),
+            persist,
+        }
+    }
+
+    /// Whether rendered HTML should also be stored in the database
+    pub fn persist(&self) -> bool {
+        self.persist
+    }
+
+    /// Take before rendering, to check with `is_current` and `insert`
+    /// afterwards
+    pub fn generation(&self) -> u64 {
+        self.memory.lock().unwrap().generation
+    }
+
+    /// Whether nothing has been invalidated since `generation` was taken
+    pub fn is_current(&self, generation: u64) -> bool {
+        self.generation() == generation
+    }
+
+    pub fn get(&self, article_id: i32, revision: i32) -> Option<String> {
+        self.memory.lock().unwrap().entries
+            .get_mut(&(article_id, revision))
+            .map(|x| x.clone())
+    }
+
+    /// Insert a rendering made in `generation`, unless something has been
+    /// invalidated since
+    pub fn insert(&self, generation: u64, article_id: i32, revision: i32, html: String) {
+        let mut memory = self.memory.lock().unwrap();
+        if memory.generation == generation {
+            memory.entries.insert((article_id, revision), html);
+        }
+    }
+
+    /// Remove all revisions of the given articles. Call after the change
+    /// that makes them outdated is committed.
+    pub fn invalidate(&self, article_ids: &[i32]) {
+        let mut memory = self.memory.lock().unwrap();
+        memory.generation += 1;
+
+        let keys = memory.entries.iter()
+            .map(|(key, _)| *key)
+            .filter(|&(article_id, _)| article_ids.contains(&article_id))
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            memory.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn invalidate_removes_all_revisions_of_article() {
+        let cache = RenderCache::new(10, false);
+        cache.insert(0, 1, 1, "a".into());
+        cache.insert(0, 1, 2, "b".into());
+        cache.insert(0, 2, 1, "c".into());
+
+        cache.invalidate(&[1]);
+
+        assert_eq!(None, cache.get(1, 1));
+        assert_eq!(None, cache.get(1, 2));
+        assert_eq!(Some("c".to_owned()), cache.get(2, 1));
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let cache = RenderCache::new(2, false);
+        cache.insert(0, 1, 1, "a".into());
+        cache.insert(0, 2, 1, "b".into());
+        cache.get(1, 1);
+        cache.insert(0, 3, 1, "c".into());
+
+        assert_eq!(Some("a".to_owned()), cache.get(1, 1));
+        assert_eq!(None, cache.get(2, 1));
+    }
+
+    #[test]
+    fn renderings_from_before_invalidation_are_not_inserted() {
+        let cache = RenderCache::new(10, false);
+        let generation = cache.generation();
+
+        cache.invalidate(&[2]);
+        assert!(!cache.is_current(generation));
+
+        cache.insert(generation, 1, 1, "a".into());
+        assert_eq!(None, cache.get(1, 1));
+
+        cache.insert(cache.generation(), 1, 1, "a".into());
+        assert_eq!(Some("a".to_owned()), cache.get(1, 1));
+    }
+}
@@ -0,0 +1,193 @@
+//! Tables from fenced blocks of comma or tab separated values:
+//!
+//! ````text
+//! ```csv header
+//! Host,Role
+//! db1,"Primary, EU"
+//! ```
+//! ````
+//!
+//! Fields may be quoted with `"` to contain delimiters, line breaks or,
+//! doubled, quotes. The first row is rendered as a header when the info
+//! string says `header`.
+
+use std::mem;
+
+use super::escape_html;
+
+pub struct Format {
+    pub delimiter: char,
+    pub header: bool,
+}
+
+/// The format given by the info string of a fenced code block, if it is
+/// one that should be rendered as a table
+pub fn format(info: &str) -> Option<Format> {
+    let mut words = info.split_whitespace();
+
+    let delimiter = match words.next() {
+        Some("csv") => ',',
+        Some("tsv") => '\t',
+        _ => return None,
+    };
+
+    Some(Format {
+        delimiter,
+        header: words.any(|x| x == "header"),
+    })
+}
+
+fn end_row(rows: &mut Vec<Vec<String>>, mut row: Vec<String>, field: String) {
+    row.push(field);
+
+    // Blank lines do not make empty rows
+    if row.len() > 1 || !row[0].is_empty() {
+        rows.push(row);
+    }
+}
+
+pub fn parse(src: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+
+    let mut at_field_start = true;
+    let mut quoted = false;
+
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            } else {
+                field.push(c);
+            }
+            continue;
+        }
+
+        match c {
+            '"' if at_field_start => {
+                quoted = true;
+                at_field_start = false;
+            },
+            c if c == delimiter => {
+                row.push(mem::replace(&mut field, String::new()));
+                at_field_start = true;
+            },
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                end_row(&mut rows, mem::replace(&mut row, Vec::new()), mem::replace(&mut field, String::new()));
+                at_field_start = true;
+            },
+            c => {
+                field.push(c);
+                at_field_start = false;
+            },
+        }
+    }
+
+    if !row.is_empty() || !field.is_empty() {
+        end_row(&mut rows, row, field);
+    }
+
+    rows
+}
+
+fn push_row(buf: &mut String, row: &[String], columns: usize, cell: &str) {
+    buf.push_str("<tr>");
+    for i in 0..columns {
+        buf.push_str(&format!(
+            "<{}>{}</{}>",
+            cell,
+            row.get(i).map_or(String::new(), |x| escape_html(x)),
+            cell
+        ));
+    }
+    buf.push_str("</tr>\n");
+}
+
+pub fn to_html(src: &str, format: &Format) -> String {
+    let rows = parse(src, format.delimiter);
+
+    // Short rows are padded, so every row has the same number of cells
+    let columns = rows.iter().map(|x| x.len()).max().unwrap_or(0);
+
+    let mut buf = String::from("<table>");
+    let mut rows = rows.iter();
+
+    if format.header {
+        if let Some(header) = rows.next() {
+            buf.push_str("<thead>");
+            push_row(&mut buf, header, columns, "th");
+            buf.push_str("</thead>");
+        }
+    }
+
+    buf.push_str("<tbody>\n");
+    for row in rows {
+        push_row(&mut buf, row, columns, "td");
+    }
+    buf.push_str("</tbody></table>\n");
+
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rows(src: &[&[&str]]) -> Vec<Vec<String>> {
+        src.iter().map(|row| row.iter().map(|x| x.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn format_from_info_string() {
+        assert_eq!(Some(','), format("csv").map(|x| x.delimiter));
+        assert_eq!(Some(true), format("tsv header").map(|x| x.header));
+        assert_eq!(Some(false), format("csv headers").map(|x| x.header));
+        assert!(format("rust").is_none());
+        assert!(format("").is_none());
+    }
+
+    #[test]
+    fn parse_plain() {
+        assert_eq!(
+            rows(&[&["a", "b"], &["", "d", ""]]),
+            parse("a,b\r\n,d,\n", ',')
+        );
+    }
+
+    #[test]
+    fn parse_quoted() {
+        assert_eq!(
+            rows(&[&["a,b", "say \"hi\"", "two\nlines"], &["x"]]),
+            parse("\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\nx", ',')
+        );
+    }
+
+    #[test]
+    fn quotes_inside_fields_are_literal() {
+        assert_eq!(rows(&[&["5\" screen", "b"]]), parse("5\" screen\tb", '\t'));
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        assert_eq!(rows(&[&["a"], &["b"]]), parse("a\n\n\nb\n", ','));
+    }
+
+    #[test]
+    fn html_is_escaped_and_rows_padded() {
+        let format = Format { delimiter: ',', header: true };
+        assert_eq!(
+            "<table><thead><tr><th>&lt;a&gt;</th><th>b</th></tr>\n</thead><tbody>\n\
+            <tr><td>1</td><td></td></tr>\n\
+            </tbody></table>\n",
+            to_html("<a>,b\n1", &format)
+        );
+    }
+}
@@ -0,0 +1,995 @@
+use std::fmt::Write;
+
+use super::{escape_html, MARKER_START, MARKER_END};
+
+// Extracted math is replaced by placeholders in the Markdown source, so the
+// Markdown parser cannot interpret `_` or `*` inside formulas as emphasis.
+// Where the marker characters occur in the source, they are themselves
+// replaced by placeholders, so they are kept as they are.
+const PLACEHOLDER_START: char = MARKER_START;
+const PLACEHOLDER_END: char = MARKER_END;
+
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+
+/// The deepest nesting of groups and commands in a formula. The parser and
+/// the writer recurse for each level, so this bounds their stack usage.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub struct Math {
+    /// The original source, including delimiters
+    pub source: String,
+    tex: String,
+    display: bool,
+    /// A placeholder character from the source rather than math
+    literal: bool,
+}
+
+impl Math {
+    pub fn to_html(&self) -> String {
+        if self.literal {
+            return escape_html(&self.source);
+        }
+
+        match to_mathml(&self.tex, self.display) {
+            Ok(mathml) => mathml,
+            Err(err) => format!(
+                "<code class=\"math-error\" title=\"{}\">{}</code>",
+                escape_html(&err), escape_html(&self.source)
+            ),
+        }
+    }
+}
+
+pub enum Fragment<'a> {
+    Text(&'a str),
+    Math(&'a Math),
+}
+
+fn is_fence(line: &str) -> Option<(char, usize)> {
+    let indent = line.len() - line.trim_left_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+
+    let line = &line[indent..];
+    for &fence_char in &['`', '~'] {
+        let len = line.len() - line.trim_left_matches(fence_char).len();
+        if len >= 3 {
+            return Some((fence_char, len));
+        }
+    }
+
+    None
+}
+
+/// Replace all math in `src` with placeholders, returning the new source
+/// and the extracted math.
+///
+/// Inline math is delimited by `$...$` and display math by `$$...$$`.
+/// Code blocks and code spans are left untouched, and `\$` can be used to
+/// escape a dollar sign.
+pub fn extract(src: &str) -> (String, Vec<Math>) {
+    let mut math = Vec::new();
+    let src = escape_placeholder_chars(src, &mut math);
+
+    let mut out = String::with_capacity(src.len());
+
+    // Consecutive lines of regular text are collected into blocks, so code
+    // spans and display math can span several lines within a paragraph.
+    let mut block = String::new();
+    let mut fence: Option<(char, usize)> = None;
+    let mut prev_blank = true;
+    let mut in_indented_code = false;
+
+    let mut lines = src.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        let eol = if lines.peek().is_some() { "\n" } else { "" };
+
+        if let Some((fence_char, fence_len)) = fence {
+            out.push_str(line);
+            out.push_str(eol);
+            match is_fence(line) {
+                Some((c, len)) if c == fence_char && len >= fence_len => fence = None,
+                _ => (),
+            }
+            continue;
+        }
+
+        let blank = line.trim().is_empty();
+        let indented = line.starts_with("    ") || line.starts_with('\t');
+
+        if !blank && indented && (prev_blank || in_indented_code) {
+            in_indented_code = true;
+        } else if !blank {
+            in_indented_code = false;
+        }
+
+        if blank || in_indented_code {
+            extract_inline(&block, &mut out, &mut math);
+            block.clear();
+            out.push_str(line);
+            out.push_str(eol);
+        } else if let Some(opening) = is_fence(line) {
+            extract_inline(&block, &mut out, &mut math);
+            block.clear();
+            fence = Some(opening);
+            out.push_str(line);
+            out.push_str(eol);
+        } else {
+            block.push_str(line);
+            block.push_str(eol);
+        }
+
+        prev_blank = blank;
+    }
+
+    extract_inline(&block, &mut out, &mut math);
+
+    (out, math)
+}
+
+fn escape_placeholder_chars(src: &str, math: &mut Vec<Math>) -> String {
+    let mut out = String::with_capacity(src.len());
+    for c in src.chars() {
+        match c {
+            PLACEHOLDER_START | PLACEHOLDER_END =>
+                push_placeholder(&mut out, math, Math {
+                    source: c.to_string(),
+                    tex: String::new(),
+                    display: false,
+                    literal: true,
+                }),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn inline_math_len(src: &str) -> Option<usize> {
+    let mut chars = src.char_indices().skip(1).peekable();
+
+    match chars.peek() {
+        Some(&(_, c)) if !c.is_whitespace() && c != '$' => (),
+        _ => return None,
+    }
+
+    let mut prev = '$';
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\n' => return None,
+            '\\' => {
+                chars.next();
+                prev = '\\';
+                continue;
+            },
+            '$' if !prev.is_whitespace() => {
+                // As in pandoc, so `$HOME/$PATH` and `$5 to $10` are not math
+                let followed_by_alphanumeric = src[i + 1..].chars().next()
+                    .map_or(false, |c| c.is_alphanumeric());
+                if !followed_by_alphanumeric {
+                    return Some(i + 1);
+                }
+            },
+            _ => (),
+        }
+        prev = c;
+    }
+
+    None
+}
+
+fn push_placeholder(out: &mut String, math: &mut Vec<Math>, item: Math) {
+    write!(out, "{}{}{}", PLACEHOLDER_START, math.len(), PLACEHOLDER_END)
+        .expect("Writing to String cannot fail");
+    math.push(item);
+}
+
+fn extract_inline(block: &str, out: &mut String, math: &mut Vec<Math>) {
+    let mut i = 0;
+
+    while i < block.len() {
+        let rest = &block[i..];
+
+        if rest.starts_with('\\') {
+            let escaped_len = rest[1..].chars().next().map_or(0, |c| c.len_utf8());
+            out.push_str(&rest[..1 + escaped_len]);
+            i += 1 + escaped_len;
+        } else if rest.starts_with('`') {
+            let run = rest.len() - rest.trim_left_matches('`').len();
+            let delimiter = &rest[..run];
+
+            let mut search = run;
+            let mut end = None;
+            while let Some(pos) = rest[search..].find(delimiter) {
+                let start = search + pos;
+                let closing_run = rest[start..].len() - rest[start..].trim_left_matches('`').len();
+                if closing_run == run {
+                    end = Some(start + run);
+                    break;
+                }
+                search = start + closing_run;
+            }
+
+            let len = end.unwrap_or(run);
+            out.push_str(&rest[..len]);
+            i += len;
+        } else if rest.starts_with("$$") {
+            match rest[2..].find("$$") {
+                Some(end) if !rest[2..2 + end].trim().is_empty() => {
+                    let item = Math {
+                        source: restore_source(&rest[..end + 4], math),
+                        tex: restore_source(&rest[2..2 + end], math),
+                        display: true,
+                        literal: false,
+                    };
+                    push_placeholder(out, math, item);
+                    i += end + 4;
+                },
+                _ => {
+                    out.push_str("$$");
+                    i += 2;
+                }
+            }
+        } else if rest.starts_with('$') {
+            match inline_math_len(rest) {
+                Some(len) => {
+                    let item = Math {
+                        source: restore_source(&rest[..len], math),
+                        tex: restore_source(&rest[1..len - 1], math),
+                        display: false,
+                        literal: false,
+                    };
+                    push_placeholder(out, math, item);
+                    i += len;
+                },
+                None => {
+                    out.push('$');
+                    i += 1;
+                }
+            }
+        } else {
+            let c = rest.chars().next().expect("rest is non-empty");
+            out.push(c);
+            i += c.len_utf8();
+        }
+    }
+}
+
+/// Split text from the Markdown parser into plain text and extracted math
+pub fn fragments<'a>(text: &'a str, math: &'a [Math]) -> Vec<Fragment<'a>> {
+    let mut fragments = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let after = &rest[start + PLACEHOLDER_START.len_utf8()..];
+        let item = after.find(PLACEHOLDER_END)
+            .and_then(|end| after[..end].parse::<usize>().ok().map(|index| (end, index)))
+            .and_then(|(end, index)| math.get(index).map(|item| (end, item)));
+
+        match item {
+            Some((end, item)) => {
+                if start > 0 {
+                    fragments.push(Fragment::Text(&rest[..start]));
+                }
+                fragments.push(Fragment::Math(item));
+                rest = &after[end + PLACEHOLDER_END.len_utf8()..];
+            },
+            None => {
+                fragments.push(Fragment::Text(&rest[..start + PLACEHOLDER_START.len_utf8()]));
+                rest = after;
+            }
+        }
+    }
+
+    if !rest.is_empty() {
+        fragments.push(Fragment::Text(rest));
+    }
+
+    fragments
+}
+
+/// Put the original source back in place of the placeholders. Used where
+/// math cannot be rendered, such as in link destinations.
+pub fn restore_source(text: &str, math: &[Math]) -> String {
+    fragments(text, math)
+        .into_iter()
+        .map(|x| match x {
+            Fragment::Text(x) => x,
+            Fragment::Math(x) => &x.source[..],
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Command(String),
+    Char(char),
+    Space,
+    BeginGroup,
+    EndGroup,
+    Superscript,
+    Subscript,
+}
+
+fn tokenize(src: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '\\' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_alphabetic() {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                if name.is_empty() {
+                    if let Some(c) = chars.next() {
+                        name.push(c);
+                    }
+                }
+                Token::Command(name)
+            },
+            '%' => {
+                // Comment until the end of the line
+                while let Some(c) = chars.next() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                continue;
+            },
+            '{' => Token::BeginGroup,
+            '}' => Token::EndGroup,
+            '^' => Token::Superscript,
+            '_' => Token::Subscript,
+            c if c.is_whitespace() => Token::Space,
+            c => Token::Char(c),
+        });
+    }
+
+    tokens
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(String, Option<&'static str>),
+    Ident(String, Option<&'static str>),
+    Operator { text: String, limits: bool },
+    Function { name: String, limits: bool },
+    Text(String),
+    Space(&'static str),
+    Row(Vec<Node>),
+    Frac(Box<Node>, Box<Node>),
+    Binom(Box<Node>, Box<Node>),
+    Sqrt(Box<Node>),
+    Root(Box<Node>, Box<Node>),
+    Over(Box<Node>, &'static str),
+    Under(Box<Node>, &'static str),
+    Fenced(String, Vec<Node>, String),
+    Scripts { base: Box<Node>, sub: Option<Box<Node>>, sup: Option<Box<Node>> },
+}
+
+impl Node {
+    fn has_limits(&self) -> bool {
+        match self {
+            &Node::Operator { limits, .. } => limits,
+            &Node::Function { limits, .. } => limits,
+            _ => false,
+        }
+    }
+
+    fn set_variant(&mut self, variant: &'static str) {
+        match self {
+            &mut Node::Number(_, ref mut x) => *x = Some(variant),
+            &mut Node::Ident(_, ref mut x) => *x = Some(variant),
+            &mut Node::Row(ref mut nodes) => for node in nodes { node.set_variant(variant) },
+            &mut Node::Fenced(_, ref mut nodes, _) => for node in nodes { node.set_variant(variant) },
+            &mut Node::Frac(ref mut a, ref mut b) |
+            &mut Node::Binom(ref mut a, ref mut b) |
+            &mut Node::Root(ref mut a, ref mut b) => {
+                a.set_variant(variant);
+                b.set_variant(variant);
+            },
+            &mut Node::Sqrt(ref mut a) |
+            &mut Node::Over(ref mut a, _) |
+            &mut Node::Under(ref mut a, _) => a.set_variant(variant),
+            &mut Node::Scripts { ref mut base, ref mut sub, ref mut sup } => {
+                base.set_variant(variant);
+                if let &mut Some(ref mut sub) = sub { sub.set_variant(variant); }
+                if let &mut Some(ref mut sup) = sup { sup.set_variant(variant); }
+            },
+            _ => (),
+        }
+    }
+}
+
+fn symbol(name: &str) -> Option<Node> {
+    fn ident(x: &str) -> Option<Node> { Some(Node::Ident(x.to_owned(), None)) }
+    fn upright(x: &str) -> Option<Node> { Some(Node::Ident(x.to_owned(), Some("normal"))) }
+    fn op(x: &str) -> Option<Node> { Some(Node::Operator { text: x.to_owned(), limits: false }) }
+    fn large(x: &str) -> Option<Node> { Some(Node::Operator { text: x.to_owned(), limits: true }) }
+    fn space(x: &'static str) -> Option<Node> { Some(Node::Space(x)) }
+
+    match name {
+        "alpha" => ident("α"), "beta" => ident("β"), "gamma" => ident("γ"),
+        "delta" => ident("δ"), "epsilon" => ident("ϵ"), "varepsilon" => ident("ε"),
+        "zeta" => ident("ζ"), "eta" => ident("η"), "theta" => ident("θ"),
+        "vartheta" => ident("ϑ"), "iota" => ident("ι"), "kappa" => ident("κ"),
+        "lambda" => ident("λ"), "mu" => ident("μ"), "nu" => ident("ν"),
+        "xi" => ident("ξ"), "pi" => ident("π"), "varpi" => ident("ϖ"),
+        "rho" => ident("ρ"), "varrho" => ident("ϱ"), "sigma" => ident("σ"),
+        "varsigma" => ident("ς"), "tau" => ident("τ"), "upsilon" => ident("υ"),
+        "phi" => ident("ϕ"), "varphi" => ident("φ"), "chi" => ident("χ"),
+        "psi" => ident("ψ"), "omega" => ident("ω"),
+
+        "Gamma" => upright("Γ"), "Delta" => upright("Δ"), "Theta" => upright("Θ"),
+        "Lambda" => upright("Λ"), "Xi" => upright("Ξ"), "Pi" => upright("Π"),
+        "Sigma" => upright("Σ"), "Upsilon" => upright("Υ"), "Phi" => upright("Φ"),
+        "Psi" => upright("Ψ"), "Omega" => upright("Ω"),
+
+        "infty" => ident("∞"), "partial" => ident("∂"), "nabla" => ident("∇"),
+        "hbar" => ident("ℏ"), "ell" => ident("ℓ"), "aleph" => ident("ℵ"),
+        "Re" => ident("ℜ"), "Im" => ident("ℑ"), "emptyset" => ident("∅"),
+
+        "pm" => op("±"), "mp" => op("∓"), "times" => op("×"), "div" => op("÷"),
+        "cdot" => op("⋅"), "ast" => op("∗"), "circ" => op("∘"), "bullet" => op("∙"),
+        "le" | "leq" => op("≤"), "ge" | "geq" => op("≥"), "ne" | "neq" => op("≠"),
+        "approx" => op("≈"), "equiv" => op("≡"), "sim" => op("∼"), "simeq" => op("≃"),
+        "cong" => op("≅"), "propto" => op("∝"), "ll" => op("≪"), "gg" => op("≫"),
+        "in" => op("∈"), "notin" => op("∉"), "ni" => op("∋"),
+        "subset" => op("⊂"), "subseteq" => op("⊆"), "supset" => op("⊃"), "supseteq" => op("⊇"),
+        "cup" => op("∪"), "cap" => op("∩"), "setminus" => op("∖"),
+        "forall" => op("∀"), "exists" => op("∃"), "neg" | "lnot" => op("¬"),
+        "land" | "wedge" => op("∧"), "lor" | "vee" => op("∨"),
+        "to" | "rightarrow" => op("→"), "leftarrow" | "gets" => op("←"),
+        "Rightarrow" => op("⇒"), "Leftarrow" => op("⇐"),
+        "leftrightarrow" => op("↔"), "Leftrightarrow" => op("⇔"),
+        "implies" => op("⟹"), "iff" => op("⟺"), "mapsto" => op("↦"),
+        "mid" => op("∣"), "parallel" => op("∥"), "perp" => op("⊥"), "angle" => op("∠"),
+        "ldots" | "dots" => op("…"), "cdots" => op("⋯"), "vdots" => op("⋮"), "ddots" => op("⋱"),
+        "langle" => op("⟨"), "rangle" => op("⟩"),
+        "lfloor" => op("⌊"), "rfloor" => op("⌋"), "lceil" => op("⌈"), "rceil" => op("⌉"),
+        "{" => op("{"), "}" => op("}"), "|" => op("‖"),
+        "%" => op("%"), "$" => op("$"), "#" => op("#"), "&" => op("&"), "_" => op("_"),
+        "prime" => op("′"),
+
+        "sum" => large("∑"), "prod" => large("∏"), "coprod" => large("∐"),
+        "bigcup" => large("⋃"), "bigcap" => large("⋂"),
+        "int" => op("∫"), "iint" => op("∬"), "iiint" => op("∭"), "oint" => op("∮"),
+
+        "," => space("0.1667em"), ":" | ">" => space("0.2222em"), ";" => space("0.2778em"),
+        "!" => space("-0.1667em"), " " => space("0.25em"),
+        "quad" => space("1em"), "qquad" => space("2em"),
+
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" |
+        "arcsin" | "arccos" | "arctan" | "sinh" | "cosh" | "tanh" |
+        "log" | "ln" | "lg" | "exp" | "dim" | "ker" | "deg" | "arg" | "hom" =>
+            Some(Node::Function { name: name.to_owned(), limits: false }),
+
+        "lim" | "limsup" | "liminf" | "max" | "min" | "sup" | "inf" |
+        "det" | "gcd" | "Pr" =>
+            Some(Node::Function { name: name.to_owned(), limits: true }),
+
+        _ => None,
+    }
+}
+
+fn char_node(c: char) -> Result<Node, String> {
+    Ok(match c {
+        c if c.is_alphabetic() => Node::Ident(c.to_string(), None),
+        c if c.is_digit(10) => Node::Number(c.to_string(), None),
+        '-' => Node::Operator { text: "\u{2212}".to_owned(), limits: false },
+        '*' => Node::Operator { text: "\u{2217}".to_owned(), limits: false },
+        '~' => Node::Space("0.25em"),
+        '&' => return Err("Alignment with & is not supported".to_owned()),
+        '#' => return Err("Unexpected #".to_owned()),
+        c => Node::Operator { text: c.to_string(), limits: false },
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// The number of groups and commands being parsed, see `MAX_DEPTH`
+    depth: usize,
+}
+
+impl Parser {
+    fn new(src: &str) -> Parser {
+        Parser { tokens: tokenize(src), pos: 0, depth: 0 }
+    }
+
+    /// Parse a group or command with `f`, refusing to go deeper than
+    /// `MAX_DEPTH`
+    fn nested<F: FnOnce(&mut Parser) -> Result<Node, String>>(&mut self, f: F) -> Result<Node, String> {
+        if self.depth == MAX_DEPTH {
+            return Err("Too deeply nested".to_owned());
+        }
+
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        while self.tokens.get(self.pos) == Some(&Token::Space) {
+            self.pos += 1;
+        }
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.peek();
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_row<F: Fn(&Token) -> bool>(&mut self, is_end: F) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+
+        loop {
+            match self.peek() {
+                None => return Ok(nodes),
+                Some(token) if is_end(token) => return Ok(nodes),
+                Some(&Token::EndGroup) => return Err("Unexpected }".to_owned()),
+                _ => (),
+            }
+
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_scripts(atom)?);
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, String> {
+        let nodes = self.parse_row(|x| x == &Token::EndGroup)?;
+        match self.next() {
+            Some(Token::EndGroup) => Ok(Node::Row(nodes)),
+            _ => Err("Missing }".to_owned()),
+        }
+    }
+
+    fn parse_argument(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::BeginGroup) => self.nested(Parser::parse_group),
+            Some(Token::Char(c)) => char_node(c),
+            Some(Token::Command(name)) => self.nested(|x| x.parse_command(&name)),
+            _ => Err("Missing argument".to_owned()),
+        }
+    }
+
+    fn parse_text_argument(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::BeginGroup) => (),
+            _ => return Err("Missing argument".to_owned()),
+        }
+
+        let mut text = String::new();
+        let mut depth = 0;
+
+        loop {
+            // Spaces are significant here, so bypass `next`
+            let token = self.tokens.get(self.pos).cloned();
+            self.pos += 1;
+
+            match token {
+                None => return Err("Missing }".to_owned()),
+                Some(Token::EndGroup) if depth == 0 => return Ok(text),
+                Some(Token::EndGroup) => depth -= 1,
+                Some(Token::BeginGroup) => depth += 1,
+                Some(Token::Space) => text.push(' '),
+                Some(Token::Superscript) => text.push('^'),
+                Some(Token::Subscript) => text.push('_'),
+                Some(Token::Char(c)) => text.push(c),
+                Some(Token::Command(name)) => {
+                    if name.chars().count() == 1 && !name.chars().all(char::is_alphabetic) {
+                        text.push_str(&name);
+                    } else {
+                        text.push('\\');
+                        text.push_str(&name);
+                    }
+                },
+            }
+        }
+    }
+
+    fn parse_delimiter(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Char('.')) => Ok(String::new()),
+            Some(Token::Char(c)) => Ok(c.to_string()),
+            Some(Token::Command(name)) => match symbol(&name) {
+                Some(Node::Operator { text, .. }) => Ok(text),
+                _ => Err(format!("Invalid delimiter \\{}", name)),
+            },
+            _ => Err("Missing delimiter".to_owned()),
+        }
+    }
+
+    fn parse_command(&mut self, name: &str) -> Result<Node, String> {
+        if let Some(node) = symbol(name) {
+            return Ok(node);
+        }
+
+        fn variant(name: &str) -> Option<&'static str> {
+            match name {
+                "mathrm" => Some("normal"),
+                "mathbf" => Some("bold"),
+                "mathit" => Some("italic"),
+                "mathbb" => Some("double-struck"),
+                "mathcal" => Some("script"),
+                "mathfrak" => Some("fraktur"),
+                "mathsf" => Some("sans-serif"),
+                "mathtt" => Some("monospace"),
+                _ => None,
+            }
+        }
+
+        if let Some(variant) = variant(name) {
+            let mut node = self.parse_argument()?;
+            node.set_variant(variant);
+            return Ok(node);
+        }
+
+        match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument()?;
+                let denominator = self.parse_argument()?;
+                Ok(Node::Frac(Box::new(numerator), Box::new(denominator)))
+            },
+            "binom" => {
+                let n = self.parse_argument()?;
+                let k = self.parse_argument()?;
+                Ok(Node::Binom(Box::new(n), Box::new(k)))
+            },
+            "sqrt" => {
+                if self.peek() == Some(&Token::Char('[')) {
+                    self.next();
+                    let index = self.parse_row(|x| x == &Token::Char(']'))?;
+                    if self.next() != Some(Token::Char(']')) {
+                        return Err("Missing ]".to_owned());
+                    }
+                    let radicand = self.parse_argument()?;
+                    Ok(Node::Root(Box::new(radicand), Box::new(Node::Row(index))))
+                } else {
+                    Ok(Node::Sqrt(Box::new(self.parse_argument()?)))
+                }
+            },
+            "text" | "textrm" | "mbox" =>
+                Ok(Node::Text(self.parse_text_argument()?)),
+            "operatorname" =>
+                Ok(Node::Function { name: self.parse_text_argument()?, limits: false }),
+            "hat" | "widehat" => Ok(Node::Over(Box::new(self.parse_argument()?), "^")),
+            "bar" | "overline" => Ok(Node::Over(Box::new(self.parse_argument()?), "\u{AF}")),
+            "vec" => Ok(Node::Over(Box::new(self.parse_argument()?), "\u{2192}")),
+            "dot" => Ok(Node::Over(Box::new(self.parse_argument()?), "\u{2D9}")),
+            "ddot" => Ok(Node::Over(Box::new(self.parse_argument()?), "\u{A8}")),
+            "tilde" | "widetilde" => Ok(Node::Over(Box::new(self.parse_argument()?), "~")),
+            "underline" => Ok(Node::Under(Box::new(self.parse_argument()?), "_")),
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let body = self.parse_row(|x| x == &Token::Command("right".to_owned()))?;
+                if self.next() != Some(Token::Command("right".to_owned())) {
+                    return Err("Missing \\right".to_owned());
+                }
+                let close = self.parse_delimiter()?;
+                Ok(Node::Fenced(open, body, close))
+            },
+            "right" => Err("Unexpected \\right".to_owned()),
+            "\\" => Err("Line breaks are not supported".to_owned()),
+            _ => Err(format!("Unknown command \\{}", name)),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::BeginGroup) => self.nested(Parser::parse_group),
+            Some(Token::Command(name)) => self.nested(|x| x.parse_command(&name)),
+            Some(Token::Char(c)) if c.is_digit(10) => {
+                let mut number = c.to_string();
+                loop {
+                    // Digits are not separated by spaces, so bypass `peek`
+                    match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+                        (Some(&Token::Char(c)), _) if c.is_digit(10) => number.push(c),
+                        (Some(&Token::Char('.')), Some(&Token::Char(c))) if c.is_digit(10) => number.push('.'),
+                        _ => break,
+                    }
+                    self.pos += 1;
+                }
+                Ok(Node::Number(number, None))
+            },
+            Some(Token::Char('\'')) => Ok(Node::Operator { text: "\u{2032}".to_owned(), limits: false }),
+            Some(Token::Char(c)) => char_node(c),
+            // A script without a base applies to an empty base
+            Some(Token::Superscript) | Some(Token::Subscript) => {
+                self.pos -= 1;
+                Ok(Node::Row(vec![]))
+            },
+            Some(Token::EndGroup) => Err("Unexpected }".to_owned()),
+            Some(Token::Space) | None => Err("Unexpected end of input".to_owned()),
+        }
+    }
+
+    fn parse_scripts(&mut self, base: Node) -> Result<Node, String> {
+        let mut sub = None;
+        let mut sup = None;
+        let mut primes = String::new();
+
+        loop {
+            let token = self.peek().cloned();
+            match token {
+                Some(Token::Subscript) => {
+                    self.next();
+                    if sub.is_some() {
+                        return Err("Double subscript".to_owned());
+                    }
+                    sub = Some(Box::new(self.parse_argument()?));
+                },
+                Some(Token::Superscript) => {
+                    self.next();
+                    if sup.is_some() {
+                        return Err("Double superscript".to_owned());
+                    }
+                    sup = Some(Box::new(self.parse_argument()?));
+                },
+                Some(Token::Char('\'')) if sup.is_none() => {
+                    self.next();
+                    primes.push('\u{2032}');
+                },
+                _ => break,
+            }
+        }
+
+        if !primes.is_empty() {
+            let primes = Node::Operator { text: primes, limits: false };
+            sup = Some(Box::new(match sup {
+                Some(sup) => Node::Row(vec![primes, *sup]),
+                None => primes,
+            }));
+        }
+
+        if sub.is_none() && sup.is_none() {
+            Ok(base)
+        } else {
+            Ok(Node::Scripts { base: Box::new(base), sub, sup })
+        }
+    }
+}
+
+fn write_node(out: &mut String, node: &Node, display: bool) {
+    fn variant_attr(variant: Option<&'static str>) -> String {
+        variant.map_or(String::new(), |x| format!(" mathvariant=\"{}\"", x))
+    }
+
+    let result = match node {
+        &Node::Number(ref x, variant) =>
+            write!(out, "<mn{}>{}</mn>", variant_attr(variant), escape_html(x)),
+        &Node::Ident(ref x, variant) =>
+            write!(out, "<mi{}>{}</mi>", variant_attr(variant), escape_html(x)),
+        &Node::Operator { ref text, .. } =>
+            write!(out, "<mo>{}</mo>", escape_html(text)),
+        &Node::Function { ref name, .. } =>
+            write!(out, "<mi>{}</mi>", escape_html(name)),
+        &Node::Text(ref x) =>
+            write!(out, "<mtext>{}</mtext>", escape_html(x)),
+        &Node::Space(width) =>
+            write!(out, "<mspace width=\"{}\"/>", width),
+        &Node::Row(ref nodes) => {
+            out.push_str("<mrow>");
+            for node in nodes {
+                write_node(out, node, display);
+            }
+            write!(out, "</mrow>")
+        },
+        &Node::Frac(ref a, ref b) => {
+            out.push_str("<mfrac>");
+            write_node(out, a, display);
+            write_node(out, b, display);
+            write!(out, "</mfrac>")
+        },
+        &Node::Binom(ref a, ref b) => {
+            out.push_str("<mrow><mo>(</mo><mfrac linethickness=\"0\">");
+            write_node(out, a, display);
+            write_node(out, b, display);
+            write!(out, "</mfrac><mo>)</mo></mrow>")
+        },
+        &Node::Sqrt(ref a) => {
+            out.push_str("<msqrt>");
+            write_node(out, a, display);
+            write!(out, "</msqrt>")
+        },
+        &Node::Root(ref a, ref index) => {
+            out.push_str("<mroot>");
+            write_node(out, a, display);
+            write_node(out, index, display);
+            write!(out, "</mroot>")
+        },
+        &Node::Over(ref a, accent) => {
+            out.push_str("<mover accent=\"true\">");
+            write_node(out, a, display);
+            write!(out, "<mo>{}</mo></mover>", escape_html(accent))
+        },
+        &Node::Under(ref a, accent) => {
+            out.push_str("<munder accentunder=\"true\">");
+            write_node(out, a, display);
+            write!(out, "<mo>{}</mo></munder>", escape_html(accent))
+        },
+        &Node::Fenced(ref open, ref nodes, ref close) => {
+            out.push_str("<mrow>");
+            if !open.is_empty() {
+                write!(out, "<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape_html(open))
+                    .expect("Writing to String cannot fail");
+            }
+            for node in nodes {
+                write_node(out, node, display);
+            }
+            if !close.is_empty() {
+                write!(out, "<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape_html(close))
+                    .expect("Writing to String cannot fail");
+            }
+            write!(out, "</mrow>")
+        },
+        &Node::Scripts { ref base, ref sub, ref sup } => {
+            let (sub_tag, sup_tag, both_tag) = match display && base.has_limits() {
+                true => ("munder", "mover", "munderover"),
+                false => ("msub", "msup", "msubsup"),
+            };
+            let tag = match (sub, sup) {
+                (&Some(_), &Some(_)) => both_tag,
+                (&Some(_), &None) => sub_tag,
+                _ => sup_tag,
+            };
+
+            write!(out, "<{}>", tag).expect("Writing to String cannot fail");
+            write_node(out, base, display);
+            if let &Some(ref sub) = sub {
+                write_node(out, sub, display);
+            }
+            if let &Some(ref sup) = sup {
+                write_node(out, sup, display);
+            }
+            write!(out, "</{}>", tag)
+        },
+    };
+    result.expect("Writing to String cannot fail");
+}
+
+/// Convert a TeX formula to MathML, or describe why it could not be converted
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, String> {
+    let mut parser = Parser::new(tex);
+    let nodes = parser.parse_row(|_| false)?;
+
+    let mut out = String::new();
+    write!(out, "<math xmlns=\"{}\"{}><semantics>",
+        MATHML_NS,
+        if display { " display=\"block\"" } else { "" }
+    ).expect("Writing to String cannot fail");
+    write_node(&mut out, &Node::Row(nodes), display);
+    write!(out, "<annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        escape_html(tex.trim())
+    ).expect("Writing to String cannot fail");
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn placeholder(index: usize) -> String {
+        format!("{}{}{}", PLACEHOLDER_START, index, PLACEHOLDER_END)
+    }
+
+    fn body(tex: &str) -> String {
+        let mathml = to_mathml(tex, false).unwrap();
+        let start = mathml.find("<semantics>").unwrap() + "<semantics>".len();
+        let end = mathml.find("<annotation").unwrap();
+        mathml[start..end].to_owned()
+    }
+
+    #[test]
+    fn extract_inline_and_display() {
+        let (src, math) = extract("Let $x_1$ be\n\n$$\\sum_i x_i$$\n");
+        assert_eq!(format!("Let {} be\n\n{}\n", placeholder(0), placeholder(1)), src);
+        assert_eq!("$x_1$", math[0].source);
+        assert_eq!(false, math[0].display);
+        assert_eq!("\\sum_i x_i", math[1].tex);
+        assert_eq!(true, math[1].display);
+    }
+
+    #[test]
+    fn extract_skips_code_and_escapes() {
+        let src = "`$x$` and \\$5\n\n```\n$y$\n```\n\n    $z$\n";
+        let (extracted, math) = extract(src);
+        assert_eq!(src, extracted);
+        assert!(math.is_empty());
+    }
+
+    #[test]
+    fn extract_ignores_currency() {
+        let src = "From $5 to $10 per month";
+        let (extracted, math) = extract(src);
+        assert_eq!(src, extracted);
+        assert!(math.is_empty());
+    }
+
+    #[test]
+    fn extract_ignores_shell_variables() {
+        let src = "Set $HOME/$PATH and $a$b";
+        let (extracted, math) = extract(src);
+        assert_eq!(src, extracted);
+        assert!(math.is_empty());
+    }
+
+    #[test]
+    fn placeholder_chars_in_source() {
+        let src = "a \u{E000}0\u{E001} $x\u{E000}$ `\u{E001}`";
+        let (extracted, math) = extract(src);
+        assert_eq!(src, restore_source(&extracted, &math));
+        assert_eq!("\u{E000}", math[0].to_html());
+        assert_eq!("$x\u{E000}$", math[4].source);
+    }
+
+    #[test]
+    fn fragments_and_restore() {
+        let (src, math) = extract("a $x$ b");
+        assert_eq!("a $x$ b", restore_source(&src, &math));
+        assert_eq!(3, fragments(&src, &math).len());
+    }
+
+    #[test]
+    fn simple_expression() {
+        assert_eq!(
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1.5</mn></mrow>",
+            body("x^2 + 1.5")
+        );
+    }
+
+    #[test]
+    fn fraction_and_escaping() {
+        assert_eq!(
+            "<mrow><mfrac><mi>a</mi><mrow><mi>b</mi><mo>&lt;</mo><mi>c</mi></mrow></mfrac></mrow>",
+            body("\\frac a{b<c}")
+        );
+    }
+
+    #[test]
+    fn display_limits() {
+        let mathml = to_mathml("\\sum_{i=1}^n i", true).unwrap();
+        assert!(mathml.contains("display=\"block\""));
+        assert!(mathml.contains("<munderover><mo>∑</mo>"));
+
+        let mathml = to_mathml("\\sum_{i=1}^n i", false).unwrap();
+        assert!(mathml.contains("<msubsup><mo>∑</mo>"));
+    }
+
+    #[test]
+    fn malformed_math() {
+        assert!(to_mathml("\\frac{a}{b", false).is_err());
+        assert!(to_mathml("x^a^b", false).is_err());
+        assert!(to_mathml("\\nosuchcommand", false).is_err());
+
+        let (_, math) = extract("$\\frac{a$");
+        let html = math[0].to_html();
+        assert!(html.starts_with("<code class=\"math-error\""));
+        assert!(html.contains("$\\frac{a$"));
+    }
+
+    #[test]
+    fn deeply_nested_math() {
+        let nested = |depth| format!("{}x{}", "{".repeat(depth), "}".repeat(depth));
+        assert!(to_mathml(&nested(MAX_DEPTH), false).is_ok());
+        assert_eq!(Err("Too deeply nested".to_owned()), to_mathml(&nested(MAX_DEPTH + 1), false));
+
+        assert!(to_mathml(&"{".repeat(100_000), false).is_err());
+        assert!(to_mathml(&format!("{}x", "\\sqrt".repeat(100_000)), false).is_err());
+
+        let (_, math) = extract(&format!("$${}$$", "{".repeat(100_000)));
+        assert!(math[0].to_html().starts_with("<code class=\"math-error\""));
+    }
+}
@@ -0,0 +1,798 @@
+use std;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, html};
+
+mod csv;
+mod math;
+mod sanitize;
+
+pub type Error = Box<std::error::Error + Send + Sync>;
+
+const MAX_INCLUDE_DEPTH: usize = 4;
+
+/// The number of includes expanded in one rendering, at any depth
+const MAX_INCLUDES: usize = 50;
+
+/// Characters from the Unicode private use area that mark ranges in text:
+/// placeholders for extracted math, and matched terms in search results.
+/// They are stripped from the search index.
+pub const MARKER_START: char = '\u{E000}';
+pub const MARKER_END: char = '\u{E001}';
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RenderOptions {
+    /// Allow a sanitized subset of raw HTML. Otherwise raw HTML is rendered
+    /// as text
+    pub allow_html: bool,
+}
+
+impl RenderOptions {
+    /// Identifies these options together with the version of the renderer,
+    /// so renderings made with other options can be told apart
+    pub fn fingerprint(&self) -> String {
+        format!("{} {:?}", env!("CARGO_PKG_VERSION"), self)
+    }
+}
+
+/// Resolves the targets of `{{include:slug}}` directives
+pub trait IncludeSource {
+    /// Get the body of the latest revision of the article with the given slug
+    fn lookup_include(&self, slug: &str) -> Result<Option<String>, Error>;
+}
+
+pub fn escape_html(src: &str) -> String {
+    let mut buf = String::with_capacity(src.len());
+    for c in src.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            c => buf.push(c),
+        }
+    }
+    buf
+}
+
+fn restore_math_source<'a>(text: CowStr<'a>, math: &[math::Math]) -> CowStr<'a> {
+    if math.is_empty() {
+        text
+    } else {
+        math::restore_source(&text, math).into()
+    }
+}
+
+fn restore_math_source_in_html<'a>(html: CowStr<'a>, math: &[math::Math]) -> CowStr<'a> {
+    if math.is_empty() {
+        html
+    } else {
+        math::fragments(&html, math)
+            .into_iter()
+            .map(|x| match x {
+                math::Fragment::Text(x) => x.to_owned(),
+                math::Fragment::Math(x) => escape_html(&x.source),
+            })
+            .collect::<String>()
+            .into()
+    }
+}
+
+fn render_math<'a, I>(events: I, math: &[math::Math]) -> Vec<Event<'a>>
+where
+    I: Iterator<Item=Event<'a>>
+{
+    let mut out = Vec::new();
+
+    // Math cannot be rendered inside code or image descriptions. In case
+    // math was extracted from these anyway, the original source is put back.
+    let mut plain_text_depth = 0;
+
+    for event in events {
+        let event = match event {
+            Event::Start(tag) => {
+                let tag = match tag {
+                    Tag::Link(link_type, dest, title) => Tag::Link(
+                        link_type,
+                        restore_math_source(dest, math),
+                        restore_math_source(title, math),
+                    ),
+                    Tag::Image(link_type, dest, title) => Tag::Image(
+                        link_type,
+                        restore_math_source(dest, math),
+                        restore_math_source(title, math),
+                    ),
+                    tag => tag,
+                };
+                match &tag {
+                    &Tag::Image(..) | &Tag::CodeBlock(..) => plain_text_depth += 1,
+                    _ => (),
+                }
+                Event::Start(tag)
+            },
+            Event::End(tag) => {
+                match &tag {
+                    &Tag::Image(..) | &Tag::CodeBlock(..) => plain_text_depth -= 1,
+                    _ => (),
+                }
+                Event::End(tag)
+            },
+            Event::Text(text) => {
+                if plain_text_depth > 0 {
+                    Event::Text(restore_math_source(text, math))
+                } else {
+                    for fragment in math::fragments(&text, math) {
+                        out.push(match fragment {
+                            math::Fragment::Text(x) => Event::Text(x.to_owned().into()),
+                            math::Fragment::Math(x) => Event::Html(x.to_html().into()),
+                        });
+                    }
+                    continue;
+                }
+            },
+            Event::Code(text) => Event::Code(restore_math_source(text, math)),
+            Event::Html(html) => Event::Html(restore_math_source_in_html(html, math)),
+            event => event,
+        };
+
+        out.push(event);
+    }
+
+    out
+}
+
+/// Whether the image is an uploaded file that can be served in smaller sizes
+fn is_resizable_attachment(dest: &str) -> bool {
+    let dest = dest.to_lowercase();
+    dest.starts_with("_files/") && !dest.contains('?') &&
+        [".png", ".jpg", ".jpeg"].iter().any(|x| dest.ends_with(x))
+}
+
+fn image_html(dest: &str, title: &str, alt: &str) -> String {
+    let dest = escape_html(dest);
+
+    let srcset = ::thumbnail::WIDTHS.iter()
+        .map(|width| format!("{}?width={} {}w", dest, width, width))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut html = format!(
+        "<img src=\"{}\" srcset=\"{}\" sizes=\"(max-width: 630px) 100vw, 616px\" alt=\"{}\"",
+        dest, srcset, escape_html(alt)
+    );
+    if !title.is_empty() {
+        html.push_str(&format!(" title=\"{}\"", escape_html(title)));
+    }
+    html.push_str(" />");
+    html
+}
+
+/// Drop link and image destinations with an unsafe scheme, such as
+/// `javascript:`, with the same rules as for raw HTML
+fn safe_urls<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let safe = |dest: CowStr<'a>| match sanitize::is_safe_url(&dest) {
+        true => dest,
+        false => "".into(),
+    };
+
+    events.into_iter()
+        .map(|x| match x {
+            Event::Start(Tag::Link(link_type, dest, title)) =>
+                Event::Start(Tag::Link(link_type, safe(dest), title)),
+            Event::Start(Tag::Image(link_type, dest, title)) =>
+                Event::Start(Tag::Image(link_type, safe(dest), title)),
+            Event::End(Tag::Link(link_type, dest, title)) =>
+                Event::End(Tag::Link(link_type, safe(dest), title)),
+            Event::End(Tag::Image(link_type, dest, title)) =>
+                Event::End(Tag::Image(link_type, safe(dest), title)),
+            x => x,
+        })
+        .collect()
+}
+
+/// Let the browser pick a thumbnail for images attached to the wiki
+fn responsive_images<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+
+    while let Some(event) = events.next() {
+        let (dest, title) = match event {
+            Event::Start(Tag::Image(_, ref dest, ref title)) if is_resizable_attachment(dest) =>
+                (dest.to_string(), title.to_string()),
+            event => {
+                out.push(event);
+                continue;
+            },
+        };
+
+        // The alt text is the plain text of the image description
+        let mut alt = String::new();
+        let mut depth = 1;
+        for event in &mut events {
+            match event {
+                Event::Start(Tag::Image(..)) => depth += 1,
+                Event::End(Tag::Image(..)) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                Event::Text(ref text) | Event::Code(ref text) => alt.push_str(text),
+                _ => (),
+            }
+        }
+
+        out.push(Event::Html(image_html(&dest, &title, &alt).into()));
+    }
+
+    out
+}
+
+/// Render fenced `csv` and `tsv` blocks as tables
+fn csv_tables<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut out = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+
+    while let Some(event) = events.next() {
+        let format = match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => csv::format(info),
+            _ => None,
+        };
+        let format = match format {
+            Some(x) => x,
+            None => {
+                out.push(event);
+                continue;
+            },
+        };
+
+        let mut src = String::new();
+        for event in &mut events {
+            match event {
+                Event::End(Tag::CodeBlock(..)) => break,
+                Event::Text(ref text) => src.push_str(text),
+                _ => (),
+            }
+        }
+
+        out.push(Event::Html(csv::to_html(&src, &format).into()));
+    }
+
+    out
+}
+
+/// Backslash-escape the `<` that starts each piece of raw HTML in `src`,
+/// until none is left. The HTML is then parsed as Markdown text, like
+/// before pulldown-cmark 0.7, so block HTML ends up in a paragraph.
+fn escape_raw_html(src: &str) -> Cow<str> {
+    let mut src = Cow::Borrowed(src);
+
+    loop {
+        let starts = Parser::new_ext(&src, parser_options())
+            .into_offset_iter()
+            .filter_map(|(event, range)| match event {
+                Event::Html(_) if src[range.start..].starts_with('<') => Some(range.start),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if starts.is_empty() {
+            return src;
+        }
+
+        let mut escaped = String::with_capacity(src.len() + starts.len());
+        let mut prev = 0;
+        for start in starts {
+            escaped.push_str(&src[prev..start]);
+            escaped.push('\\');
+            prev = start;
+        }
+        escaped.push_str(&src[prev..]);
+
+        src = Cow::Owned(escaped);
+    }
+}
+
+/// Footnote references without a definition are left as they are written,
+/// as they were before footnotes were supported
+fn undefined_footnotes_as_text<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let defined = events.iter()
+        .filter_map(|x| match x {
+            &Event::Start(Tag::FootnoteDefinition(ref label)) => Some(label.to_string()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    events.into_iter()
+        .map(|x| match x {
+            Event::FootnoteReference(ref label) if !defined.contains(&label.to_string()) =>
+                Event::Text(format!("[^{}]", label).into()),
+            x => x,
+        })
+        .collect()
+}
+
+/// Render any raw HTML that is left after `escape_raw_html` as text
+fn disable_html<'a>(event: Event<'a>) -> Event<'a> {
+    match event {
+        Event::Html(x) => Event::Text(x),
+        event => event,
+    }
+}
+
+fn is_block(tag: &Tag) -> bool {
+    match tag {
+        &Tag::Emphasis | &Tag::Strong | &Tag::Strikethrough |
+            &Tag::Link(..) | &Tag::Image(..) => false,
+        _ => true,
+    }
+}
+
+fn sanitize_html<'a, I>(events: I) -> Vec<Event<'a>>
+where
+    I: Iterator<Item=Event<'a>>
+{
+    let mut sanitizer = sanitize::Sanitizer::new();
+    let mut out = Vec::new();
+
+    for event in events {
+        match event {
+            Event::Html(html) => out.push(Event::Html(sanitizer.sanitize(&html).into())),
+            Event::Text(_) | Event::Code(_) | Event::SoftBreak | Event::HardBreak
+                if sanitizer.is_dropping() => (),
+            Event::End(tag) => {
+                if is_block(&tag) {
+                    sanitizer.end_block();
+                }
+                out.push(Event::End(tag));
+            },
+            event => out.push(event),
+        }
+    }
+
+    let rest = sanitizer.finish();
+    if !rest.is_empty() {
+        out.push(Event::Html(rest.into()));
+    }
+
+    out
+}
+
+fn include_directive(text: &str) -> Option<&str> {
+    const PREFIX: &str = "{{include:";
+    const SUFFIX: &str = "}}";
+
+    let text = text.trim();
+    if !text.starts_with(PREFIX) || !text.ends_with(SUFFIX) || text.len() < PREFIX.len() + SUFFIX.len() {
+        return None;
+    }
+
+    let slug = text[PREFIX.len()..text.len() - SUFFIX.len()].trim();
+    let valid = !slug.is_empty() &&
+        !slug.contains(|c: char| c == '{' || c == '}' || c.is_whitespace());
+
+    if valid { Some(slug) } else { None }
+}
+
+/// Recognize a paragraph that consists of nothing but an include directive.
+/// Returns the slug and the number of events that make up the paragraph.
+fn paragraph_include(events: &[Event]) -> Option<(String, usize)> {
+    match events.first() {
+        Some(&Event::Start(Tag::Paragraph)) => (),
+        _ => return None,
+    }
+
+    let mut text = String::new();
+    for (i, event) in events.iter().enumerate().skip(1) {
+        match event {
+            &Event::Text(ref x) => text.push_str(x),
+            &Event::End(Tag::Paragraph) =>
+                return include_directive(&text).map(|slug| (slug.to_owned(), i + 1)),
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+fn include_notice(message: &str, slug: &str) -> String {
+    format!(
+        "<p class=\"include-notice\">{} <a href=\"{}\">{}</a></p>\n",
+        message, escape_html(slug), escape_html(slug)
+    )
+}
+
+/// The includes being expanded, innermost last
+#[derive(Default)]
+struct IncludeStack {
+    slugs: Vec<String>,
+
+    /// The number of includes looked up so far, across the whole rendering
+    expanded: usize,
+}
+
+fn render_include<S: IncludeSource>(slug: &str, includes: &S, options: RenderOptions, stack: &mut IncludeStack)
+    -> Result<String, Error>
+{
+    if stack.slugs.iter().any(|x| x == slug) {
+        return Ok(include_notice("Circular inclusion of", slug));
+    }
+
+    if stack.slugs.len() >= MAX_INCLUDE_DEPTH {
+        return Ok(include_notice("Too deeply nested inclusion of", slug));
+    }
+
+    if stack.expanded >= MAX_INCLUDES {
+        return Ok(include_notice("Too many inclusions to include", slug));
+    }
+    stack.expanded += 1;
+
+    match includes.lookup_include(slug)? {
+        None => Ok(include_notice("Nothing to include from the missing article", slug)),
+        Some(body) => {
+            stack.slugs.push(slug.to_owned());
+            let rendered = render(&body, includes, options, stack);
+            stack.slugs.pop();
+
+            Ok(format!("<div class=\"include\">\n{}</div>\n", rendered?))
+        }
+    }
+}
+
+fn expand_includes<'a, S: IncludeSource>(events: Vec<Event<'a>>, includes: &S, options: RenderOptions, stack: &mut IncludeStack)
+    -> Result<Vec<Event<'a>>, Error>
+{
+    let mut out = Vec::with_capacity(events.len());
+
+    let mut i = 0;
+    while i < events.len() {
+        match paragraph_include(&events[i..]) {
+            Some((slug, len)) => {
+                out.push(Event::Html(render_include(&slug, includes, options, stack)?.into()));
+                i += len;
+            },
+            None => {
+                out.push(events[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn parser_options() -> Options {
+    Options::ENABLE_TABLES |
+    Options::ENABLE_FOOTNOTES |
+    Options::ENABLE_STRIKETHROUGH |
+    Options::ENABLE_TASKLISTS
+}
+
+fn render<S: IncludeSource>(src: &str, includes: &S, options: RenderOptions, stack: &mut IncludeStack)
+    -> Result<String, Error>
+{
+    let (src, math) = math::extract(src);
+
+    let src = if options.allow_html { Cow::Borrowed(&src[..]) } else { escape_raw_html(&src) };
+
+    let p = Parser::new_ext(&src, parser_options());
+    let events = if options.allow_html {
+        sanitize_html(p)
+    } else {
+        p.map(disable_html).collect()
+    };
+    let events = undefined_footnotes_as_text(events);
+    let events = expand_includes(events, includes, options, stack)?;
+
+    let mut buf = String::new();
+    let events = responsive_images(csv_tables(safe_urls(render_math(events.into_iter(), &math))));
+    html::push_html(&mut buf, events.into_iter());
+    Ok(buf)
+}
+
+/// Render Markdown to HTML, expanding `{{include:slug}}` directives from
+/// `includes`
+pub fn render_markdown<S: IncludeSource>(src: &str, includes: &S, options: RenderOptions) -> Result<String, Error> {
+    render(src, includes, options, &mut IncludeStack::default())
+}
+
+/// Find the slugs of all articles included by `src`
+pub fn find_includes(src: &str) -> Vec<String> {
+    let events = Parser::new_ext(src, parser_options()).collect::<Vec<_>>();
+
+    let mut slugs = Vec::new();
+    for i in 0..events.len() {
+        if let Some((slug, _)) = paragraph_include(&events[i..]) {
+            if !slugs.contains(&slug) {
+                slugs.push(slug);
+            }
+        }
+    }
+
+    slugs
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    impl IncludeSource for HashMap<&'static str, &'static str> {
+        fn lookup_include(&self, slug: &str) -> Result<Option<String>, Error> {
+            Ok(self.get(slug).map(|x| x.to_string()))
+        }
+    }
+
+    fn render_markdown(src: &str) -> String {
+        super::render_markdown(src, &HashMap::new(), RenderOptions::default()).unwrap()
+    }
+
+    fn render_markdown_with_html(src: &str) -> String {
+        let options = RenderOptions { allow_html: true };
+        super::render_markdown(src, &HashMap::new(), options).unwrap()
+    }
+
+    #[test]
+    fn html_is_rendered_as_text() {
+        assert_eq!(
+            "<p>&lt;b&gt;bold&lt;/b&gt;</p>\n",
+            render_markdown("<b>bold</b>")
+        );
+    }
+
+    // The expected output in the following tests is what the renderer gave
+    // before moving to pulldown-cmark 0.7, which must not change for
+    // existing articles
+
+    #[test]
+    fn html_block_is_rendered_as_paragraph_text() {
+        assert_eq!(
+            "<p>&lt;div&gt;\nhello\n&lt;/div&gt;</p>\n",
+            render_markdown("<div>\nhello\n</div>\n")
+        );
+        assert_eq!(
+            "<p>&lt;p&gt;\n<em>a</em>\n&lt;/p&gt;</p>\n",
+            render_markdown("<p>\n*a*\n</p>\n")
+        );
+        assert_eq!(
+            "<blockquote>\n<p>&lt;div&gt;\nx</p>\n</blockquote>\n",
+            render_markdown("> <div>\n> x\n")
+        );
+        assert_eq!(
+            "<p>a &lt;!-- c --&gt; b</p>\n",
+            render_markdown("a <!-- c --> b")
+        );
+    }
+
+    #[test]
+    fn code_with_html_is_unchanged() {
+        assert_eq!(
+            "<p><code>&lt;b&gt;</code></p>\n<pre><code>&lt;div&gt;\n</code></pre>\n",
+            render_markdown("`<b>`\n\n    <div>\n")
+        );
+    }
+
+    #[test]
+    fn lists_are_unchanged() {
+        assert_eq!(
+            "<ul>\n<li>a</li>\n<li>b</li>\n</ul>\n",
+            render_markdown("- a\n- b\n")
+        );
+        assert_eq!(
+            "<ol>\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n<p>c</p>\n</li>\n</ol>\n",
+            render_markdown("1. a\n2. b\n\n   c\n")
+        );
+        assert_eq!(
+            "<ul>\n<li>&lt;div&gt;\na</li>\n</ul>\n",
+            render_markdown("- <div>\n  a\n")
+        );
+    }
+
+    #[test]
+    fn undefined_footnote_reference_is_text() {
+        assert_eq!("<p>Text[^1]</p>\n", render_markdown("Text[^1]\n"));
+    }
+
+    #[test]
+    fn allowed_html_is_sanitized() {
+        assert_eq!(
+            "<p>Press <kbd>Ctrl</kbd>+<kbd>C</kbd></p>\n",
+            render_markdown_with_html("Press <kbd>Ctrl</kbd>+<kbd>C</kbd>")
+        );
+        assert_eq!(
+            "<details>\n<summary>More</summary>\n<p><em>Hidden</em></p>\n</details>\n",
+            render_markdown_with_html("<details>\n<summary>More</summary>\n\n*Hidden*\n\n</details>\n")
+        );
+        assert_eq!(
+            "<p>Click <a>here</a>.</p>\n",
+            render_markdown_with_html("Click <a href=\"javascript:alert(1)\" onclick=\"alert(1)\">here</a>.")
+        );
+    }
+
+    #[test]
+    fn unsafe_markdown_urls_are_dropped() {
+        for render in &[render_markdown, render_markdown_with_html] {
+            assert_eq!("<p><a href=\"\">a</a></p>\n", render("[a](javascript:alert(1))"));
+            assert_eq!("<p><a href=\"\">a</a></p>\n", render("[a](JavaScript&#58;alert(1))"));
+            assert_eq!("<p><a href=\"\">a</a></p>\n", render("[a]\n\n[a]: <java\tscript:alert(1)>"));
+            assert_eq!("<p><a href=\"\">javascript:alert(1)</a></p>\n", render("<javascript:alert(1)>"));
+            assert_eq!("<p><img src=\"\" alt=\"a\" /></p>\n", render("![a](data:text/html,x)"));
+            assert_eq!("<p><a href=\"https://example.com/\">a</a></p>\n", render("[a](https://example.com/)"));
+            assert_eq!("<p><a href=\"Other%20page\">a</a></p>\n", render("[a](Other%20page)"));
+        }
+    }
+
+    #[test]
+    fn scripts_are_dropped() {
+        assert_eq!(
+            "<p>Before  after</p>\n",
+            render_markdown_with_html("Before <script>alert(1)</script> after")
+        );
+        assert_eq!(
+            "\n<p>Text</p>\n",
+            render_markdown_with_html("<script>\nalert(1)\n</script>\n\nText")
+        );
+    }
+
+    #[test]
+    fn line_breaks_in_tables() {
+        assert_eq!(
+            "<table><thead><tr><th>a</th></tr></thead><tbody>\n\
+                <tr><td>1<br>2</td></tr>\n</tbody></table>\n",
+            render_markdown_with_html("| a |\n|---|\n| 1<br>2 |\n")
+        );
+    }
+
+    #[test]
+    fn math_in_html_attributes_is_escaped() {
+        assert_eq!(
+            "<p><span title=\"$&lt;b&gt;$\">x</span></p>\n",
+            render_markdown_with_html("<span title=\"$<b>$\">x</span>")
+        );
+    }
+
+    #[test]
+    fn attachment_images_have_srcset() {
+        assert_eq!(
+            "<p><img src=\"_files/a.png\" \
+                srcset=\"_files/a.png?width=400 400w, _files/a.png?width=800 800w, _files/a.png?width=1600 1600w\" \
+                sizes=\"(max-width: 630px) 100vw, 616px\" alt=\"A &quot;b&quot;\" title=\"T\" /></p>\n",
+            render_markdown("![A *\"b\"*](_files/a.png \"T\")")
+        );
+    }
+
+    #[test]
+    fn other_images_are_unchanged() {
+        assert_eq!(
+            "<p><img src=\"_files/a.gif\" alt=\"a\" /> <img src=\"http://example.com/a.png\" alt=\"b\" /></p>\n",
+            render_markdown("![a](_files/a.gif) ![b](http://example.com/a.png)")
+        );
+    }
+
+    #[test]
+    fn csv_table() {
+        assert_eq!(
+            "<table><thead><tr><th>Host</th><th>Role</th></tr>\n</thead><tbody>\n\
+            <tr><td>db1</td><td>Primary, EU</td></tr>\n\
+            </tbody></table>\n",
+            render_markdown("```csv header\nHost,Role\ndb1,\"Primary, EU\"\n```")
+        );
+    }
+
+    #[test]
+    fn tsv_table_without_header() {
+        assert_eq!(
+            "<table><tbody>\n<tr><td>a $x$</td><td>*b*</td></tr>\n</tbody></table>\n",
+            render_markdown("```tsv\na $x$\t*b*\n```")
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            "<table><thead><tr><th>a</th><th>b</th></tr></thead><tbody>\n\
+                <tr><td>1</td><td>2</td></tr>\n</tbody></table>\n",
+            render_markdown("| a | b |\n|---|---|\n| 1 | 2 |\n")
+        );
+    }
+
+    #[test]
+    fn strikethrough() {
+        assert_eq!("<p><del>gone</del></p>\n", render_markdown("~~gone~~"));
+    }
+
+    #[test]
+    fn task_lists() {
+        let rendered = render_markdown("- [ ] todo\n- [x] done\n");
+        assert!(rendered.contains("<input disabled=\"\" type=\"checkbox\"/>\ntodo"));
+        assert!(rendered.contains("<input disabled=\"\" type=\"checkbox\" checked=\"\"/>\ndone"));
+    }
+
+    #[test]
+    fn footnotes() {
+        let rendered = render_markdown("Text[^1]\n\n[^1]: Note\n");
+        assert!(rendered.contains("<sup class=\"footnote-reference\"><a href=\"#1\">1</a></sup>"));
+        assert!(rendered.contains("<div class=\"footnote-definition\" id=\"1\">"));
+    }
+
+    #[test]
+    fn math_in_text_but_not_in_code() {
+        let rendered = render_markdown("$a_1$ and `$a_1$`");
+        assert!(rendered.starts_with("<p><math "));
+        assert!(rendered.contains("<code>$a_1$</code>"));
+    }
+
+    #[test]
+    fn private_use_characters_are_kept() {
+        assert_eq!(
+            "<p>a \u{E000}0\u{E001} <code>\u{E000}</code> $HOME/$PATH</p>\n",
+            render_markdown("a \u{E000}0\u{E001} `\u{E000}` $HOME/$PATH")
+        );
+    }
+
+    #[test]
+    fn include() {
+        let includes = hashmap!{
+            "contacts" => "Call *Bob*",
+        };
+        assert_eq!(
+            "<p>Before</p>\n<div class=\"include\">\n<p>Call <em>Bob</em></p>\n</div>\n<p>After</p>\n",
+            super::render_markdown("Before\n\n{{include:contacts}}\n\nAfter", &includes, RenderOptions::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn include_missing() {
+        assert_eq!(
+            "<p class=\"include-notice\">Nothing to include from the missing article <a href=\"nope\">nope</a></p>\n",
+            render_markdown("{{include:nope}}")
+        );
+    }
+
+    #[test]
+    fn include_cycle() {
+        let includes = hashmap!{
+            "a" => "{{include:b}}",
+            "b" => "{{include:a}}",
+        };
+        let rendered = super::render_markdown("{{include:a}}", &includes, RenderOptions::default()).unwrap();
+        assert_eq!(2, rendered.matches("<div class=\"include\">").count());
+        assert!(rendered.contains("Circular inclusion of"));
+    }
+
+    #[test]
+    fn include_depth() {
+        let includes = hashmap!{
+            "a" => "{{include:b}}",
+            "b" => "{{include:c}}",
+            "c" => "{{include:d}}",
+            "d" => "{{include:e}}",
+            "e" => "Deep",
+        };
+        let rendered = super::render_markdown("{{include:a}}", &includes, RenderOptions::default()).unwrap();
+        assert!(rendered.contains("Too deeply nested inclusion of"));
+        assert!(!rendered.contains("Deep"));
+    }
+
+    #[test]
+    fn include_count() {
+        let includes = hashmap!{
+            "a" => "{{include:x}}\n\n{{include:x}}\n\n{{include:x}}\n\n{{include:x}}\n\n{{include:x}}\n\n{{include:x}}\n\n{{include:x}}\n\n{{include:x}}\n\n{{include:x}}\n\n{{include:x}}",
+            "x" => "X",
+        };
+        let src = vec!["{{include:a}}"; 10].join("\n\n");
+        let rendered = super::render_markdown(&src, &includes, RenderOptions::default()).unwrap();
+        assert_eq!(MAX_INCLUDES, rendered.matches("<div class=\"include\">").count());
+        assert!(rendered.contains("Too many inclusions to include"));
+    }
+
+    #[test]
+    fn find_includes_only_in_own_paragraphs() {
+        assert_eq!(
+            vec!["a".to_owned(), "b".to_owned()],
+            find_includes("{{include:a}}\n\nText {{include:x}}\n\n{{include:b}}\n\n    {{include:c}}\n\n{{include:a}}\n")
+        );
+    }
+}
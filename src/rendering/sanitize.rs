@@ -0,0 +1,485 @@
+use std::char;
+
+use super::escape_html;
+
+const ALLOWED_TAGS: &[&str] = &[
+    "a", "abbr", "b", "blockquote", "br", "caption", "cite", "code", "dd",
+    "del", "details", "dfn", "div", "dl", "dt", "em", "figcaption", "figure",
+    "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img", "ins", "kbd", "li",
+    "mark", "ol", "p", "pre", "q", "rp", "rt", "ruby", "s", "samp", "small",
+    "span", "strong", "sub", "summary", "sup", "table", "tbody", "td",
+    "tfoot", "th", "thead", "time", "tr", "u", "ul", "var", "wbr",
+];
+
+const VOID_TAGS: &[&str] = &["br", "hr", "img", "wbr"];
+
+/// Elements whose contents must never reach the output, not even as text
+const DROP_CONTENT_TAGS: &[&str] = &[
+    "script", "style", "iframe", "frame", "frameset", "object", "embed",
+    "applet", "noscript", "noembed", "noframes", "template", "textarea",
+    "title", "xmp", "plaintext", "svg", "math",
+];
+
+const GLOBAL_ATTRIBUTES: &[&str] = &["title", "lang", "dir"];
+
+const ALLOWED_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("a", &["href"]),
+    ("blockquote", &["cite"]),
+    ("del", &["cite", "datetime"]),
+    ("details", &["open"]),
+    ("img", &["src", "alt", "width", "height"]),
+    ("ins", &["cite", "datetime"]),
+    ("ol", &["start", "type", "reversed"]),
+    ("q", &["cite"]),
+    ("td", &["colspan", "rowspan", "align"]),
+    ("th", &["colspan", "rowspan", "align", "scope"]),
+    ("time", &["datetime"]),
+];
+
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "cite"];
+
+const SAFE_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+fn is_allowed_attribute(tag: &str, attribute: &str) -> bool {
+    GLOBAL_ATTRIBUTES.contains(&attribute) ||
+        ALLOWED_ATTRIBUTES.iter()
+            .any(|&(x, attributes)| x == tag && attributes.contains(&attribute))
+}
+
+/// Only relative URLs and URLs with a known safe scheme are allowed.
+/// Browsers ignore whitespace and control characters in the scheme, so
+/// they are ignored here as well.
+pub fn is_safe_url(url: &str) -> bool {
+    let url = url.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>();
+
+    match url.find(|c| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(i) if url[i..].starts_with(':') =>
+            SAFE_URL_SCHEMES.contains(&&url[..i].to_ascii_lowercase()[..]),
+        _ => true,
+    }
+}
+
+fn named_entity(name: &str) -> Option<char> {
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "colon" => ':',
+        "Tab" => '\t',
+        "NewLine" => '\n',
+        "sol" => '/',
+        "num" => '#',
+        "quest" => '?',
+        "period" => '.',
+        "lpar" => '(',
+        "rpar" => ')',
+        _ => return None,
+    })
+}
+
+/// Decode character references in an attribute value, so the value can be
+/// inspected and escaped again on output. Unknown named references are left
+/// as they are, which makes them render literally after escaping.
+fn decode_entities(src: &str) -> String {
+    let mut buf = String::with_capacity(src.len());
+    let mut rest = src;
+
+    while let Some(start) = rest.find('&') {
+        buf.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let after = &rest[1..];
+        let decoded = if after.starts_with('#') {
+            let (digits, radix) = if after[1..].starts_with('x') || after[1..].starts_with('X') {
+                (&after[2..], 16)
+            } else {
+                (&after[1..], 10)
+            };
+            let len = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+            if len == 0 {
+                None
+            } else {
+                let c = u32::from_str_radix(&digits[..len], radix).ok()
+                    .and_then(|x| if x == 0 { None } else { char::from_u32(x) })
+                    .unwrap_or('\u{fffd}');
+                let mut consumed = 1 + (after.len() - digits.len()) + len;
+                if digits[len..].starts_with(';') {
+                    consumed += 1;
+                }
+                Some((c, consumed))
+            }
+        } else {
+            after.find(';')
+                .and_then(|end| named_entity(&after[..end]).map(|c| (c, end + 2)))
+        };
+
+        match decoded {
+            Some((c, consumed)) => {
+                buf.push(c);
+                rest = &rest[consumed..];
+            },
+            None => {
+                buf.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    buf.push_str(rest);
+    buf
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack.to_ascii_lowercase().find(needle)
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Open {
+        name: String,
+        attributes: Vec<(String, Option<String>)>,
+        self_closing: bool,
+    },
+    Close(String),
+    Ignored,
+}
+
+fn is_attribute_name_char(c: char) -> bool {
+    !c.is_whitespace() && c != '"' && c != '\'' && c != '>' && c != '/' && c != '='
+}
+
+/// Parse the tag at the start of `src`, which starts with `<`. Returns the
+/// token and the number of bytes consumed, or `None` if `src` does not start
+/// with something that the browser would treat as markup.
+fn parse_markup(src: &str) -> Option<(Token, usize)> {
+    let after = &src[1..];
+
+    if after.starts_with("!--") {
+        let end = after[3..].find("-->").map(|x| 1 + 3 + x + 3).unwrap_or(src.len());
+        return Some((Token::Ignored, end));
+    }
+
+    if after.starts_with('!') || after.starts_with('?') {
+        let end = after.find('>').map(|x| x + 2).unwrap_or(src.len());
+        return Some((Token::Ignored, end));
+    }
+
+    let (closing, after) = if after.starts_with('/') {
+        (true, &after[1..])
+    } else {
+        (false, after)
+    };
+
+    if !after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let name_len = after.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(after.len());
+    let name = after[..name_len].to_ascii_lowercase();
+    let mut rest = &after[name_len..];
+
+    let mut attributes = Vec::new();
+    let mut self_closing = false;
+
+    loop {
+        rest = rest.trim_left_matches(|c: char| c.is_whitespace());
+
+        if rest.starts_with('>') {
+            rest = &rest[1..];
+            break;
+        }
+
+        if rest.starts_with('/') {
+            rest = &rest[1..];
+            self_closing = rest.starts_with('>');
+            continue;
+        }
+
+        if rest.is_empty() {
+            // Unterminated tag
+            return None;
+        }
+
+        let attr_len = rest.find(|c: char| !is_attribute_name_char(c)).unwrap_or(rest.len());
+        let attr_len = if attr_len == 0 { rest.chars().next().unwrap().len_utf8() } else { attr_len };
+        let attr_name = rest[..attr_len].to_ascii_lowercase();
+        rest = &rest[attr_len..];
+
+        let after_name = rest.trim_left_matches(|c: char| c.is_whitespace());
+        let value = if after_name.starts_with('=') {
+            let value_src = after_name[1..].trim_left_matches(|c: char| c.is_whitespace());
+            let (value, len) = match value_src.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    match value_src[1..].find(quote) {
+                        Some(end) => (&value_src[1..end + 1], end + 2),
+                        None => return None,
+                    }
+                },
+                _ => {
+                    let end = value_src.find(|c: char| c.is_whitespace() || c == '>')
+                        .unwrap_or(value_src.len());
+                    (&value_src[..end], end)
+                },
+            };
+            rest = &value_src[len..];
+            Some(decode_entities(value))
+        } else {
+            None
+        };
+
+        attributes.push((attr_name, value));
+    }
+
+    let consumed = src.len() - rest.len();
+
+    if closing {
+        Some((Token::Close(name), consumed))
+    } else {
+        Some((Token::Open { name, attributes, self_closing }, consumed))
+    }
+}
+
+fn next_token(src: &str) -> (Token, usize) {
+    match src.find('<') {
+        Some(0) => match parse_markup(src) {
+            Some(x) => x,
+            None => (Token::Text("&lt;"), 1),
+        },
+        Some(x) => (Token::Text(&src[..x]), x),
+        None => (Token::Text(src), src.len()),
+    }
+}
+
+/// An allowlist based HTML sanitizer for the raw HTML fragments in
+/// Markdown. Fragments are fed in document order, and elements that are
+/// opened in one fragment may be closed in a later one.
+pub struct Sanitizer {
+    open: Vec<String>,
+    dropping: Option<String>,
+}
+
+impl Sanitizer {
+    pub fn new() -> Sanitizer {
+        Sanitizer {
+            open: vec![],
+            dropping: None,
+        }
+    }
+
+    /// Whether we are inside an element whose contents are being dropped
+    pub fn is_dropping(&self) -> bool {
+        self.dropping.is_some()
+    }
+
+    /// Stop dropping contents. Used at the end of Markdown blocks, so an
+    /// unterminated `<script>` cannot swallow the rest of the document.
+    pub fn end_block(&mut self) {
+        self.dropping = None;
+    }
+
+    pub fn sanitize(&mut self, src: &str) -> String {
+        let mut buf = String::with_capacity(src.len());
+        let mut rest = src;
+
+        while !rest.is_empty() {
+            if let Some(dropping) = self.dropping.take() {
+                let close = format!("</{}", dropping);
+                match find_ignore_case(rest, &close) {
+                    Some(start) => {
+                        let end = rest[start..].find('>').map(|x| start + x + 1).unwrap_or(rest.len());
+                        rest = &rest[end..];
+                    },
+                    None => {
+                        self.dropping = Some(dropping);
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let (token, consumed) = next_token(rest);
+            rest = &rest[consumed..];
+
+            match token {
+                Token::Text(text) => buf.push_str(&text.replace('>', "&gt;")),
+                Token::Open { name, attributes, self_closing } => {
+                    if DROP_CONTENT_TAGS.contains(&&name[..]) {
+                        if !self_closing {
+                            self.dropping = Some(name);
+                        }
+                    } else if ALLOWED_TAGS.contains(&&name[..]) {
+                        self.push_open_tag(&mut buf, name, attributes);
+                    }
+                },
+                Token::Close(name) => self.push_close_tag(&mut buf, &name),
+                Token::Ignored => (),
+            }
+        }
+
+        buf
+    }
+
+    /// Close all elements that are still open
+    pub fn finish(&mut self) -> String {
+        let mut buf = String::new();
+        while let Some(name) = self.open.pop() {
+            buf.push_str(&format!("</{}>", name));
+        }
+        self.dropping = None;
+        buf
+    }
+
+    fn push_open_tag(&mut self, buf: &mut String, name: String, attributes: Vec<(String, Option<String>)>) {
+        buf.push('<');
+        buf.push_str(&name);
+
+        for (attribute, value) in attributes {
+            if !is_allowed_attribute(&name, &attribute) {
+                continue;
+            }
+
+            match value {
+                Some(value) => {
+                    if URL_ATTRIBUTES.contains(&&attribute[..]) && !is_safe_url(&value) {
+                        continue;
+                    }
+                    buf.push_str(&format!(" {}=\"{}\"", attribute, escape_html(&value)));
+                },
+                None => {
+                    if URL_ATTRIBUTES.contains(&&attribute[..]) {
+                        continue;
+                    }
+                    buf.push(' ');
+                    buf.push_str(&attribute);
+                }
+            }
+        }
+
+        buf.push('>');
+
+        if !VOID_TAGS.contains(&&name[..]) {
+            self.open.push(name);
+        }
+    }
+
+    fn push_close_tag(&mut self, buf: &mut String, name: &str) {
+        if !self.open.iter().any(|x| x == name) {
+            // Not opened by the sanitized HTML. Dropping it keeps authors
+            // from closing elements that belong to the page.
+            return;
+        }
+
+        while let Some(open) = self.open.pop() {
+            buf.push_str(&format!("</{}>", open));
+            if open == name {
+                break;
+            }
+        }
+    }
+}
+
+/// Sanitize a complete HTML document fragment
+#[cfg(test)]
+pub fn sanitize(src: &str) -> String {
+    let mut sanitizer = Sanitizer::new();
+    let mut buf = sanitizer.sanitize(src);
+    buf.push_str(&sanitizer.finish());
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn safe_markup_is_kept() {
+        assert_eq!(
+            "<details open><summary>More</summary>Press <kbd>Ctrl</kbd>+<kbd>C</kbd><br></details>",
+            sanitize("<details open><summary>More</summary>Press <kbd>Ctrl</kbd>+<kbd>C</kbd><br></details>")
+        );
+        assert_eq!(
+            "<a href=\"https://example.com/?a=1&amp;b=2\" title=\"x\">link</a>",
+            sanitize("<A HREF='https://example.com/?a=1&amp;b=2' title=x>link</a>")
+        );
+        assert_eq!("<img src=\"pic.png\" alt=\"A picture\">", sanitize("<img src=pic.png alt=\"A picture\" />"));
+    }
+
+    #[test]
+    fn unbalanced_tags() {
+        assert_eq!("<sup>1</sup>", sanitize("<sup>1"));
+        assert_eq!("text", sanitize("text</div></article></body>"));
+        assert_eq!("<b><i>x</i></b>", sanitize("<b><i>x</b>"));
+    }
+
+    #[test]
+    fn text_is_kept() {
+        assert_eq!("1 &lt; 2 &gt; 0 &amp;", sanitize("1 < 2 > 0 &amp;"));
+    }
+
+    #[test]
+    fn xss_corpus() {
+        let corpus = [
+            ("<script>alert(1)</script>", ""),
+            ("<SCRIPT SRC=//evil.example/x.js></SCRIPT>", ""),
+            ("<script>document.write('</scr' + 'ipt>')</script>", ""),
+            ("<scr<script>ipt>alert(1)</script>", "ipt&gt;alert(1)"),
+            ("<style>body { display: none }</style>", ""),
+            ("<img src=x onerror=alert(1)>", "<img src=\"x\">"),
+            ("<img src=x onerror=\"alert(1)\"/>", "<img src=\"x\">"),
+            ("<img src=\"javascript:alert(1)\">", "<img>"),
+            ("<img src=\"data:image/svg+xml;base64,PHN2Zz4=\">", "<img>"),
+            ("<IMG SRC=\"jav\tascript:alert('XSS');\">", "<img>"),
+            ("<img src=\" &#14;  javascript:alert(1)\">", "<img>"),
+            ("<a href=\"javascript:alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"JaVaScRiPt:alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\" javascript:alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"jav&#x09;ascript:alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"&#106;&#97;&#118;&#97;&#115;&#99;&#114;&#105;&#112;&#116;&#58;alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"&#x6A&#x61&#x76&#x61&#x73&#x63&#x72&#x69&#x70&#x74&#x3A;alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"javascript&colon;alert(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"vbscript:msgbox(1)\">x</a>", "<a>x</a>"),
+            ("<a href=\"data:text/html,<script>alert(1)</script>\">x</a>", "<a>x</a>"),
+            ("<a href=javascript:alert(1)>x</a>", "<a>x</a>"),
+            ("<a href>x</a>", "<a>x</a>"),
+            ("<a href=\"x\" onclick=\"alert(1)\">x</a>", "<a href=\"x\">x</a>"),
+            ("<a href=\"/wiki/page\" style=\"position:fixed\">x</a>", "<a href=\"/wiki/page\">x</a>"),
+            ("<a title='\"><script>alert(1)</script>'>x</a>", "<a title=\"&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;\">x</a>"),
+            ("<p/onmouseover=alert(1)>x</p>", "<p>x</p>"),
+            ("<details open ontoggle=alert(1)>", "<details open></details>"),
+            ("<div style=\"background:url(javascript:alert(1))\">x</div>", "<div>x</div>"),
+            ("<span class=\"editor-controls\" id=\"x\">x</span>", "<span>x</span>"),
+            ("<svg onload=alert(1)>", ""),
+            ("<svg><script>alert(1)</script></svg>", ""),
+            ("<math><mi xlink:href=\"javascript:alert(1)\">x</mi></math>", ""),
+            ("<iframe src=\"https://evil.example\"></iframe>", ""),
+            ("<iframe srcdoc=\"<script>alert(1)</script>\">", ""),
+            ("<object data=\"javascript:alert(1)\"></object>", ""),
+            ("<embed src=\"javascript:alert(1)\">", ""),
+            ("<body onload=alert(1)>", ""),
+            ("<meta http-equiv=\"refresh\" content=\"0;url=javascript:alert(1)\">", ""),
+            ("<base href=\"javascript:/\">", ""),
+            ("<link rel=\"stylesheet\" href=\"https://evil.example/x.css\">", ""),
+            ("<form action=\"https://evil.example\"><input name=password></form>", ""),
+            ("<button formaction=javascript:alert(1)>x</button>", "x"),
+            ("<!--<script>alert(1)</script>-->", ""),
+            ("<!--><script>alert(1)</script>-->", ""),
+            ("<![CDATA[<script>alert(1)</script>]]>", "alert(1)]]&gt;"),
+            ("<?xml version=\"1.0\"?>", ""),
+            ("<textarea><script>alert(1)</script></textarea>", ""),
+            ("<noscript><p title=\"</noscript><img src=x onerror=alert(1)>\"></noscript>", "<img src=\"x\">\"&gt;"),
+            ("<table background=\"javascript:alert(1)\"><tr><td>x</td></tr></table>", "<table><tr><td>x</td></tr></table>"),
+            ("<blockquote cite=\"javascript:alert(1)\">x</blockquote>", "<blockquote>x</blockquote>"),
+            ("<a href=\"https://ok.example\"\nonmouseover=alert(1)>x</a>", "<a href=\"https://ok.example\">x</a>"),
+            ("<img src=\"x\" alt=\"x\" onerror=alert(1)", "&lt;img src=\"x\" alt=\"x\" onerror=alert(1)"),
+        ];
+
+        for &(input, expected) in corpus.iter() {
+            assert_eq!(expected, sanitize(input), "Sanitizing {:?}", input);
+        }
+    }
+}
@@ -8,24 +8,47 @@ use serde_urlencoded;
 
 use assets::ScriptJs;
 use mimes::*;
-use rendering::render_markdown;
+use models;
 use site::Layout;
 use state::{State, UpdateResult, RebaseConflict};
 use web::{Resource, ResponseFuture};
 
 use super::changes_resource::QueryParameters;
+use super::user_resource::user_link;
 
 #[derive(BartDisplay)]
 #[template="templates/article.html"]
 struct Template<'a> {
+    article_id: Option<i32>,
     revision: i32,
     last_updated: Option<&'a str>,
+    attachments: Vec<models::AttachmentStub>,
 
     edit: bool,
     cancel_url: Option<&'a str>,
     title: &'a str,
     raw: &'a str,
     rendered: String,
+
+    watch: Option<Watch>,
+}
+
+/// The button for watching or unwatching the article, for known identities
+pub struct Watch {
+    pub article_id: i32,
+    /// The new state after pressing the button
+    pub watch: bool,
+    pub label: &'static str,
+}
+
+impl Watch {
+    pub fn new(article_id: i32, watching: bool) -> Self {
+        Watch {
+            article_id,
+            watch: !watching,
+            label: if watching { "Unwatch" } else { "Watch" },
+        }
+    }
 }
 
 impl<'a> Template<'a> {
@@ -46,11 +69,12 @@ pub struct ArticleResource {
     article_id: i32,
     revision: i32,
     edit: bool,
+    identity: Option<String>,
 }
 
 impl ArticleResource {
     pub fn new(state: State, article_id: i32, revision: i32, edit: bool) -> Self {
-        Self { state, article_id, revision, edit }
+        Self { state, article_id, revision, edit, identity: None }
     }
 }
 
@@ -58,10 +82,11 @@ pub fn last_updated(article_id: i32, created: &DateTime<Local>, author: Option<&
     struct Author<'a> {
         author: &'a str,
         history: String,
+        profile: String,
     }
 
     #[derive(BartDisplay)]
-    #[template_string = "Last updated <a href=\"{{article_history}}\">{{created}}</a>{{#author}} by <a href=\"{{.history}}\">{{.author}}</a>{{/author}}"]
+    #[template_string = "Last updated <a href=\"{{article_history}}\">{{created}}</a>{{#author}} by <a href=\"{{.history}}\">{{.author}}</a> (<a href=\"{{.profile}}\">profile</a>){{/author}}"]
     struct Template<'a> {
         created: &'a str,
         article_history: &'a str,
@@ -73,6 +98,7 @@ pub fn last_updated(article_id: i32, created: &DateTime<Local>, author: Option<&
         article_history: &format!("_changes{}", QueryParameters::default().article_id(Some(article_id)).into_link()),
         author: author.map(|author| Author {
             author: &author,
+            profile: user_link(author),
             history: format!("_changes{}", QueryParameters::default().author(Some(author.to_owned())).into_link()),
         }),
     }.to_string()
@@ -84,6 +110,10 @@ impl Resource for ArticleResource {
         vec![Options, Head, Get, Put, Post]
     }
 
+    fn hacky_inject_identity(&mut self, identity: Option<String>) {
+        self.identity = identity;
+    }
+
     fn head(&self) -> ResponseFuture {
         Box::new(futures::finished(Response::new()
             .with_status(hyper::StatusCode::Ok)
@@ -92,28 +122,41 @@ impl Resource for ArticleResource {
     }
 
     fn get(self: Box<Self>) -> ResponseFuture {
+        let state = self.state.clone();
         let data = self.state.get_article_revision(self.article_id, self.revision)
-            .map(|x| x.expect("Data model guarantees that this exists"));
+            .map(|x| x.expect("Data model guarantees that this exists"))
+            .and_then(move |data| {
+                state.render_article(data.article_id, data.revision, data.body.clone())
+                    .map(move |rendered| (data, rendered))
+            });
+        let attachments = self.state.get_article_attachments(self.article_id);
+        let watching: Box<Future<Item=Option<bool>, Error=::state::Error>> = match self.identity {
+            Some(ref identity) => Box::new(self.state.is_watching(identity.clone(), self.article_id).map(Some)),
+            None => Box::new(futures::finished(None)),
+        };
         let head = self.head();
 
-        Box::new(data.join(head)
-            .and_then(move |(data, head)| {
+        Box::new(data.join4(attachments, watching, head)
+            .and_then(move |((data, rendered), attachments, watching, head)| {
                 Ok(head
                     .with_body(Layout {
                         base: None, // Hmm, should perhaps accept `base` as argument
                         title: &data.title,
                         body: &Template {
+                            article_id: Some(data.article_id),
                             revision: data.revision,
                             last_updated: Some(&last_updated(
                                 data.article_id,
                                 &Local.from_utc_datetime(&data.created),
                                 data.author.as_ref().map(|x| &**x)
                             )),
+                            attachments,
                             edit: self.edit,
                             cancel_url: Some(data.link()),
                             title: &data.title,
                             raw: &data.body,
-                            rendered: render_markdown(&data.body),
+                            rendered,
+                            watch: watching.map(|x| Watch::new(data.article_id, x)),
                         },
                     }.to_string()))
             }))
@@ -142,6 +185,8 @@ impl Resource for ArticleResource {
             last_updated: &'a str,
         }
 
+        let state = self.state.clone();
+
         Box::new(body
             .concat2()
             .map_err(Into::into)
@@ -152,53 +197,63 @@ impl Resource for ArticleResource {
             .and_then(move |update: UpdateArticle| {
                 self.state.update_article(self.article_id, update.base_revision, update.title, update.body, identity)
             })
-            .and_then(|updated| match updated {
-                UpdateResult::Success(updated) =>
-                    Ok(Response::new()
-                        .with_status(hyper::StatusCode::Ok)
-                        .with_header(ContentType(APPLICATION_JSON.clone()))
-                        .with_body(serde_json::to_string(&PutResponse {
-                            conflict: false,
-                            slug: &updated.slug,
-                            revision: updated.revision,
-                            title: &updated.title,
-                            body: &updated.body,
-                            rendered: &Template {
-                                title: &updated.title,
-                                rendered: render_markdown(&updated.body),
-                            }.to_string(),
-                            last_updated: &last_updated(
-                                updated.article_id,
-                                &Local.from_utc_datetime(&updated.created),
-                                updated.author.as_ref().map(|x| &**x)
-                            ),
-                        }).expect("Should never fail"))
+            .and_then(move |updated| -> ResponseFuture {
+                match updated {
+                    UpdateResult::Success(updated) => Box::new(state
+                        .render_article(updated.article_id, updated.revision, updated.body.clone())
+                        .and_then(move |rendered| {
+                            Ok(Response::new()
+                                .with_status(hyper::StatusCode::Ok)
+                                .with_header(ContentType(APPLICATION_JSON.clone()))
+                                .with_body(serde_json::to_string(&PutResponse {
+                                    conflict: false,
+                                    slug: &updated.slug,
+                                    revision: updated.revision,
+                                    title: &updated.title,
+                                    body: &updated.body,
+                                    rendered: &Template {
+                                        title: &updated.title,
+                                        rendered,
+                                    }.to_string(),
+                                    last_updated: &last_updated(
+                                        updated.article_id,
+                                        &Local.from_utc_datetime(&updated.created),
+                                        updated.author.as_ref().map(|x| &**x)
+                                    ),
+                                }).expect("Should never fail"))
+                            )
+                        })
                     ),
-                UpdateResult::RebaseConflict(RebaseConflict {
-                    base_article, title, body
-                }) => {
-                    let title = title.flatten();
-                    let body = body.flatten();
-                    Ok(Response::new()
-                        .with_status(hyper::StatusCode::Ok)
-                        .with_header(ContentType(APPLICATION_JSON.clone()))
-                        .with_body(serde_json::to_string(&PutResponse {
-                            conflict: true,
-                            slug: &base_article.slug,
-                            revision: base_article.revision,
-                            title: &title,
-                            body: &body,
-                            rendered: &Template {
-                                title: &title,
-                                rendered: render_markdown(&body),
-                            }.to_string(),
-                            last_updated: &last_updated(
-                                base_article.article_id,
-                                &Local.from_utc_datetime(&base_article.created),
-                                base_article.author.as_ref().map(|x| &**x)
-                            ),
-                        }).expect("Should never fail"))
-                    )
+                    UpdateResult::RebaseConflict(RebaseConflict {
+                        base_article, title, body
+                    }) => {
+                        let title = title.flatten();
+                        let body = body.flatten();
+                        Box::new(state.render_markdown(body.clone())
+                            .and_then(move |rendered| {
+                                Ok(Response::new()
+                                    .with_status(hyper::StatusCode::Ok)
+                                    .with_header(ContentType(APPLICATION_JSON.clone()))
+                                    .with_body(serde_json::to_string(&PutResponse {
+                                        conflict: true,
+                                        slug: &base_article.slug,
+                                        revision: base_article.revision,
+                                        title: &title,
+                                        body: &body,
+                                        rendered: &Template {
+                                            title: &title,
+                                            rendered,
+                                        }.to_string(),
+                                        last_updated: &last_updated(
+                                            base_article.article_id,
+                                            &Local.from_utc_datetime(&base_article.created),
+                                            base_article.author.as_ref().map(|x| &**x)
+                                        ),
+                                    }).expect("Should never fail"))
+                                )
+                            })
+                        )
+                    }
                 }
             })
         )
@@ -209,6 +264,8 @@ impl Resource for ArticleResource {
 
         use futures::Stream;
 
+        let state = self.state.clone();
+
         Box::new(body
             .concat2()
             .map_err(Into::into)
@@ -219,39 +276,46 @@ impl Resource for ArticleResource {
             .and_then(move |update: UpdateArticle| {
                 self.state.update_article(self.article_id, update.base_revision, update.title, update.body, identity)
             })
-            .and_then(|updated| {
+            .and_then(move |updated| -> ResponseFuture {
                 match updated {
-                    UpdateResult::Success(updated) => Ok(Response::new()
+                    UpdateResult::Success(updated) => Box::new(futures::finished(Response::new()
                         .with_status(hyper::StatusCode::SeeOther)
                         .with_header(ContentType(TEXT_PLAIN.clone()))
                         .with_header(Location::new(updated.link().to_owned()))
                         .with_body("See other")
-                    ),
+                    )),
                     UpdateResult::RebaseConflict(RebaseConflict {
                         base_article, title, body
                     }) => {
                         let title = title.flatten();
                         let body = body.flatten();
-                        Ok(Response::new()
-                            .with_status(hyper::StatusCode::Ok)
-                            .with_header(ContentType(TEXT_HTML.clone()))
-                            .with_body(Layout {
-                                base: None,
-                                title: &title,
-                                body: &Template {
-                                    revision: base_article.revision,
-                                    last_updated: Some(&last_updated(
-                                        base_article.article_id,
-                                        &Local.from_utc_datetime(&base_article.created),
-                                        base_article.author.as_ref().map(|x| &**x)
-                                    )),
-                                    edit: true,
-                                    cancel_url: Some(base_article.link()),
-                                    title: &title,
-                                    raw: &body,
-                                    rendered: render_markdown(&body),
-                                },
-                            }.to_string())
+                        Box::new(state.render_markdown(body.clone())
+                            .and_then(move |rendered| {
+                                Ok(Response::new()
+                                    .with_status(hyper::StatusCode::Ok)
+                                    .with_header(ContentType(TEXT_HTML.clone()))
+                                    .with_body(Layout {
+                                        base: None,
+                                        title: &title,
+                                        body: &Template {
+                                            article_id: Some(base_article.article_id),
+                                            revision: base_article.revision,
+                                            last_updated: Some(&last_updated(
+                                                base_article.article_id,
+                                                &Local.from_utc_datetime(&base_article.created),
+                                                base_article.author.as_ref().map(|x| &**x)
+                                            )),
+                                            attachments: vec![],
+                                            edit: true,
+                                            cancel_url: Some(base_article.link()),
+                                            title: &title,
+                                            raw: &body,
+                                            rendered,
+                                            watch: None,
+                                        },
+                                    }.to_string())
+                                )
+                            })
                         )
                     }
                 }
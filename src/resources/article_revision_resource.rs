@@ -6,21 +6,23 @@ use hyper::server::*;
 
 use mimes::*;
 use models;
-use rendering::render_markdown;
 use site::Layout;
+use state::State;
 use web::{Resource, ResponseFuture};
 
 use super::changes_resource::QueryParameters;
+use super::user_resource::user_link;
 use super::diff_resource;
 use super::pagination::Pagination;
 
 pub struct ArticleRevisionResource {
+    state: State,
     data: models::ArticleRevision,
 }
 
 impl ArticleRevisionResource {
-    pub fn new(data: models::ArticleRevision) -> Self {
-        Self { data }
+    pub fn new(state: State, data: models::ArticleRevision) -> Self {
+        Self { state, data }
     }
 }
 
@@ -28,10 +30,11 @@ pub fn timestamp_and_author(sequence_number: i32, article_id: i32, created: &Dat
     struct Author<'a> {
         author: &'a str,
         history: String,
+        profile: String,
     }
 
     #[derive(BartDisplay)]
-    #[template_string = "<a href=\"{{article_history}}\">{{created}}</a>{{#author}} by <a href=\"{{.history}}\">{{.author}}</a>{{/author}}"]
+    #[template_string = "<a href=\"{{article_history}}\">{{created}}</a>{{#author}} by <a href=\"{{.history}}\">{{.author}}</a> (<a href=\"{{.profile}}\">profile</a>){{/author}}"]
     struct Template<'a> {
         created: &'a str,
         article_history: &'a str,
@@ -50,6 +53,7 @@ pub fn timestamp_and_author(sequence_number: i32, article_id: i32, created: &Dat
         ),
         author: author.map(|author| Author {
             author: &author,
+            profile: user_link(author),
             history: format!("_changes{}",
                 QueryParameters::default()
                     .pagination(pagination)
@@ -87,10 +91,11 @@ impl Resource for ArticleRevisionResource {
         }
 
         let head = self.head();
+        let rendered = self.state.render_article(self.data.article_id, self.data.revision, self.data.body.clone());
         let data = self.data;
 
-        Box::new(head
-            .and_then(move |head|
+        Box::new(head.join(rendered)
+            .and_then(move |(head, rendered)|
                 Ok(head
                     .with_body(Layout {
                         base: Some("../../"), // Hmm, should perhaps accept `base` as argument
@@ -116,7 +121,7 @@ impl Resource for ArticleRevisionResource {
                                     None
                                 },
                             title: &data.title,
-                            rendered: render_markdown(&data.body),
+                            rendered,
                         },
                     }.to_string()))
             ))
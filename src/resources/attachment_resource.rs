@@ -0,0 +1,159 @@
+use std::cmp;
+
+use futures::{self, Future};
+use hyper;
+use hyper::header::{
+    AcceptRanges, ByteRangeSpec, Charset, ContentDisposition, ContentLength,
+    ContentRange, ContentRangeSpec, ContentType, DispositionParam,
+    DispositionType, ETag, EntityTag, Headers, IfNoneMatch, Range, RangeUnit,
+};
+use hyper::mime;
+use hyper::server::*;
+
+use mimes;
+use models;
+use web::{Resource, ResponseFuture};
+
+header! { (ContentSecurityPolicy, "Content-Security-Policy") => [String] }
+header! { (XContentTypeOptions, "X-Content-Type-Options") => [String] }
+
+/// Resolve a byte range against the length of the content. Returns the
+/// first and last byte, inclusive, or `None` if the range cannot be
+/// satisfied.
+fn satisfiable_range(spec: &ByteRangeSpec, len: u64) -> Option<(u64, u64)> {
+    match *spec {
+        ByteRangeSpec::FromTo(first, last) if first <= last && first < len =>
+            Some((first, cmp::min(last, len - 1))),
+        ByteRangeSpec::AllFrom(first) if first < len =>
+            Some((first, len - 1)),
+        ByteRangeSpec::Last(n) if n > 0 && len > 0 =>
+            Some((len - cmp::min(n, len), len - 1)),
+        _ => None,
+    }
+}
+
+pub struct AttachmentResource {
+    attachment: models::Attachment,
+    width: Option<u32>,
+    if_none_match: Option<IfNoneMatch>,
+    range: Option<Range>,
+}
+
+impl AttachmentResource {
+    /// `width` is the thumbnail width when `attachment` holds a scaled down
+    /// version of the original
+    pub fn new(attachment: models::Attachment, width: Option<u32>) -> Self {
+        Self {
+            attachment,
+            width,
+            if_none_match: None,
+            range: None,
+        }
+    }
+
+    fn etag(&self) -> EntityTag {
+        match self.width {
+            Some(width) => EntityTag::strong(format!("{}-{}", self.attachment.sequence_number, width)),
+            None => EntityTag::strong(self.attachment.sequence_number.to_string()),
+        }
+    }
+
+    fn not_modified(&self) -> bool {
+        let etag = self.etag();
+
+        match self.if_none_match {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(ref tags)) => tags.iter().any(|x| x.weak_eq(&etag)),
+            None => false,
+        }
+    }
+
+    fn response(&self) -> Response {
+        let content_type = self.attachment.content_type.parse()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+        // Uploaded files are served from the same origin as the wiki, so
+        // they must not be able to run scripts in its context.
+        let response = Response::new()
+            .with_header(ContentType(content_type))
+            .with_header(ETag(self.etag()))
+            .with_header(AcceptRanges(vec![RangeUnit::Bytes]))
+            .with_header(XContentTypeOptions("nosniff".to_owned()))
+            .with_header(ContentSecurityPolicy("sandbox".to_owned()));
+
+        if mimes::is_inline_safe(&self.attachment.content_type) {
+            response
+        } else {
+            response.with_header(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(
+                    Charset::Ext("UTF-8".to_owned()),
+                    None,
+                    self.attachment.name.as_bytes().to_vec(),
+                )],
+            })
+        }
+    }
+}
+
+impl Resource for AttachmentResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Head, Get]
+    }
+
+    fn head(&self) -> ResponseFuture {
+        Box::new(futures::finished(self.response()
+            .with_status(hyper::StatusCode::Ok)
+            .with_header(ContentLength(self.attachment.data.len() as u64))
+        ))
+    }
+
+    fn get(self: Box<Self>) -> ResponseFuture {
+        if self.not_modified() {
+            return Box::new(futures::finished(Response::new()
+                .with_status(hyper::StatusCode::NotModified)
+                .with_header(ETag(self.etag()))
+            ));
+        }
+
+        let len = self.attachment.data.len() as u64;
+
+        // Only single ranges are supported. For anything else, the full
+        // content is a valid response.
+        let range = match self.range {
+            Some(Range::Bytes(ref specs)) if specs.len() == 1 =>
+                Some(satisfiable_range(&specs[0], len)),
+            _ => None,
+        };
+
+        let response = self.response();
+        let data = self.attachment.data;
+
+        Box::new(futures::finished(match range {
+            None => response
+                .with_status(hyper::StatusCode::Ok)
+                .with_header(ContentLength(len))
+                .with_body(data),
+            Some(Some((first, last))) => response
+                .with_status(hyper::StatusCode::PartialContent)
+                .with_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: Some((first, last)),
+                    instance_length: Some(len),
+                }))
+                .with_header(ContentLength(last - first + 1))
+                .with_body(data[first as usize..last as usize + 1].to_vec()),
+            Some(None) => Response::new()
+                .with_status(hyper::StatusCode::RangeNotSatisfiable)
+                .with_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(len),
+                })),
+        }))
+    }
+
+    fn hacky_inject_headers(&mut self, headers: &Headers) {
+        self.if_none_match = headers.get::<IfNoneMatch>().cloned();
+        self.range = headers.get::<Range>().cloned();
+    }
+}
@@ -0,0 +1,116 @@
+use futures::{self, Future, Stream};
+use hyper;
+use hyper::header::{ContentType, Headers, Location};
+use hyper::mime::Mime;
+use hyper::server::*;
+
+use mimes::*;
+use state::{AttachmentResult, State};
+use web::{Resource, ResponseFuture};
+use web::multipart;
+
+const MAX_UPLOAD_SIZE: usize = 32 * 1024 * 1024;
+
+fn bad_request(message: &'static str) -> Response {
+    Response::new()
+        .with_status(hyper::StatusCode::BadRequest)
+        .with_header(ContentType(TEXT_PLAIN.clone()))
+        .with_body(message)
+}
+
+pub struct AttachmentsResource {
+    state: State,
+    content_type: Option<Mime>,
+}
+
+impl AttachmentsResource {
+    pub fn new(state: State) -> Self {
+        Self { state, content_type: None }
+    }
+}
+
+impl Resource for AttachmentsResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Post]
+    }
+
+    fn post(self: Box<Self>, body: hyper::Body, identity: Option<String>) -> ResponseFuture {
+        let boundary = match self.content_type.as_ref().and_then(multipart::boundary) {
+            Some(x) => x,
+            None => return Box::new(futures::finished(bad_request("Expected multipart/form-data\n"))),
+        };
+
+        let state = self.state;
+
+        Box::new(body
+            .fold((Vec::new(), false), |(mut buf, too_large), chunk| -> Result<_, hyper::Error> {
+                if too_large || buf.len() + chunk.len() > MAX_UPLOAD_SIZE {
+                    Ok((buf, true))
+                } else {
+                    buf.extend_from_slice(&chunk);
+                    Ok((buf, false))
+                }
+            })
+            .map_err(Into::into)
+            .and_then(move |(body, too_large)| -> ResponseFuture {
+                if too_large {
+                    return Box::new(futures::finished(Response::new()
+                        .with_status(hyper::StatusCode::PayloadTooLarge)
+                        .with_header(ContentType(TEXT_PLAIN.clone()))
+                        .with_body("The file is too large\n")
+                    ));
+                }
+
+                let parts = match multipart::parse(&body, &boundary) {
+                    Ok(x) => x,
+                    Err(_) => return Box::new(futures::finished(bad_request("Malformed multipart/form-data\n"))),
+                };
+
+                let article_id = parts.iter()
+                    .find(|x| x.name == "article_id")
+                    .and_then(|x| String::from_utf8_lossy(x.data).parse::<i32>().ok());
+
+                let file = match parts.into_iter().find(|x| x.name == "file" && x.filename.is_some()) {
+                    Some(x) => x,
+                    None => return Box::new(futures::finished(bad_request("Missing file\n"))),
+                };
+
+                let filename = file.filename.unwrap_or_default();
+                let content_type = match file.content_type {
+                    Some(ref x) if x != "application/octet-stream" => x.clone(),
+                    _ => from_extension(&filename).to_owned(),
+                };
+
+                Box::new(state.create_attachment(filename, content_type, file.data.to_vec(), identity, article_id)
+                    .and_then(move |result| {
+                        let attachment = match result {
+                            AttachmentResult::Success(x) => x,
+                            AttachmentResult::NameTaken => return Ok(Response::new()
+                                .with_status(hyper::StatusCode::Conflict)
+                                .with_header(ContentType(TEXT_PLAIN.clone()))
+                                .with_body("A file with this name is attached to another article. Rename the file and try again.\n")
+                            ),
+                        };
+
+                        let location = match article_id {
+                            Some(article_id) => format!("_by_id/{}", article_id),
+                            None => attachment.link(),
+                        };
+
+                        Ok(Response::new()
+                            .with_status(hyper::StatusCode::SeeOther)
+                            .with_header(ContentType(TEXT_PLAIN.clone()))
+                            .with_header(Location::new(location))
+                            .with_body("See other")
+                        )
+                    })
+                )
+            })
+        )
+    }
+
+    fn hacky_inject_headers(&mut self, headers: &Headers) {
+        self.content_type = headers.get::<ContentType>().map(|x| x.0.clone());
+    }
+}
@@ -0,0 +1,147 @@
+use futures::{self, Future, Sink, Stream};
+use futures::future::{loop_fn, Loop};
+use futures::sync::mpsc::Sender;
+use hyper;
+use hyper::header::{CacheControl, CacheDirective, ContentType, Headers};
+use hyper::server::*;
+use serde_json;
+
+use live_changes::Signal;
+use mimes::*;
+use models;
+use schema::article_revisions;
+use state::State;
+use web::{Resource, ResponseFuture};
+
+use super::changes_resource::JsonChange;
+
+header! { (LastEventId, "Last-Event-ID") => [i32] }
+
+/// The number of changes to look up at a time when catching up
+const BATCH_SIZE: i32 = 100;
+
+type BodySender = Sender<Result<hyper::Chunk, hyper::Error>>;
+type SendFuture = Box<Future<Item=(i32, BodySender), Error=()> + Send>;
+type Step = Loop<(i32, BodySender), (i32, BodySender)>;
+
+/// New changes as server-sent events. The id of each event is the sequence
+/// number of the change, so a client reconnecting with `Last-Event-ID`
+/// gets the changes it missed.
+pub struct ChangeStreamResource {
+    state: State,
+    article_id: Option<i32>,
+    last_event_id: Option<i32>,
+}
+
+impl ChangeStreamResource {
+    pub fn new(state: State, article_id: Option<i32>) -> Self {
+        Self { state, article_id, last_event_id: None }
+    }
+}
+
+fn event(change: &models::ArticleRevisionStub) -> String {
+    format!("id: {}\nevent: change\ndata: {}\n\n",
+        change.sequence_number,
+        serde_json::to_string(&JsonChange::new(change)).expect("Should never fail"))
+}
+
+/// Send all the changes after the sequence number `after`. Resolves to the
+/// sequence number of the last change sent, and fails when the client has
+/// gone away.
+fn send_changes(state: State, article_id: Option<i32>, after: i32, sender: BodySender) -> SendFuture {
+    Box::new(loop_fn((after, sender), move |(after, sender)| {
+        state.query_article_revision_stubs(move |query| {
+            use diesel::prelude::*;
+
+            let query = query
+                .filter(article_revisions::sequence_number.gt(after))
+                .order(article_revisions::sequence_number.asc())
+                .limit(BATCH_SIZE as i64);
+
+            match article_id {
+                Some(x) => query.filter(article_revisions::article_id.eq(x)),
+                None => query,
+            }
+        })
+            .map_err(|err| eprintln!("Unable to read changes: {}", err))
+            .and_then(move |changes| -> Box<Future<Item=Step, Error=()> + Send> {
+                let last = match changes.last() {
+                    Some(x) => x.sequence_number,
+                    None => return Box::new(futures::finished(Loop::Break((after, sender)))),
+                };
+                let more = changes.len() as i32 == BATCH_SIZE;
+                let events = changes.iter().map(event).collect::<String>();
+
+                Box::new(sender.send(Ok(events.into()))
+                    .map_err(|_| ())
+                    .map(move |sender| match more {
+                        true => Loop::Continue((last, sender)),
+                        false => Loop::Break((last, sender)),
+                    }))
+            })
+    }))
+}
+
+impl Resource for ChangeStreamResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Head, Get]
+    }
+
+    fn hacky_inject_headers(&mut self, headers: &Headers) {
+        self.last_event_id = headers.get::<LastEventId>().map(|x| x.0);
+    }
+
+    fn head(&self) -> ResponseFuture {
+        Box::new(futures::finished(Response::new()
+            .with_status(hyper::StatusCode::Ok)
+            .with_header(ContentType(TEXT_EVENT_STREAM.clone()))
+            .with_header(CacheControl(vec![CacheDirective::NoCache]))
+        ))
+    }
+
+    fn get(self: Box<Self>) -> ResponseFuture {
+        let (sender, body) = hyper::Body::pair();
+
+        // Start listening before finding the latest change, so no change
+        // falls in between
+        let signals = self.state.listen_for_changes();
+
+        let start: Box<Future<Item=i32, Error=()> + Send> = match self.last_event_id {
+            Some(x) => Box::new(futures::finished(x)),
+            None => Box::new(self.state.query_article_revision_stubs(|query| {
+                use diesel::prelude::*;
+
+                query
+                    .order(article_revisions::sequence_number.desc())
+                    .limit(1)
+            })
+                .map(|x| x.first().map(|x| x.sequence_number).unwrap_or(0))
+                .map_err(|err| eprintln!("Unable to read changes: {}", err))),
+        };
+
+        let state = self.state.clone();
+        let article_id = self.article_id;
+
+        let stream = start
+            .and_then(move |after| {
+                // Catch up on the changes the client missed, if any
+                send_changes(state.clone(), article_id, after, sender)
+                    .and_then(move |start| signals.fold(start, move |(after, sender), signal| -> SendFuture {
+                        match signal {
+                            Signal::Changed =>
+                                send_changes(state.clone(), article_id, after, sender),
+                            Signal::KeepAlive =>
+                                Box::new(sender.send(Ok(":\n\n".into()))
+                                    .map(move |sender| (after, sender))
+                                    .map_err(|_| ())),
+                        }
+                    }))
+            })
+            .map(|_| ());
+
+        self.state.spawn(stream);
+
+        Box::new(self.head().map(move |head| head.with_body(body)))
+    }
+}
@@ -1,19 +1,28 @@
+use std::collections::BTreeMap;
+
+use chrono::{Local, TimeZone};
 use diesel;
 use futures::{self, Future};
 use futures::future::{done, finished};
+use futures_cpupool::CpuFuture;
 use hyper;
-use hyper::header::ContentType;
+use hyper::header::{Accept, ContentType, Location};
 use hyper::server::*;
+use serde_json;
 use serde_urlencoded;
 
+use build_config;
 use mimes::*;
-use schema::article_revisions;
+use models;
+use schema::{article_revisions, watches};
 use site::Layout;
 use state::State;
 use web::{Resource, ResponseFuture};
 
+use super::change_stream_resource::ChangeStreamResource;
 use super::diff_resource;
 use super::pagination::Pagination;
+use super::user_resource::user_link;
 use super::TemporaryRedirectResource;
 
 const DEFAULT_LIMIT: i32 = 30;
@@ -24,6 +33,7 @@ type BoxResource = Box<Resource + Sync + Send>;
 pub struct ChangesLookup {
     state: State,
     show_authors: bool,
+    base_url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -35,6 +45,10 @@ pub struct QueryParameters {
     author: Option<String>,
 
     limit: Option<i32>,
+
+    format: Option<String>,
+
+    group: Option<bool>,
 }
 
 impl QueryParameters {
@@ -61,6 +75,17 @@ impl QueryParameters {
         }
     }
 
+    pub fn format(self, format: Option<String>) -> Self {
+        Self { format, ..self }
+    }
+
+    pub fn group(self, group: bool) -> Self {
+        Self {
+            group: if group { Some(true) } else { None },
+            ..self
+        }
+    }
+
     pub fn into_link(self) -> String {
         let args = serde_urlencoded::to_string(self).expect("Serializing to String cannot fail");
         if args.len() > 0 {
@@ -71,10 +96,156 @@ impl QueryParameters {
     }
 }
 
+/// A change as represented in JSON, both in the list of changes and in
+/// the change stream
+#[derive(Serialize)]
+pub struct JsonChange<'a> {
+    sequence_number: i32,
+    article_id: i32,
+    revision: i32,
+    created: String,
+    author: Option<&'a str>,
+    slug: &'a str,
+    title: &'a str,
+    latest: bool,
+    link: String,
+    diff_link: Option<String>,
+}
+
+impl<'a> JsonChange<'a> {
+    pub fn new(x: &'a models::ArticleRevisionStub) -> JsonChange<'a> {
+        use chrono::{DateTime, Utc};
+
+        JsonChange {
+            sequence_number: x.sequence_number,
+            article_id: x.article_id,
+            revision: x.revision,
+            created: DateTime::<Utc>::from_utc(x.created, Utc).to_rfc3339(),
+            author: x.author.as_ref().map(|x| &**x),
+            slug: &x.slug,
+            title: &x.title,
+            latest: x.latest,
+            link: format!("_revisions/{}/{}", x.article_id, x.revision),
+            diff_link: diff_link(x),
+        }
+    }
+}
+
+fn diff_link(x: &models::ArticleRevisionStub) -> Option<String> {
+    if x.revision > 1 {
+        Some(format!("_diff/{}?{}",
+            x.article_id,
+            diff_resource::QueryParameters::new(
+                x.revision as u32 - 1,
+                x.revision as u32,
+            )
+        ))
+    } else {
+        None
+    }
+}
+
+struct GroupAuthor {
+    name: String,
+    link: Option<String>,
+}
+
+/// Consecutive changes to the same article on the same day
+struct ChangeGroup {
+    title: String,
+    link: String,
+    time: String,
+    edits: usize,
+    authors: Vec<GroupAuthor>,
+    size_change: String,
+    diff_link: Option<String>,
+}
+
+struct Day {
+    date: String,
+    groups: Vec<ChangeGroup>,
+}
+
+/// Group `changes`, which are ordered from newest to oldest, by local date
+/// and then by runs of consecutive revisions of the same article. Runs stop
+/// at gaps, so a group never covers revisions that are filtered out of
+/// `changes`. `sizes` has the body size of each change and of the revision
+/// before it.
+fn group_changes(changes: &[models::ArticleRevisionStub], sizes: &BTreeMap<(i32, i32), i64>) -> Vec<Day> {
+    let local = |x: &models::ArticleRevisionStub| Local.from_utc_datetime(&x.created);
+
+    let mut days: Vec<Day> = Vec::new();
+    let mut start = 0;
+
+    while start < changes.len() {
+        let newest = &changes[start];
+        let date = local(newest).date();
+
+        let mut end = start + 1;
+        while end < changes.len() &&
+            changes[end].article_id == newest.article_id &&
+            changes[end].revision == changes[end - 1].revision - 1 &&
+            local(&changes[end]).date() == date
+        {
+            end += 1;
+        }
+
+        let run = &changes[start..end];
+        let oldest = &run[run.len() - 1];
+
+        let mut authors: Vec<Option<&str>> = Vec::new();
+        for author in run.iter().map(|x| x.author.as_ref().map(|x| &x[..])) {
+            if !authors.contains(&author) {
+                authors.push(author);
+            }
+        }
+
+        let size = |revision| sizes.get(&(newest.article_id, revision)).cloned().unwrap_or(0);
+        let size_change = size(newest.revision) - size(oldest.revision - 1);
+
+        let group = ChangeGroup {
+            title: newest.title.clone(),
+            link: format!("_revisions/{}/{}", newest.article_id, newest.revision),
+            time: local(newest).format("%H:%M").to_string(),
+            edits: run.len(),
+            authors: authors.into_iter().map(|x| GroupAuthor {
+                name: x.unwrap_or("").to_owned(),
+                link: x.map(user_link),
+            }).collect(),
+            size_change: match size_change {
+                x if x > 0 => format!("+{} B", x),
+                x => format!("{} B", x),
+            },
+            diff_link: if oldest.revision > 1 {
+                Some(format!("_diff/{}?{}",
+                    newest.article_id,
+                    diff_resource::QueryParameters::new(
+                        oldest.revision as u32 - 1,
+                        newest.revision as u32,
+                    )
+                ))
+            } else {
+                None
+            },
+        };
+
+        let date = date.format("%A %Y-%m-%d").to_string();
+        if days.last().map(|x| x.date != date).unwrap_or(true) {
+            days.push(Day { date, groups: vec![] });
+        }
+        days.last_mut().unwrap().groups.push(group);
+
+        start = end;
+    }
+
+    days
+}
+
 fn apply_query_config<'a>(
     mut query: article_revisions::BoxedQuery<'a, diesel::sqlite::Sqlite>,
     article_id: Option<i32>,
     author: Option<String>,
+    watcher: Option<String>,
     limit: i32,
 )
     -> article_revisions::BoxedQuery<'a, diesel::sqlite::Sqlite>
@@ -89,22 +260,52 @@ fn apply_query_config<'a>(
         query = query.filter(article_revisions::author.eq(author));
     }
 
+    if let Some(watcher) = watcher {
+        query = query.filter(article_revisions::article_id.eq_any(
+            watches::table
+                .filter(watches::identity.eq(watcher))
+                .select(watches::article_id)
+        ));
+    }
+
     query.limit(limit as i64 + 1)
 }
 
 impl ChangesLookup {
-    pub fn new(state: State, show_authors: bool) -> ChangesLookup {
-        Self { state, show_authors }
+    pub fn new(state: State, show_authors: bool, base_url: Option<String>) -> ChangesLookup {
+        Self { state, show_authors, base_url }
     }
 
     pub fn lookup(&self, query: Option<&str>) -> Box<Future<Item=Option<BoxResource>, Error=::web::Error>> {
+        self.lookup_changes(query, false)
+    }
+
+    /// The changes to the articles watched by the identity of the request
+    pub fn lookup_watchlist(&self, query: Option<&str>) -> Box<Future<Item=Option<BoxResource>, Error=::web::Error>> {
+        self.lookup_changes(query, true)
+    }
+
+    /// The stream of new changes, optionally for a single article
+    pub fn lookup_stream(&self, query: Option<&str>) -> Result<Option<BoxResource>, ::web::Error> {
+        #[derive(Deserialize)]
+        struct StreamParameters {
+            article_id: Option<i32>,
+        }
+
+        let params: StreamParameters = serde_urlencoded::from_str(query.unwrap_or(""))?;
+
+        Ok(Some(Box::new(ChangeStreamResource::new(self.state.clone(), params.article_id)) as BoxResource))
+    }
+
+    fn lookup_changes(&self, query: Option<&str>, watchlist: bool) -> Box<Future<Item=Option<BoxResource>, Error=::web::Error>> {
         use super::pagination;
 
         let state = self.state.clone();
         let show_authors = self.show_authors;
+        let base_url = self.base_url.clone();
 
         Box::new(
-            done((|| {
+            done((|| -> Result<_, ::web::Error> {
                 let params: QueryParameters = serde_urlencoded::from_str(query.unwrap_or(""))?;
 
                 let pagination = pagination::from_fields(params.after, params.before)?;
@@ -115,16 +316,24 @@ impl ChangesLookup {
                     _ => Err("`limit` argument must be in range [1, 100]"),
                 }?;
 
-                Ok((pagination, params.article_id, params.author, limit))
+                let atom = match params.format.as_ref().map(|x| &**x) {
+                    None => Ok(false),
+                    Some("atom") => Ok(true),
+                    _ => Err("`format` argument must be `atom`"),
+                }?;
+
+                let group = params.group.unwrap_or(false);
+
+                Ok((pagination, params.article_id, params.author, limit, atom, group))
             })())
-            .and_then(move |(pagination, article_id, author, limit)| match pagination {
-                Pagination::After(x) => {
+            .and_then(move |(pagination, article_id, author, limit, atom, group)| match (pagination, watchlist) {
+                (Pagination::After(x), false) => {
                     let author2 = author.clone();
 
                     Box::new(state.query_article_revision_stubs(move |query| {
                         use diesel::prelude::*;
 
-                        apply_query_config(query, article_id, author2, limit)
+                        apply_query_config(query, article_id, author2, None, limit)
                             .filter(article_revisions::sequence_number.gt(x))
                             .order(article_revisions::sequence_number.asc())
                     }).and_then(move |mut data| {
@@ -136,13 +345,13 @@ impl ChangesLookup {
 
                         let args =
                             QueryParameters {
-                                after: None,
-                                before: None,
                                 article_id,
                                 author,
-                                limit: None,
+                                ..QueryParameters::default()
                             }
-                            .limit(limit);
+                            .limit(limit)
+                            .format(if atom { Some("atom".to_owned()) } else { None })
+                            .group(group);
 
                         Ok(Some(match extra_element {
                             Some(x) => Box::new(TemporaryRedirectResource::new(
@@ -156,8 +365,22 @@ impl ChangesLookup {
                         }))
                     })) as Box<Future<Item=Option<BoxResource>, Error=::web::Error>>
                 },
-                Pagination::Before(x) => Box::new(finished(Some(Box::new(ChangesResource::new(state, show_authors, Some(x), article_id, author, limit)) as BoxResource))),
-                Pagination::None => Box::new(finished(Some(Box::new(ChangesResource::new(state, show_authors, None, article_id, author, limit)) as BoxResource))),
+                (pagination, _) => {
+                    let (after, before) = match pagination {
+                        Pagination::After(x) => (Some(x), None),
+                        Pagination::Before(x) => (None, Some(x)),
+                        Pagination::None => (None, None),
+                    };
+
+                    Box::new(finished(Some(Box::new(
+                        ChangesResource::new(state, show_authors, before, article_id, author, limit)
+                            .after(after)
+                            .watchlist(watchlist)
+                            .group(group)
+                            .atom(atom)
+                            .base_url(base_url)
+                    ) as BoxResource)))
+                },
             })
         )
     }
@@ -170,14 +393,84 @@ pub struct ChangesResource {
     article_id: Option<i32>,
     author: Option<String>,
     limit: i32,
+
+    /// Set for the `after` parameter of the watchlist, which redirects to
+    /// the equivalent page given by `before`. The watchlist depends on the
+    /// identity of the request, so this can't be resolved during lookup
+    after: Option<i32>,
+
+    /// Show only changes to the articles watched by `identity`
+    watchlist: bool,
+    identity: Option<String>,
+
+    /// Collapse consecutive changes to the same article on the same day
+    /// in the HTML view
+    group: bool,
+
+    response_type: ResponseType,
+
+    /// The configured public URL of the wiki, ending with a slash, for
+    /// absolute links in the feed. There is no feed without it
+    base_url: Option<String>,
+}
+
+pub enum ResponseType {
+    Html,
+    Json,
+
+    /// Chosen by Accept or by the `format` parameter, which is sticky so
+    /// the links in the feed stay feeds
+    Atom { format_parameter: bool },
 }
 
 impl ChangesResource {
     pub fn new(state: State, show_authors: bool, before: Option<i32>, article_id: Option<i32>, author: Option<String>, limit: i32) -> Self {
-        Self { state, show_authors, before, article_id, author, limit }
+        Self {
+            state,
+            show_authors,
+            before,
+            article_id,
+            author,
+            limit,
+            after: None,
+            watchlist: false,
+            identity: None,
+            group: false,
+            response_type: ResponseType::Html,
+            base_url: None,
+        }
+    }
+
+    pub fn after(self, after: Option<i32>) -> Self {
+        Self { after, ..self }
+    }
+
+    pub fn watchlist(self, watchlist: bool) -> Self {
+        Self { watchlist, ..self }
+    }
+
+    pub fn group(self, group: bool) -> Self {
+        Self { group, ..self }
+    }
+
+    pub fn atom(self, atom: bool) -> Self {
+        if atom {
+            Self { response_type: ResponseType::Atom { format_parameter: true }, ..self }
+        } else {
+            self
+        }
+    }
+
+    pub fn base_url(self, base_url: Option<String>) -> Self {
+        Self { base_url, ..self }
     }
 
     fn query_args(&self) -> QueryParameters {
+        let format = match self.response_type {
+            ResponseType::Atom { format_parameter: true } => Some("atom".to_owned()),
+            _ => None,
+        };
+
         QueryParameters {
             after: None,
             before: self.before,
@@ -186,6 +479,185 @@ impl ChangesResource {
             ..QueryParameters::default()
         }
         .limit(self.limit)
+        .format(format)
+        .group(self.group)
+    }
+
+    fn path(&self) -> &'static str {
+        if self.watchlist { "_watchlist" } else { "_changes" }
+    }
+
+    /// Like `QueryParameters::into_link`, but for the path of this resource
+    fn link(&self, args: QueryParameters) -> String {
+        let link = args.into_link();
+        if link.starts_with('?') {
+            link
+        } else {
+            self.path().to_owned()
+        }
+    }
+
+    fn watcher(&self) -> Option<String> {
+        if self.watchlist { self.identity.clone() } else { None }
+    }
+
+    fn query(&self) -> CpuFuture<Vec<models::ArticleRevisionStub>, ::state::Error> {
+        let (before, article_id, author, watcher, limit) =
+            (self.before.clone(), self.article_id.clone(), self.author.clone(), self.watcher(), self.limit);
+
+        self.state.query_article_revision_stubs(move |query| {
+            use diesel::prelude::*;
+
+            let query = apply_query_config(query, article_id, author, watcher, limit)
+                .order(article_revisions::sequence_number.desc());
+
+            match before {
+                Some(x) => query.filter(article_revisions::sequence_number.lt(x)),
+                None => query,
+            }
+        })
+    }
+
+    fn absolute_link(&self, base_url: &str, args: QueryParameters) -> String {
+        let link = self.link(args);
+        if link.starts_with('?') {
+            format!("{}{}{}", base_url, self.path(), link)
+        } else {
+            format!("{}{}", base_url, link)
+        }
+    }
+
+    /// The location of the page given by `before` that starts right after
+    /// `after`, or the first page if there are not enough changes after it
+    fn after_location(&self, after: i32) -> CpuFuture<String, ::state::Error> {
+        let (article_id, author, watcher, limit) =
+            (self.article_id.clone(), self.author.clone(), self.watcher(), self.limit);
+        let args = self.query_args();
+        let first_page = self.link(self.query_args());
+
+        self.state.query_article_revision_stubs(move |query| {
+            use diesel::prelude::*;
+
+            apply_query_config(query, article_id, author, watcher, limit)
+                .filter(article_revisions::sequence_number.gt(after))
+                .order(article_revisions::sequence_number.asc())
+        }).map(move |mut data| {
+            let extra_element = if data.len() > limit as usize {
+                data.pop()
+            } else {
+                None
+            };
+
+            match extra_element {
+                Some(x) => args.pagination(Pagination::Before(x.sequence_number)).into_link(),
+                None => first_page,
+            }
+        })
+    }
+
+    fn get_atom(self: Box<Self>) -> ResponseFuture {
+        use chrono::{DateTime, Utc};
+
+        struct Entry {
+            id: String,
+            title: String,
+            updated: String,
+            author: String,
+            link: String,
+            summary: String,
+        }
+
+        #[derive(BartDisplay)]
+        #[template="templates/changes.xml"]
+        struct Template<'a> {
+            id: String,
+            title: String,
+            updated: String,
+            self_link: String,
+            alternate_link: String,
+            entries: &'a [Entry],
+        }
+
+        impl<'a> Template<'a> {
+            fn project_name(&self) -> &str { build_config::PROJECT_NAME }
+        }
+
+        // Guessing the base URL from request headers would let clients
+        // inject links into the feed
+        let base_url = match self.base_url {
+            Some(ref x) => x.clone(),
+            None => return Box::new(futures::finished(Response::new()
+                .with_status(hyper::StatusCode::NotFound)
+                .with_header(ContentType(TEXT_PLAIN.clone()))
+                .with_body("The Atom feed is only available when the wiki is started with --base-url\n")
+            )),
+        };
+
+        let state = self.state.clone();
+        let limit = self.limit as usize;
+        let data = self.query()
+            .and_then(move |mut data| {
+                data.truncate(limit);
+                state.get_revisions_with_previous(
+                    data.into_iter().map(|x| (x.article_id, x.revision)).collect()
+                )
+            });
+
+        let head = self.head();
+
+        Box::new(data.join(head)
+            .and_then(move |(data, head)| {
+                let rfc3339 = |x| DateTime::<Utc>::from_utc(x, Utc).to_rfc3339();
+
+                let entries = data.iter().map(|&(ref x, ref previous)| {
+                    let link = match previous {
+                        &Some(ref previous) => format!("_diff/{}?{}",
+                            x.article_id,
+                            diff_resource::QueryParameters::new(previous.revision as u32, x.revision as u32)
+                        ),
+                        &None => format!("_revisions/{}/{}", x.article_id, x.revision),
+                    };
+
+                    Entry {
+                        id: format!("{}_revisions/{}/{}", base_url, x.article_id, x.revision),
+                        title: x.title.clone(),
+                        updated: rfc3339(x.created),
+                        author: x.author.clone().unwrap_or_else(|| "Anonymous".to_owned()),
+                        link: format!("{}{}", base_url, link),
+                        summary: diff_resource::summary_html(
+                            previous.as_ref().map(|x| &x.body[..]).unwrap_or(""),
+                            &x.body
+                        ),
+                    }
+                }).collect::<Vec<_>>();
+
+                let title = match self.author {
+                    Some(ref author) => format!("Changes by {}", author),
+                    None => "Changes".to_owned(),
+                };
+
+                let feed_args = self.query_args()
+                    .pagination(Pagination::None)
+                    .format(Some("atom".to_owned()))
+                    .group(false);
+
+                let title = match self.watchlist {
+                    true => format!("Watchlist of {}", self.identity.as_ref().map(|x| &x[..]).unwrap_or("")),
+                    false => title,
+                };
+
+                Ok(head
+                    .with_body(Template {
+                        id: self.absolute_link(&base_url, feed_args),
+                        title,
+                        updated: data.first()
+                            .map(|x| rfc3339(x.0.created))
+                            .unwrap_or_else(|| Utc::now().to_rfc3339()),
+                        self_link: self.absolute_link(&base_url, self.query_args().format(Some("atom".to_owned()))),
+                        alternate_link: self.absolute_link(&base_url, self.query_args().format(None)),
+                        entries: &entries,
+                    }.to_string()))
+            }))
     }
 }
 
@@ -195,15 +667,85 @@ impl Resource for ChangesResource {
         vec![Options, Head, Get]
     }
 
+    // This is a complete hack, searching for a reasonable design:
+    fn hacky_inject_accept_header(&mut self, accept: Accept) {
+        use hyper::header::QualityItem;
+        use hyper::mime;
+
+        if let ResponseType::Atom { format_parameter: true } = self.response_type {
+            return;
+        }
+
+        self.response_type = match accept.first() {
+            Some(&QualityItem { item: ref mime, .. })
+                if mime.type_() == APPLICATION_ATOM_XML.type_() && mime.subtype() == APPLICATION_ATOM_XML.subtype()
+                => ResponseType::Atom { format_parameter: false },
+            Some(&QualityItem { item: ref mime, .. })
+                if mime.type_() == mime::APPLICATION && mime.subtype() == mime::JSON
+                => ResponseType::Json,
+            _ => ResponseType::Html,
+        };
+    }
+
+    fn hacky_inject_identity(&mut self, identity: Option<String>) {
+        self.identity = identity;
+    }
+
     fn head(&self) -> ResponseFuture {
+        if let Some(after) = self.after {
+            return Box::new(self.after_location(after)
+                .map(|location| Response::new()
+                    .with_status(hyper::StatusCode::TemporaryRedirect)
+                    .with_header(Location::new(location))
+                ));
+        }
+
+        if self.watchlist && self.identity.is_none() {
+            return Box::new(futures::finished(Response::new()
+                .with_status(hyper::StatusCode::Forbidden)
+                .with_header(ContentType(TEXT_HTML.clone()))
+            ));
+        }
+
+        let content_type = match self.response_type {
+            ResponseType::Html => ContentType(TEXT_HTML.clone()),
+            ResponseType::Json => ContentType(APPLICATION_JSON.clone()),
+            ResponseType::Atom { .. } => ContentType(APPLICATION_ATOM_XML.clone()),
+        };
+
         Box::new(futures::finished(Response::new()
             .with_status(hyper::StatusCode::Ok)
-            .with_header(ContentType(TEXT_HTML.clone()))
+            .with_header(content_type)
         ))
     }
 
     fn get(self: Box<Self>) -> ResponseFuture {
-        use chrono::{TimeZone, Local};
+        if self.after.is_some() {
+            return Box::new(self.head()
+                .map(|head| {
+                    let body = format!("Moved to {}", head.headers().get::<Location>().expect("Set by head"));
+                    head.with_body(body)
+                }));
+        }
+
+        if self.watchlist && self.identity.is_none() {
+            #[derive(BartDisplay)]
+            #[template_string = "<div class=\"container\">\n<header>\n<h1>Watchlist</h1>\n</header>\n<article>\n<p>The watchlist shows changes to the articles you watch, but this wiki does not know who you are. It must run behind a reverse proxy that authenticates users, with <code>--trust-identity</code>.</p>\n</article>\n</div>\n"]
+            struct Template;
+
+            return Box::new(self.head()
+                .map(|head| head
+                    .with_body(Layout {
+                        base: None,
+                        title: "Watchlist",
+                        body: &Template,
+                    }.to_string())
+                ));
+        }
+
+        if let ResponseType::Atom { .. } = self.response_type {
+            return self.get_atom();
+        }
 
         struct Row<'a> {
             resource: &'a ChangesResource,
@@ -229,6 +771,10 @@ impl Resource for ChangesResource {
                     .author(self.author.clone())
                     .into_link()
             }
+
+            fn user_link(&self) -> String {
+                self.author.as_ref().map(|x| user_link(x)).unwrap_or_default()
+            }
         }
 
         struct NavLinks {
@@ -236,6 +782,15 @@ impl Resource for ChangesResource {
             end: String,
         }
 
+        #[derive(Serialize)]
+        struct JsonResponse<'a> {
+            changes: &'a [JsonChange<'a>],
+            prev: Option<String>,
+            first: Option<String>,
+            next: Option<String>,
+            last: Option<String>,
+        }
+
         #[derive(BartDisplay)]
         #[template="templates/changes.html"]
         struct Template<'a> {
@@ -245,13 +800,20 @@ impl Resource for ChangesResource {
             newer: Option<NavLinks>,
             older: Option<NavLinks>,
             changes: &'a [Row<'a>],
+
+            watchlist: bool,
+            watched: &'a [models::WatchedArticle],
+
+            group: bool,
+            days: &'a [Day],
         }
 
         impl<'a> Template<'a> {
             fn subject_clause(&self) -> String {
-                match self.resource.article_id {
-                    Some(x) => format!(" <a href=\"_by_id/{}\">this article</a>", x),
-                    None => format!(" the wiki"),
+                match (self.resource.article_id, self.watchlist) {
+                    (Some(x), _) => format!(" <a href=\"_by_id/{}\">this article</a>", x),
+                    (None, true) => format!(" the articles you watch"),
+                    (None, false) => format!(" the wiki"),
                 }
             }
 
@@ -261,39 +823,63 @@ impl Resource for ChangesResource {
 
             fn all_articles_link(&self) -> Option<String> {
                 self.resource.article_id.map(|_| {
-                    self.resource.query_args()
+                    self.resource.link(self.resource.query_args()
                         .article_id(None)
+                    )
+                })
+            }
+
+            fn feed_link(&self) -> Option<String> {
+                self.resource.base_url.as_ref().map(|_| {
+                    self.resource.query_args()
+                        .pagination(Pagination::None)
+                        .format(Some("atom".to_owned()))
+                        .group(false)
                         .into_link()
                 })
             }
 
+            fn group_link(&self) -> String {
+                self.resource.link(self.resource.query_args()
+                    .group(!self.resource.group)
+                )
+            }
+
             fn all_authors_link(&self) -> Option<String> {
                 self.resource.author.as_ref().map(|_| {
-                    self.resource.query_args()
+                    self.resource.link(self.resource.query_args()
                         .author(None)
-                        .into_link()
+                    )
                 })
             }
         }
 
-        let (before, article_id, author, limit) =
-            (self.before.clone(), self.article_id.clone(), self.author.clone(), self.limit);
-        let data = self.state.query_article_revision_stubs(move |query| {
-            use diesel::prelude::*;
+        // The grouped view needs the sizes of the changed revisions and of
+        // the ones before them
+        let state = self.state.clone();
+        let group = self.group;
+        let data = self.query()
+            .and_then(move |data| -> Box<Future<Item=(Vec<models::ArticleRevisionStub>, BTreeMap<(i32, i32), i64>), Error=::state::Error>> {
+                if !group {
+                    return Box::new(futures::finished((data, BTreeMap::new())));
+                }
 
-            let query = apply_query_config(query, article_id, author, limit)
-                .order(article_revisions::sequence_number.desc());
+                let revisions = data.iter()
+                    .flat_map(|x| vec![(x.article_id, x.revision), (x.article_id, x.revision - 1)])
+                    .collect();
 
-            match before {
-                Some(x) => query.filter(article_revisions::sequence_number.lt(x)),
-                None => query,
-            }
-        });
+                Box::new(state.get_revision_sizes(revisions)
+                    .map(move |sizes| (data, sizes)))
+            });
+        let watched: Box<Future<Item=Vec<models::WatchedArticle>, Error=::state::Error>> = match self.watcher() {
+            Some(identity) => Box::new(self.state.get_watched_articles(identity)),
+            None => Box::new(futures::finished(vec![])),
+        };
 
         let head = self.head();
 
-        Box::new(data.join(head)
-            .and_then(move |(mut data, head)| {
+        Box::new(data.join3(watched, head)
+            .and_then(move |((mut data, sizes), watched, head)| {
                 use std::iter::Iterator;
 
                 let extra_element = if data.len() > self.limit as usize {
@@ -306,7 +892,7 @@ impl Resource for ChangesResource {
                     Some(x) => (
                         Some(NavLinks {
                             more: self.query_args().pagination(Pagination::After(x-1)).into_link(),
-                            end: self.query_args().pagination(Pagination::None).into_link(),
+                            end: self.link(self.query_args().pagination(Pagination::None)),
                         }),
                         extra_element.map(|_| NavLinks {
                             more: self.query_args()
@@ -326,7 +912,29 @@ impl Resource for ChangesResource {
                     ),
                 };
 
+                if let ResponseType::Json = self.response_type {
+                    // The navigation links are relative to the path of this
+                    // resource, which is less convenient outside of HTML
+                    let absolute = |x: &String| if x.starts_with('?') { format!("{}{}", self.path(), x) } else { x.clone() };
+
+                    return Ok(head
+                        .with_body(serde_json::to_string(&JsonResponse {
+                            changes: &data.iter().map(JsonChange::new).collect::<Vec<_>>(),
+                            prev: newer.as_ref().map(|x| absolute(&x.more)),
+                            first: newer.as_ref().map(|x| absolute(&x.end)),
+                            next: older.as_ref().map(|x| absolute(&x.more)),
+                            last: older.as_ref().map(|x| absolute(&x.end)),
+                        }).expect("Should never fail")));
+                }
+
+                let days = match self.group {
+                    true => group_changes(&data, &sizes),
+                    false => vec![],
+                };
+
                 let changes = &data.into_iter().map(|x| {
+                    let diff_link = diff_link(&x);
+
                     Row {
                         resource: &self,
                         sequence_number: x.sequence_number,
@@ -337,33 +945,93 @@ impl Resource for ChangesResource {
                         _slug: x.slug,
                         title: x.title,
                         _latest: x.latest,
-                        diff_link:
-                            if x.revision > 1 {
-                                Some(format!("_diff/{}?{}",
-                                    x.article_id,
-                                    diff_resource::QueryParameters::new(
-                                        x.revision as u32 - 1,
-                                        x.revision as u32,
-                                    )
-                                ))
-                            } else {
-                                None
-                            },
+                        diff_link,
                     }
                 }).collect::<Vec<_>>();
 
                 Ok(head
                     .with_body(Layout {
                         base: None, // Hmm, should perhaps accept `base` as argument
-                        title: "Changes",
+                        title: if self.watchlist { "Watchlist" } else { "Changes" },
                         body: &Template {
                             resource: &self,
                             show_authors: self.show_authors,
                             newer,
                             older,
-                            changes
+                            changes,
+                            watchlist: self.watchlist,
+                            watched: &watched,
+                            group: self.group,
+                            days: &days,
                         },
                     }.to_string()))
             }))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn change(article_id: i32, revision: i32, author: &str) -> models::ArticleRevisionStub {
+        models::ArticleRevisionStub {
+            sequence_number: revision,
+            article_id,
+            revision,
+            created: Local.ymd(2017, 12, 18).and_hms(12, revision as u32, 0).naive_utc(),
+            slug: "slug".into(),
+            title: "Title".into(),
+            latest: false,
+            author: Some(author.into()),
+        }
+    }
+
+    fn summary(days: &[Day]) -> Vec<(usize, Vec<&str>, Option<&str>)> {
+        days.iter()
+            .flat_map(|day| day.groups.iter())
+            .map(|x| (
+                x.edits,
+                x.authors.iter().map(|x| &x.name[..]).collect(),
+                x.diff_link.as_ref().map(|x| &x[..]),
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn group_changes_merges_consecutive_revisions() {
+        let changes = vec![change(1, 5, "alice"), change(1, 4, "bob"), change(1, 3, "alice"), change(2, 1, "bob")];
+        let days = group_changes(&changes, &BTreeMap::new());
+
+        assert_eq!(1, days.len());
+        assert_eq!(
+            vec![
+                (3, vec!["alice", "bob"], Some("_diff/1?from=2&to=5")),
+                (1, vec!["bob"], None),
+            ],
+            summary(&days)
+        );
+    }
+
+    #[test]
+    fn group_changes_stops_at_gaps() {
+        let changes = vec![change(1, 5, "alice"), change(1, 3, "alice")];
+        let days = group_changes(&changes, &BTreeMap::new());
+
+        assert_eq!(
+            vec![
+                (1, vec!["alice"], Some("_diff/1?from=4&to=5")),
+                (1, vec!["alice"], Some("_diff/1?from=2&to=3")),
+            ],
+            summary(&days)
+        );
+    }
+
+    #[test]
+    fn group_changes_size_change() {
+        let changes = vec![change(1, 3, "alice"), change(1, 2, "alice")];
+        let sizes = vec![((1, 1), 10), ((1, 2), 15), ((1, 3), 12)].into_iter().collect();
+        let days = group_changes(&changes, &sizes);
+
+        assert_eq!("+2 B", days[0].groups[0].size_change);
+    }
+}
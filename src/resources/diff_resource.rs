@@ -19,6 +19,45 @@ use super::pagination::Pagination;
 
 type BoxResource = Box<Resource + Sync + Send>;
 
+const SUMMARY_MAX_LINES: usize = 20;
+
+/// A short HTML summary of a change to the body of an article, with the
+/// added and removed lines, for showing outside of the wiki such as in feeds
+pub fn summary_html(from: &str, to: &str) -> String {
+    use rendering::escape_html;
+
+    let changes = diff::lines(from, to)
+        .into_iter()
+        .filter_map(|x| match x {
+            diff::Result::Left(x) => Some(("del", "-", x)),
+            diff::Result::Both(..) => None,
+            diff::Result::Right(x) => Some(("ins", "+", x)),
+        })
+        .collect::<Vec<_>>();
+
+    let added = changes.iter().filter(|x| x.0 == "ins").count();
+    let removed = changes.len() - added;
+
+    let mut html = format!(
+        "<p>{} line{} added, {} line{} removed</p>\n",
+        added, if added == 1 { "" } else { "s" },
+        removed, if removed == 1 { "" } else { "s" }
+    );
+
+    if !changes.is_empty() {
+        html.push_str("<pre>");
+        for &(tag, sign, line) in changes.iter().take(SUMMARY_MAX_LINES) {
+            html.push_str(&format!("<{}>{} {}</{}>\n", tag, sign, escape_html(line), tag));
+        }
+        if changes.len() > SUMMARY_MAX_LINES {
+            html.push_str("\u{2026}\n");
+        }
+        html.push_str("</pre>\n");
+    }
+
+    html
+}
+
 #[derive(Clone)]
 pub struct DiffLookup {
     state: State,
@@ -1,23 +1,38 @@
 pub mod pagination;
 
 mod about_resource;
+mod attachment_resource;
+mod attachments_resource;
 mod article_revision_resource;
 mod article_resource;
+mod change_stream_resource;
 mod changes_resource;
 mod diff_resource;
 mod html_resource;
 mod new_article_resource;
+mod opensearch_resource;
 mod search_resource;
 mod sitemap_resource;
+mod stats_resource;
 mod temporary_redirect_resource;
+mod titles_resource;
+mod user_resource;
+mod watch_resource;
 
 pub use self::about_resource::AboutResource;
+pub use self::attachment_resource::AttachmentResource;
+pub use self::attachments_resource::AttachmentsResource;
 pub use self::article_revision_resource::ArticleRevisionResource;
 pub use self::article_resource::ArticleResource;
 pub use self::changes_resource::{ChangesLookup, ChangesResource};
 pub use self::diff_resource::{DiffLookup, DiffResource};
 pub use self::html_resource::HtmlResource;
 pub use self::new_article_resource::NewArticleResource;
+pub use self::opensearch_resource::OpenSearchResource;
 pub use self::search_resource::SearchLookup;
 pub use self::sitemap_resource::SitemapResource;
+pub use self::stats_resource::StatsResource;
 pub use self::temporary_redirect_resource::TemporaryRedirectResource;
+pub use self::titles_resource::TitlesLookup;
+pub use self::user_resource::{UserLookup, UserResource};
+pub use self::watch_resource::WatchResource;
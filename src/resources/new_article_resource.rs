@@ -7,19 +7,33 @@ use serde_urlencoded;
 
 use assets::ScriptJs;
 use mimes::*;
-use rendering::render_markdown;
+use models;
 use site::Layout;
 use state::State;
 use web::{Resource, ResponseFuture};
 
+use super::article_resource::Watch;
+
 const NEW: &str = "NEW";
 
-const EMPTY_ARTICLE_MESSAGE: &str = "
+const MAX_SUGGESTIONS: usize = 5;
+
+const NOT_FOUND_MESSAGE: &str = "
 <p>Not found</p>
+";
+
+const EMPTY_ARTICLE_MESSAGE: &str = "
 <p>There's no article here yet. You can create one by clicking the
 edit-link below and saving a new article.</p>
 ";
 
+/// Links to existing articles that the user may have meant
+#[derive(BartDisplay)]
+#[template="templates/did_you_mean.html"]
+pub struct DidYouMean<'a> {
+    pub suggestions: &'a [models::ArticleTitle],
+}
+
 fn title_from_slug(slug: &str) -> String {
     ::titlecase::titlecase(&slug.replace('-', " "))
 }
@@ -27,6 +41,8 @@ fn title_from_slug(slug: &str) -> String {
 pub struct NewArticleResource {
     state: State,
     slug: Option<String>,
+    /// Suggested title, instead of one derived from `slug`
+    title: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -37,8 +53,8 @@ struct CreateArticle {
 }
 
 impl NewArticleResource {
-    pub fn new(state: State, slug: Option<String>) -> Self {
-        Self { state, slug }
+    pub fn new(state: State, slug: Option<String>, title: Option<String>) -> Self {
+        Self { state, slug, title }
     }
 }
 
@@ -59,14 +75,18 @@ impl Resource for NewArticleResource {
         #[derive(BartDisplay)]
         #[template="templates/article.html"]
         struct Template<'a> {
+            article_id: Option<i32>,
             revision: &'a str,
             last_updated: Option<&'a str>,
+            attachments: Vec<models::AttachmentStub>,
 
             edit: bool,
             cancel_url: Option<&'a str>,
             title: &'a str,
             raw: &'a str,
             rendered: &'a str,
+
+            watch: Option<Watch>,
         }
         impl<'a> Template<'a> {
             fn script_js_checksum(&self) -> &'static str {
@@ -74,18 +94,35 @@ impl Resource for NewArticleResource {
             }
         }
 
-        let title = self.slug.as_ref()
-            .map_or("".to_owned(), |x| title_from_slug(x));
+        let title = match (self.title.as_ref(), self.slug.as_ref()) {
+            (Some(title), _) => title.clone(),
+            (None, Some(slug)) => title_from_slug(slug),
+            (None, None) => "".to_owned(),
+        };
+
+        let suggestions: Box<Future<Item=_, Error=_>> = match self.slug {
+            Some(ref slug) => Box::new(self.state.suggest_titles(slug.clone(), MAX_SUGGESTIONS)),
+            None => Box::new(futures::finished(vec![])),
+        };
+
+        Box::new(suggestions.join(self.head())
+            .and_then(move |(suggestions, head)| {
+                let rendered = format!(
+                    "{}{}{}",
+                    NOT_FOUND_MESSAGE,
+                    DidYouMean { suggestions: &suggestions },
+                    EMPTY_ARTICLE_MESSAGE
+                );
 
-        Box::new(self.head()
-            .and_then(move |head| {
                 Ok(head
                     .with_body(Layout {
                         base: None, // Hmm, should perhaps accept `base` as argument
                         title: &title,
                         body: &Template {
+                            article_id: None,
                             revision: NEW,
                             last_updated: None,
+                            attachments: vec![],
 
                             // Implicitly start in edit-mode when no slug is given. This
                             // currently directly corresponds to the /_new endpoint
@@ -94,7 +131,9 @@ impl Resource for NewArticleResource {
                             cancel_url: self.slug.as_ref().map(|x| &**x),
                             title: &title,
                             raw: "",
-                            rendered: EMPTY_ARTICLE_MESSAGE,
+                            rendered: &rendered,
+
+                            watch: None,
                         },
                     }.to_string()))
             }))
@@ -125,6 +164,8 @@ impl Resource for NewArticleResource {
             last_updated: &'a str,
         }
 
+        let state = self.state.clone();
+
         Box::new(body
             .concat2()
             .map_err(Into::into)
@@ -138,7 +179,11 @@ impl Resource for NewArticleResource {
                 }
                 self.state.create_article(self.slug.clone(), arg.title, arg.body, identity)
             })
-            .and_then(|updated| {
+            .and_then(move |updated| {
+                state.render_article(updated.article_id, updated.revision, updated.body.clone())
+                    .map(move |rendered| (updated, rendered))
+            })
+            .and_then(|(updated, rendered)| {
                 futures::finished(Response::new()
                     .with_status(hyper::StatusCode::Ok)
                     .with_header(ContentType(APPLICATION_JSON.clone()))
@@ -150,7 +195,7 @@ impl Resource for NewArticleResource {
                         body: &updated.body,
                         rendered: &Template {
                             title: &updated.title,
-                            rendered: render_markdown(&updated.body),
+                            rendered,
                         }.to_string(),
                         last_updated: &super::article_resource::last_updated(
                             updated.article_id,
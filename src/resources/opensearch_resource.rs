@@ -0,0 +1,70 @@
+use futures::{self, Future};
+use hyper;
+use hyper::header::{ContentType, Headers};
+use hyper::server::*;
+
+use build_config;
+use mimes::*;
+use site;
+use web::{Resource, ResponseFuture};
+
+/// OpenSearch description, letting browsers add the wiki as a search engine.
+/// The URLs must be absolute, so they are made from the configured base URL
+/// or else the Host header of the request.
+pub struct OpenSearchResource {
+    base_url: Option<String>,
+}
+
+impl OpenSearchResource {
+    pub fn new(base_url: Option<String>) -> Self {
+        Self { base_url }
+    }
+}
+
+impl Resource for OpenSearchResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Head, Get]
+    }
+
+    fn head(&self) -> ResponseFuture {
+        Box::new(futures::finished(Response::new()
+            .with_status(hyper::StatusCode::Ok)
+            .with_header(ContentType(APPLICATION_OPENSEARCHDESCRIPTION_XML.clone()))
+        ))
+    }
+
+    fn get(self: Box<Self>) -> ResponseFuture {
+        #[derive(BartDisplay)]
+        #[template="templates/opensearch.xml"]
+        struct Template<'a> {
+            base_url: &'a str,
+        }
+
+        impl<'a> Template<'a> {
+            fn project_name(&self) -> &str { build_config::PROJECT_NAME }
+        }
+
+        let base_url = match self.base_url {
+            Some(ref x) => x.clone(),
+            None => return Box::new(futures::finished(Response::new()
+                .with_status(hyper::StatusCode::BadRequest)
+                .with_body("Missing Host header")
+            )),
+        };
+
+        Box::new(self.head()
+            .and_then(move |head| {
+                Ok(head
+                    .with_body(Template {
+                        base_url: &base_url,
+                    }.to_string()))
+            }))
+    }
+
+    fn hacky_inject_headers(&mut self, headers: &Headers) {
+        if self.base_url.is_none() {
+            self.base_url = site::base_url_from_headers(headers);
+        }
+    }
+}
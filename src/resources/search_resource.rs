@@ -1,3 +1,4 @@
+use chrono::NaiveDate;
 use futures::{self, Future};
 use hyper;
 use hyper::header::{Accept, ContentType};
@@ -6,13 +7,18 @@ use serde_json;
 use serde_urlencoded;
 
 use mimes::*;
-use models::SearchResult;
+use models::{ArticleTitle, FacetCount, RevisionSearchResult, SearchFacets, SearchFrontPage, SearchResult};
+use search_query;
 use site::Layout;
 use state::State;
 use web::{Resource, ResponseFuture};
 
+use super::new_article_resource::DidYouMean;
+
 const DEFAULT_LIMIT: u32 = 10;
 const DEFAULT_SNIPPET_SIZE: u32 = 8;
+const MAX_SUGGESTIONS: usize = 5;
+const DATE_FORMAT: &str = "%Y-%m-%d";
 
 type BoxResource = Box<Resource + Sync + Send>;
 
@@ -22,6 +28,12 @@ pub struct QueryParameters {
     offset: Option<u32>,
     limit: Option<u32>,
     snippet_size: Option<u32>,
+    history: Option<u8>,
+    format: Option<String>,
+    author: Option<String>,
+    modified_after: Option<String>,
+    modified_before: Option<String>,
+    namespace: Option<String>,
 }
 
 impl QueryParameters {
@@ -46,6 +58,43 @@ impl QueryParameters {
         }
     }
 
+    pub fn history(self, history: bool) -> Self {
+        Self {
+            history: if history { Some(1) } else { None },
+            ..self
+        }
+    }
+
+    pub fn filters(self, filters: &search_query::Filters) -> Self {
+        Self {
+            author: filters.author.clone(),
+            modified_after: filters.modified_after.map(|x| x.format(DATE_FORMAT).to_string()),
+            modified_before: filters.modified_before.map(|x| x.format(DATE_FORMAT).to_string()),
+            namespace: filters.namespace.clone(),
+            ..self
+        }
+    }
+
+    fn parse_filters(&self) -> Result<search_query::Filters, ::web::Error> {
+        fn non_empty(x: &Option<String>) -> Option<String> {
+            x.as_ref().map(|x| x.trim()).and_then(|x| if x.is_empty() { None } else { Some(x.to_owned()) })
+        }
+
+        fn date(x: &Option<String>) -> Result<Option<NaiveDate>, ::web::Error> {
+            match non_empty(x) {
+                Some(x) => Ok(Some(NaiveDate::parse_from_str(&x, DATE_FORMAT)?)),
+                None => Ok(None),
+            }
+        }
+
+        Ok(search_query::Filters {
+            author: non_empty(&self.author),
+            modified_after: date(&self.modified_after)?,
+            modified_before: date(&self.modified_before)?,
+            namespace: non_empty(&self.namespace),
+        })
+    }
+
     pub fn into_link(self) -> String {
         let args = serde_urlencoded::to_string(self).expect("Serializing to String cannot fail");
         if args.len() > 0 {
@@ -68,6 +117,7 @@ impl SearchLookup {
 
     pub fn lookup(&self, query: Option<&str>) -> Result<Option<BoxResource>, ::web::Error> {
         let args: QueryParameters = serde_urlencoded::from_str(query.unwrap_or(""))?;
+        let filters = args.parse_filters()?;
 
         Ok(Some(Box::new(
             SearchResource::new(
@@ -76,11 +126,25 @@ impl SearchLookup {
                 args.limit.unwrap_or(DEFAULT_LIMIT),
                 args.offset.unwrap_or(0),
                 args.snippet_size.unwrap_or(DEFAULT_SNIPPET_SIZE),
+                args.history.unwrap_or(0) != 0,
             )
+                .opensearch(args.format.as_ref().map_or(false, |x| x == "opensearch"))
+                .filters(filters)
         )))
     }
 }
 
+struct FacetLink {
+    value: String,
+    count: usize,
+    link: String,
+}
+
+struct Facet {
+    name: &'static str,
+    values: Vec<FacetLink>,
+}
+
 pub struct SearchResource {
     state: State,
     response_type: ResponseType,
@@ -89,17 +153,47 @@ pub struct SearchResource {
     limit: u32,
     offset: u32,
     snippet_size: u32,
+
+    /// Search all revisions rather than only the latest
+    history: bool,
+
+    filters: search_query::Filters,
 }
 
 // This is a complete hack, searching for a reasonable design:
 pub enum ResponseType {
     Html,
     Json,
+
+    /// Suggestions in the OpenSearch JSON format, see the `_opensearch`
+    /// description. Chosen by the `format` parameter rather than by Accept.
+    OpenSearch,
 }
 
 impl SearchResource {
-    pub fn new(state: State, query: Option<String>, limit: u32, offset: u32, snippet_size: u32) -> Self {
-        Self { state, response_type: ResponseType::Html, query, limit, offset, snippet_size }
+    pub fn new(state: State, query: Option<String>, limit: u32, offset: u32, snippet_size: u32, history: bool) -> Self {
+        Self {
+            state,
+            response_type: ResponseType::Html,
+            query,
+            limit,
+            offset,
+            snippet_size,
+            history,
+            filters: search_query::Filters::default(),
+        }
+    }
+
+    pub fn filters(self, filters: search_query::Filters) -> Self {
+        Self { filters, ..self }
+    }
+
+    pub fn opensearch(self, opensearch: bool) -> Self {
+        if opensearch {
+            Self { response_type: ResponseType::OpenSearch, ..self }
+        } else {
+            self
+        }
     }
 
     fn query_args(&self) -> QueryParameters {
@@ -110,6 +204,226 @@ impl SearchResource {
             .offset(self.offset)
             .limit(self.limit)
             .snippet_size(self.snippet_size)
+            .history(self.history)
+            .filters(&self.filters)
+    }
+
+    fn filter_link(&self, filters: &search_query::Filters) -> String {
+        self.query_args()
+            .offset(0)
+            .filters(filters)
+            .into_link()
+    }
+
+    /// Links for narrowing down the search by each facet value
+    fn facet_links(&self, facets: &SearchFacets) -> Vec<Facet> {
+        let links = |name, counts: &[FacetCount], filter: &Fn(&str) -> Option<search_query::Filters>| Facet {
+            name,
+            values: counts.iter()
+                .filter_map(|x| filter(&x.value).map(|filters| FacetLink {
+                    value: x.value.clone(),
+                    count: x.count,
+                    link: self.filter_link(&filters),
+                }))
+                .collect(),
+        };
+
+        vec![
+            links("Author", &facets.authors, &|x| Some(search_query::Filters {
+                author: Some(x.to_owned()),
+                ..self.filters.clone()
+            })),
+            links("Namespace", &facets.namespaces, &|x| Some(search_query::Filters {
+                namespace: Some(x.to_owned()),
+                ..self.filters.clone()
+            })),
+            links("Last changed", &facets.years, &|x| x.parse::<i32>().ok().map(|year| search_query::Filters {
+                modified_after: Some(NaiveDate::from_ymd(year, 1, 1)),
+                modified_before: Some(NaiveDate::from_ymd(year + 1, 1, 1)),
+                ..self.filters.clone()
+            })),
+        ]
+            .into_iter()
+            .filter(|x| !x.values.is_empty())
+            .collect()
+    }
+
+    /// Descriptions of the filters in effect, with links for removing them
+    fn active_filters(&self) -> Vec<(String, String)> {
+        use search_query::Filters;
+
+        let f = &self.filters;
+        let mut active = Vec::new();
+
+        if let Some(ref author) = f.author {
+            active.push((format!("by {}", author), self.filter_link(&Filters { author: None, ..f.clone() })));
+        }
+        if let Some(ref namespace) = f.namespace {
+            active.push((format!("in {}", namespace), self.filter_link(&Filters { namespace: None, ..f.clone() })));
+        }
+        if let Some(after) = f.modified_after {
+            active.push((format!("changed on or after {}", after), self.filter_link(&Filters { modified_after: None, ..f.clone() })));
+        }
+        if let Some(before) = f.modified_before {
+            active.push((format!("changed before {}", before), self.filter_link(&Filters { modified_before: None, ..f.clone() })));
+        }
+
+        active
+    }
+
+    /// Links to the previous and next pages, given the results fetched with
+    /// one more than `limit` to tell if there is a next page
+    fn pagination<T>(&self, data: &mut Vec<T>) -> (Option<String>, Option<String>) {
+        let prev = if self.offset > 0 {
+            Some(self.query_args()
+                .offset(self.offset.saturating_sub(self.limit))
+                .into_link()
+            )
+        } else {
+            None
+        };
+
+        let next = if data.len() > self.limit as usize {
+            data.pop();
+            Some(self.query_args()
+                .offset(self.offset + self.limit)
+                .into_link()
+            )
+        } else {
+            None
+        };
+
+        (prev, next)
+    }
+
+    /// Shown when there is no query
+    fn get_front_page(self: Box<Self>) -> ResponseFuture {
+        #[derive(BartDisplay)]
+        #[template="templates/search_front_page.html"]
+        struct Template<'a> {
+            recent: &'a [ArticleTitle],
+            most_edited: &'a [::models::EditedArticle],
+            random: &'a [ArticleTitle],
+        }
+
+        let data = self.state.get_search_front_page(self.limit as i32);
+        let head = self.head();
+
+        Box::new(data.join(head)
+            .and_then(move |(data, head)| {
+                match &self.response_type {
+                    &ResponseType::Json => Ok(head
+                        .with_body(serde_json::to_string(&data).expect("Should never fail"))
+                    ),
+                    &ResponseType::OpenSearch => Ok(head
+                        .with_body(serde_json::to_string(&(
+                            "",
+                            data.recent.iter().map(|x| &x.title).collect::<Vec<_>>(),
+                        )).expect("Should never fail"))
+                    ),
+                    &ResponseType::Html => {
+                        let SearchFrontPage { recent, most_edited, random } = data;
+                        Ok(head
+                            .with_body(Layout {
+                                base: None,
+                                title: "Search",
+                                body: &Template {
+                                    recent: &recent,
+                                    most_edited: &most_edited,
+                                    random: &random,
+                                },
+                            }.to_string()))
+                    },
+                }
+            }))
+    }
+
+    fn get_history(self: Box<Self>) -> ResponseFuture {
+        use chrono::{TimeZone, Local};
+
+        #[derive(Serialize)]
+        struct JsonHit<'a> {
+            article_id: i32,
+            revision: i32,
+            link: String,
+            created: String,
+            author: Option<&'a str>,
+            title: &'a str,
+            title_highlights: &'a [::models::Highlight],
+            snippet: &'a str,
+            snippet_highlights: &'a [::models::Highlight],
+        }
+
+        #[derive(Serialize)]
+        struct JsonResponse<'a> {
+            query: &'a str,
+            history: bool,
+            hits: Vec<JsonHit<'a>>,
+            prev: Option<String>,
+            next: Option<String>,
+        }
+
+        #[derive(BartDisplay)]
+        #[template="templates/search_history.html"]
+        struct Template<'a> {
+            query: &'a str,
+            hits: &'a [(usize, &'a RevisionSearchResult, String)],
+            prev: Option<String>,
+            next: Option<String>,
+            latest_link: String,
+        }
+
+        let query = self.query.as_ref().map(|x| x.clone()).unwrap_or("".to_owned());
+
+        let data = self.state.search_history_query(query, self.filters.clone(), (self.limit + 1) as i32, self.offset as i32, self.snippet_size as i32);
+        let head = self.head();
+
+        Box::new(data.join(head)
+            .and_then(move |(mut data, head)| {
+                let (prev, next) = self.pagination(&mut data);
+                let query = self.query.as_ref().map(|x| &**x).unwrap_or("");
+
+                let created = |x: &RevisionSearchResult| Local.from_utc_datetime(&x.created).to_rfc2822();
+
+                match &self.response_type {
+                    &ResponseType::Json | &ResponseType::OpenSearch => Ok(head
+                        .with_body(serde_json::to_string(&JsonResponse {
+                            query,
+                            history: true,
+                            hits: data.iter()
+                                .map(|x| JsonHit {
+                                    article_id: x.article_id,
+                                    revision: x.revision,
+                                    link: x.link(),
+                                    created: created(x),
+                                    author: x.author.as_ref().map(|x| &**x),
+                                    title: &x.title,
+                                    title_highlights: &x.title_highlights,
+                                    snippet: &x.snippet,
+                                    snippet_highlights: &x.snippet_highlights,
+                                })
+                                .collect(),
+                            prev,
+                            next,
+                        }).expect("Should never fail"))
+                    ),
+                    &ResponseType::Html => Ok(head
+                        .with_body(Layout {
+                            base: None,
+                            title: "Search history",
+                            body: &Template {
+                                query,
+                                hits: &data.iter()
+                                    .enumerate()
+                                    .map(|(i, x)| (i, x, created(x)))
+                                    .collect::<Vec<_>>(),
+                                prev,
+                                next,
+                                latest_link: self.query_args().offset(0).history(false).into_link(),
+                            },
+                        }.to_string())),
+                }
+            }))
     }
 }
 
@@ -124,6 +438,10 @@ impl Resource for SearchResource {
         use hyper::header::QualityItem;
         use hyper::mime;
 
+        if let ResponseType::OpenSearch = self.response_type {
+            return;
+        }
+
         self.response_type = match accept.first() {
             Some(&QualityItem { item: ref mime, .. })
                 if mime.type_() == mime::APPLICATION && mime.subtype() == mime::JSON
@@ -136,6 +454,7 @@ impl Resource for SearchResource {
         let content_type = match &self.response_type {
             &ResponseType::Json => ContentType(APPLICATION_JSON.clone()),
             &ResponseType::Html => ContentType(TEXT_HTML.clone()),
+            &ResponseType::OpenSearch => ContentType(APPLICATION_X_SUGGESTIONS_JSON.clone()),
         };
 
         Box::new(futures::finished(Response::new()
@@ -149,6 +468,8 @@ impl Resource for SearchResource {
         struct JsonResponse<'a> {
             query: &'a str,
             hits: &'a [SearchResult],
+            suggestions: &'a [ArticleTitle],
+            facets: &'a SearchFacets,
             prev: Option<String>,
             next: Option<String>,
         }
@@ -160,44 +481,67 @@ impl Resource for SearchResource {
             hits: &'a [(usize, &'a SearchResult)],
             prev: Option<String>,
             next: Option<String>,
+            history_link: String,
+            did_you_mean: String,
+            active_filters: Vec<(String, String)>,
+            facets: Vec<Facet>,
+        }
+
+        if self.history {
+            return self.get_history();
+        }
+
+        if self.query.as_ref().map_or(true, |x| x.trim().is_empty()) {
+            return self.get_front_page();
         }
 
-        // TODO: Show a search "front page" when no query is given:
         let query = self.query.as_ref().map(|x| x.clone()).unwrap_or("".to_owned());
 
-        let data = self.state.search_query(query, (self.limit + 1) as i32, self.offset as i32, self.snippet_size as i32);
-        let head = self.head();
+        // When nothing matches, suggest articles with similar titles
+        let state = self.state.clone();
+        let suggest = self.offset == 0;
+        let plain_text = search_query::plain_text(&query);
 
-        Box::new(data.join(head)
-            .and_then(move |(mut data, head)| {
-                let prev = if self.offset > 0 {
-                    Some(self.query_args()
-                        .offset(self.offset.saturating_sub(self.limit))
-                        .into_link()
-                    )
-                } else {
-                    None
-                };
-
-                let next = if data.len() > self.limit as usize {
-                    data.pop();
-                    Some(self.query_args()
-                        .offset(self.offset + self.limit)
-                        .into_link()
-                    )
+        let facets: Box<Future<Item=SearchFacets, Error=::state::Error>> = match &self.response_type {
+            &ResponseType::OpenSearch => Box::new(futures::finished(SearchFacets::default())),
+            _ => Box::new(self.state.search_facets(query.clone(), self.filters.clone())),
+        };
+
+        let data = self.state.search_query(query, self.filters.clone(), (self.limit + 1) as i32, self.offset as i32, self.snippet_size as i32)
+            .and_then(move |data| -> Box<Future<Item=(Vec<SearchResult>, Vec<ArticleTitle>), Error=::state::Error>> {
+                if data.is_empty() && suggest {
+                    Box::new(state.suggest_titles(plain_text, MAX_SUGGESTIONS)
+                        .map(move |suggestions| (data, suggestions)))
                 } else {
-                    None
-                };
+                    Box::new(futures::finished((data, vec![])))
+                }
+            });
+        let head = self.head();
+
+        Box::new(data.join3(facets, head)
+            .and_then(move |((mut data, suggestions), facets, head)| {
+                let (prev, next) = self.pagination(&mut data);
 
                 match &self.response_type {
                     &ResponseType::Json => Ok(head
                         .with_body(serde_json::to_string(&JsonResponse {
                             query: self.query.as_ref().map(|x| &**x).unwrap_or(""),
                             hits: &data,
+                            suggestions: &suggestions,
+                            facets: &facets,
                             prev,
                             next,
                         }).expect("Should never fail"))
                     ),
+                    &ResponseType::OpenSearch => Ok(head
+                        .with_body(serde_json::to_string(&(
+                            self.query.as_ref().map(|x| &**x).unwrap_or(""),
+                            data.iter()
+                                .map(|x| &x.title)
+                                .chain(suggestions.iter().map(|x| &x.title))
+                                .collect::<Vec<_>>(),
+                        )).expect("Should never fail"))
+                    ),
                     &ResponseType::Html => Ok(head
                         .with_body(Layout {
                             base: None, // Hmm, should perhaps accept `base` as argument
@@ -209,6 +553,10 @@ impl Resource for SearchResource {
                                     .collect::<Vec<_>>(),
                                 prev,
                                 next,
+                                history_link: self.query_args().offset(0).history(true).into_link(),
+                                did_you_mean: DidYouMean { suggestions: &suggestions }.to_string(),
+                                active_filters: self.active_filters(),
+                                facets: self.facet_links(&facets),
                             },
                         }.to_string())),
                 }
@@ -0,0 +1,90 @@
+use futures::{self, Future};
+use hyper;
+use hyper::header::ContentType;
+use hyper::server::*;
+
+use chart;
+use mimes::*;
+use models;
+use site::Layout;
+use state::State;
+use web::{Resource, ResponseFuture};
+
+use super::user_resource::user_link;
+
+pub struct StatsResource {
+    state: State,
+}
+
+impl StatsResource {
+    pub fn new(state: State) -> Self {
+        StatsResource { state }
+    }
+}
+
+impl Resource for StatsResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Head, Get]
+    }
+
+    fn head(&self) -> ResponseFuture {
+        Box::new(futures::finished(Response::new()
+            .with_status(hyper::StatusCode::Ok)
+            .with_header(ContentType(TEXT_HTML.clone()))
+        ))
+    }
+
+    fn get(self: Box<Self>) -> ResponseFuture {
+        struct Contributor<'a> {
+            author: &'a str,
+            link: String,
+            edits: i64,
+        }
+
+        #[derive(BartDisplay)]
+        #[template="templates/stats.html"]
+        struct Template<'a> {
+            articles: i64,
+            revisions: i64,
+            edits_per_day: String,
+            edits_per_week: String,
+            top_contributors: &'a [Contributor<'a>],
+            largest: &'a [models::ArticleSize],
+            fastest_growing: &'a [models::ArticleSize],
+        }
+
+        let chart = |edits: &[(::chrono::NaiveDate, i64)]| chart::bar_chart(
+            &edits.iter()
+                .map(|&(date, count)| (date.format("%Y-%m-%d").to_string(), count))
+                .collect::<Vec<_>>()
+        );
+
+        let data = self.state.get_stats();
+        let head = self.head();
+
+        Box::new(data.join(head)
+            .and_then(move |(stats, head)| {
+                Ok(head
+                    .with_body(Layout {
+                        base: None, // Hmm, should perhaps accept `base` as argument
+                        title: "Statistics",
+                        body: &Template {
+                            articles: stats.articles,
+                            revisions: stats.revisions,
+                            edits_per_day: chart(&stats.edits_per_day),
+                            edits_per_week: chart(&stats.edits_per_week),
+                            top_contributors: &stats.top_contributors.iter()
+                                .map(|x| Contributor {
+                                    author: &x.author,
+                                    link: user_link(&x.author),
+                                    edits: x.edits,
+                                })
+                                .collect::<Vec<_>>(),
+                            largest: &stats.largest,
+                            fastest_growing: &stats.fastest_growing,
+                        },
+                    }.to_string()))
+            }))
+    }
+}
@@ -0,0 +1,94 @@
+use futures::{self, Future};
+use hyper;
+use hyper::header::ContentType;
+use hyper::server::*;
+use serde_json;
+use serde_urlencoded;
+
+use mimes::*;
+use models::ArticleTitle;
+use state::State;
+use web::{Resource, ResponseFuture};
+
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 50;
+
+type BoxResource = Box<Resource + Sync + Send>;
+
+#[derive(Deserialize)]
+struct QueryParameters {
+    prefix: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Clone)]
+pub struct TitlesLookup {
+    state: State,
+}
+
+impl TitlesLookup {
+    pub fn new(state: State) -> Self {
+        Self { state }
+    }
+
+    pub fn lookup(&self, query: Option<&str>) -> Result<Option<BoxResource>, ::web::Error> {
+        let args: QueryParameters = serde_urlencoded::from_str(query.unwrap_or(""))?;
+
+        let limit = match args.limit {
+            None => DEFAULT_LIMIT,
+            Some(x) if 1 <= x && x <= MAX_LIMIT => x,
+            _ => return Err("`limit` argument must be in range [1, 50]".into()),
+        };
+
+        Ok(Some(Box::new(
+            TitlesResource::new(self.state.clone(), args.prefix.unwrap_or_default(), limit)
+        )))
+    }
+}
+
+/// Titles of articles matching a prefix, for autocompletion
+pub struct TitlesResource {
+    state: State,
+    prefix: String,
+    limit: u32,
+}
+
+impl TitlesResource {
+    pub fn new(state: State, prefix: String, limit: u32) -> Self {
+        Self { state, prefix, limit }
+    }
+}
+
+impl Resource for TitlesResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Head, Get]
+    }
+
+    fn head(&self) -> ResponseFuture {
+        Box::new(futures::finished(Response::new()
+            .with_status(hyper::StatusCode::Ok)
+            .with_header(ContentType(APPLICATION_JSON.clone()))
+        ))
+    }
+
+    fn get(self: Box<Self>) -> ResponseFuture {
+        #[derive(Serialize)]
+        struct JsonResponse<'a> {
+            prefix: &'a str,
+            titles: &'a [ArticleTitle],
+        }
+
+        let data = self.state.get_titles_by_prefix(self.prefix.clone(), self.limit as i32);
+        let head = self.head();
+
+        Box::new(data.join(head)
+            .and_then(move |(data, head)| {
+                Ok(head
+                    .with_body(serde_json::to_string(&JsonResponse {
+                        prefix: &self.prefix,
+                        titles: &data,
+                    }).expect("Should never fail")))
+            }))
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::{TimeZone, Local};
+use futures::{self, Future};
+use hyper;
+use hyper::header::ContentType;
+use hyper::server::*;
+use percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use serde_urlencoded;
+
+use mimes::*;
+use models;
+use site::Layout;
+use state::State;
+use web::{Resource, ResponseFuture};
+
+use super::changes_resource::QueryParameters;
+
+/// The number of articles and contributions to list on the page
+const LIMIT: i32 = 10;
+
+type BoxResource = Box<Resource + Sync + Send>;
+
+/// The page about the given author, relative to the root of the wiki
+pub fn user_link(identity: &str) -> String {
+    format!("_users/{}", utf8_percent_encode(identity, PATH_SEGMENT_ENCODE_SET))
+}
+
+#[derive(Clone)]
+pub struct UserLookup {
+    state: State,
+}
+
+impl UserLookup {
+    pub fn new(state: State) -> Self {
+        Self { state }
+    }
+
+    pub fn lookup(&self, identity: String) -> Box<Future<Item=Option<BoxResource>, Error=::web::Error>> {
+        let state = self.state.clone();
+
+        Box::new(self.state.get_user_contributions(identity.clone(), LIMIT)
+            .and_then(move |data| Ok(data.map(move |data|
+                Box::new(UserResource::new(state, identity, data)) as BoxResource
+            )))
+        )
+    }
+}
+
+pub struct UserResource {
+    state: State,
+    identity: String,
+    data: models::UserContributions,
+}
+
+impl UserResource {
+    pub fn new(state: State, identity: String, data: models::UserContributions) -> Self {
+        Self { state, identity, data }
+    }
+}
+
+impl Resource for UserResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Head, Get]
+    }
+
+    fn head(&self) -> ResponseFuture {
+        Box::new(futures::finished(Response::new()
+            .with_status(hyper::StatusCode::Ok)
+            .with_header(ContentType(TEXT_HTML.clone()))
+        ))
+    }
+
+    fn get(self: Box<Self>) -> ResponseFuture {
+        struct Contribution<'a> {
+            stub: &'a models::ArticleRevisionStub,
+            created: String,
+        }
+
+        struct Profile<'a> {
+            link: &'a str,
+            rendered: String,
+        }
+
+        #[derive(BartDisplay)]
+        #[template="templates/user.html"]
+        struct Template<'a> {
+            identity: &'a str,
+            has_edits: bool,
+            edits: i64,
+            first_edit: Option<String>,
+            last_edit: Option<String>,
+            most_edited: &'a [models::EditedArticle],
+            recent: &'a [Contribution<'a>],
+            changes_link: String,
+            profile: Option<Profile<'a>>,
+            new_profile_link: String,
+        }
+
+        let head = self.head();
+        let rendered: Box<Future<Item=Option<String>, Error=::state::Error>> = match self.data.profile {
+            Some(ref x) => Box::new(self.state.render_article(x.article_id, x.revision, x.body.clone()).map(Some)),
+            None => Box::new(futures::finished(None)),
+        };
+
+        Box::new(head.join(rendered)
+            .and_then(move |(head, rendered)| {
+                let date = |x: &::chrono::NaiveDateTime| Local.from_utc_datetime(x).to_rfc2822();
+                let data = &self.data;
+
+                Ok(head
+                    .with_body(Layout {
+                        base: Some("../"), // Hmm, should perhaps accept `base` as argument
+                        title: &self.identity,
+                        body: &Template {
+                            identity: &self.identity,
+                            has_edits: data.edits > 0,
+                            edits: data.edits,
+                            first_edit: data.first_edit.as_ref().map(&date),
+                            last_edit: data.last_edit.as_ref().map(&date),
+                            most_edited: &data.most_edited,
+                            recent: &data.recent.iter()
+                                .map(|x| Contribution { stub: x, created: date(&x.created) })
+                                .collect::<Vec<_>>(),
+                            changes_link: format!("_changes{}",
+                                QueryParameters::default()
+                                    .author(Some(self.identity.clone()))
+                                    .into_link()
+                            ),
+                            profile: match (data.profile.as_ref(), rendered) {
+                                (Some(profile), Some(rendered)) => Some(Profile {
+                                    link: profile.link(),
+                                    rendered,
+                                }),
+                                _ => None,
+                            },
+                            new_profile_link: format!("_new?{}",
+                                serde_urlencoded::to_string(&[("title", models::profile_title(&self.identity))])
+                                    .expect("Serializing to String cannot fail")
+                            ),
+                        },
+                    }.to_string()))
+            }))
+    }
+}
@@ -0,0 +1,80 @@
+use futures::{self, Future, Stream};
+use hyper;
+use hyper::header::{ContentType, Location};
+use hyper::server::*;
+use serde_urlencoded;
+
+use mimes::*;
+use state::State;
+use web::{Resource, ResponseFuture};
+
+#[derive(Deserialize)]
+struct WatchArticle {
+    article_id: i32,
+    watch: bool,
+}
+
+/// Watches and unwatches articles for the identity of the request, then
+/// shows the watchlist
+pub struct WatchResource {
+    state: State,
+}
+
+impl WatchResource {
+    pub fn new(state: State) -> Self {
+        Self { state }
+    }
+}
+
+impl Resource for WatchResource {
+    fn allow(&self) -> Vec<hyper::Method> {
+        use hyper::Method::*;
+        vec![Options, Post]
+    }
+
+    fn post(self: Box<Self>, body: hyper::Body, identity: Option<String>) -> ResponseFuture {
+        let identity = match identity {
+            Some(x) => x,
+            None => return Box::new(futures::finished(Response::new()
+                .with_status(hyper::StatusCode::Forbidden)
+                .with_header(ContentType(TEXT_PLAIN.clone()))
+                .with_body("Watching articles requires a known identity\n")
+            )),
+        };
+
+        let state = self.state;
+
+        Box::new(body
+            .concat2()
+            .map_err(Into::into)
+            .and_then(|body| {
+                serde_urlencoded::from_bytes(&body)
+                    .map_err(Into::into)
+            })
+            .and_then(move |arg: WatchArticle| {
+                state.get_article_slug(arg.article_id)
+                    .and_then(move |slug| -> ResponseFuture {
+                        if slug.is_none() {
+                            return Box::new(futures::finished(Response::new()
+                                .with_status(hyper::StatusCode::NotFound)
+                                .with_header(ContentType(TEXT_PLAIN.clone()))
+                                .with_body("No such article\n")
+                            ));
+                        }
+
+                        let done = match arg.watch {
+                            true => state.watch_article(identity, arg.article_id),
+                            false => state.unwatch_article(identity, arg.article_id),
+                        };
+
+                        Box::new(done.map(|()| Response::new()
+                            .with_status(hyper::StatusCode::SeeOther)
+                            .with_header(ContentType(TEXT_PLAIN.clone()))
+                            .with_header(Location::new("_watchlist"))
+                            .with_body("See other")
+                        ))
+                    })
+            })
+        )
+    }
+}
@@ -0,0 +1,288 @@
+//! Parser for the query language of the search box, compiling to an FTS5
+//! MATCH expression:
+//!
+//!  * `word`, matching words with the same stem
+//!  * `"exact phrase"`
+//!  * `-word` or `-"phrase"`, excluding articles that match
+//!  * `a OR b`
+//!  * `prefix*`
+//!  * `title:word` or `title:"phrase"`, matching only in the title
+//!
+//! Terms are combined with AND. The user's text only ever ends up inside
+//! FTS5 strings, so no query can inject FTS5 syntax.
+
+use chrono::NaiveDate;
+
+/// Restrictions on the latest revisions of the articles to search for
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    pub author: Option<String>,
+
+    /// Inclusive
+    pub modified_after: Option<NaiveDate>,
+
+    /// Exclusive
+    pub modified_before: Option<NaiveDate>,
+
+    /// See `models::namespace`
+    pub namespace: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Term {
+    pub text: String,
+    pub quoted: bool,
+    pub prefix: bool,
+    pub title_only: bool,
+    pub negated: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    Or,
+    Term(Term),
+}
+
+const TITLE_FIELD: &str = "title:";
+
+fn parse_term(src: &str) -> (Term, &str) {
+    let mut rest = src;
+
+    let negated = rest.starts_with('-');
+    if negated {
+        rest = &rest[1..];
+    }
+
+    let title_only = rest.starts_with(TITLE_FIELD);
+    if title_only {
+        rest = &rest[TITLE_FIELD.len()..];
+    }
+
+    let quoted = rest.starts_with('"');
+    let text;
+    if quoted {
+        rest = &rest[1..];
+        let end = rest.find('"').unwrap_or(rest.len());
+        text = &rest[..end];
+        rest = &rest[end..];
+        if !rest.is_empty() {
+            rest = &rest[1..];
+        }
+    } else {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        text = rest[..end].trim_right_matches('*');
+        rest = &rest[text.len()..];
+    }
+
+    let prefix = rest.starts_with('*');
+    if prefix {
+        rest = &rest[1..];
+    }
+
+    (
+        Term {
+            text: text.to_owned(),
+            quoted,
+            prefix,
+            title_only,
+            negated,
+        },
+        rest
+    )
+}
+
+pub fn parse(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = query.trim_left();
+
+    while !rest.is_empty() {
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if &rest[..word_end] == "OR" {
+            tokens.push(Token::Or);
+            rest = &rest[word_end..];
+        } else {
+            let (term, tail) = parse_term(rest);
+            if !term.text.is_empty() {
+                tokens.push(Token::Term(term));
+            }
+            rest = tail;
+        }
+
+        rest = rest.trim_left();
+    }
+
+    // Match the last word as a prefix, so results can be shown while the
+    // user is typing
+    let last_term = tokens.iter_mut().rev()
+        .filter_map(|x| match *x {
+            Token::Term(ref mut term) => Some(term),
+            Token::Or => None,
+        })
+        .next();
+    if let Some(term) = last_term {
+        if !term.quoted && !term.negated {
+            term.prefix = true;
+        }
+    }
+
+    tokens
+}
+
+/// The text of the terms that must match, for comparing the query to
+/// titles and slugs
+pub fn plain_text(query: &str) -> String {
+    parse(query).into_iter()
+        .filter_map(|x| match x {
+            Token::Term(ref term) if !term.negated => Some(term.text.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn fts_quote(src: &str) -> String {
+    format!("\"{}\"", src.replace('"', "\"\""))
+}
+
+/// An FTS5 expression matching titles with words starting with each of the
+/// words in `prefix`, or `None` if there are no words
+pub fn title_word_prefixes(prefix: &str) -> Option<String> {
+    let words = prefix.split_whitespace()
+        .map(|x| format!("{}{}*", TITLE_FIELD, fts_quote(x)))
+        .collect::<Vec<_>>();
+
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" AND "))
+    }
+}
+
+fn compile_term(term: &Term) -> String {
+    format!(
+        "{}{}{}",
+        if term.title_only { TITLE_FIELD } else { "" },
+        fts_quote(&term.text),
+        if term.prefix { "*" } else { "" }
+    )
+}
+
+/// Compile the query to an FTS5 expression. Returns `None` when nothing
+/// can match, such as for queries with only excluded terms.
+pub fn compile(query: &str) -> Option<String> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut excluded = Vec::new();
+    let mut or = false;
+
+    for token in parse(query) {
+        match token {
+            Token::Or => or = true,
+            Token::Term(ref term) if term.negated => {
+                excluded.push(compile_term(term));
+                or = false;
+            },
+            Token::Term(ref term) => {
+                if or && !groups.is_empty() {
+                    groups.last_mut().unwrap().push(compile_term(term));
+                } else {
+                    groups.push(vec![compile_term(term)]);
+                }
+                or = false;
+            },
+        }
+    }
+
+    if groups.is_empty() {
+        return None;
+    }
+
+    let included = groups.into_iter()
+        .map(|x| if x.len() > 1 { format!("({})", x.join(" OR ")) } else { x.join("") })
+        .collect::<Vec<_>>()
+        .join(" AND ");
+
+    if excluded.is_empty() {
+        Some(included)
+    } else {
+        Some(format!("({}) NOT ({})", included, excluded.join(" OR ")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn compile(query: &str) -> String {
+        super::compile(query).unwrap_or_else(|| "<none>".to_owned())
+    }
+
+    #[test]
+    fn words_are_combined_with_and() {
+        assert_eq!("\"a\" AND \"b\"*", compile("a  b"));
+        assert_eq!("\"word\"*", compile("word"));
+    }
+
+    #[test]
+    fn phrase() {
+        assert_eq!("\"exact phrase\" AND \"b\"*", compile("\"exact phrase\" b"));
+        assert_eq!("\"a\" AND \"unterminated phrase\"", compile("a \"unterminated phrase"));
+    }
+
+    #[test]
+    fn exclude() {
+        assert_eq!("(\"a\") NOT (\"b\" OR \"c d\")", compile("a -b -\"c d\""));
+        assert_eq!("<none>", compile("-a"));
+    }
+
+    #[test]
+    fn or() {
+        assert_eq!("\"a\" AND (\"b\" OR \"c\"*)", compile("a b OR c"));
+        assert_eq!("(\"a\" OR \"b\" OR \"c\"*)", compile("a OR b OR c"));
+        assert_eq!("\"a\" AND \"or\"*", compile("a or"));
+        assert_eq!("\"a\"*", compile("OR a OR"));
+    }
+
+    #[test]
+    fn prefix() {
+        assert_eq!("\"pre\"* AND \"b\"*", compile("pre* b"));
+        assert_eq!("\"exact\"*", compile("\"exact\"*"));
+        assert_eq!("(\"a\"*) NOT (\"b\"*)", compile("a* -b*"));
+    }
+
+    #[test]
+    fn title() {
+        assert_eq!("title:\"a\" AND title:\"b c\"", compile("title:a title:\"b c\" "));
+        assert_eq!("(\"x\") NOT (title:\"a\")", compile("x -title:a"));
+    }
+
+    #[test]
+    fn quotes_are_escaped() {
+        assert_eq!("\"a\"\"b\"*", compile("a\"b"));
+        assert_eq!("\"a\" AND \"b\"\"\"*", compile("\"a\"b\""));
+    }
+
+    #[test]
+    fn operators_are_literal() {
+        assert_eq!(
+            "\"NEAR(a\" AND \"b)\" AND \"AND\" AND \"NOT\" AND \"c:d\" AND \"^e\" AND \"{title}\"*",
+            compile("NEAR(a b) AND NOT c:d ^e {title}")
+        );
+    }
+
+    #[test]
+    fn plain_text_of_query() {
+        assert_eq!("Getting started db", plain_text("title:\"Getting started\" -x OR db*"));
+    }
+
+    #[test]
+    fn title_word_prefixes_are_quoted() {
+        assert_eq!(Some("title:\"a\"\"\"* AND title:\"b\"*".to_owned()), title_word_prefixes(" a\" b"));
+        assert_eq!(None, title_word_prefixes(" "));
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!("<none>", compile(""));
+        assert_eq!("<none>", compile(" - \"\" title: * "));
+    }
+}
@@ -4,7 +4,7 @@
 use std::fmt;
 
 use futures::{self, Future};
-use hyper::header::{Accept, ContentType, Server};
+use hyper::header::{Accept, ContentType, Headers, Host, Server};
 use hyper::mime;
 use hyper::server::*;
 use hyper;
@@ -21,6 +21,25 @@ lazy_static! {
 }
 
 header! { (XIdentity, "X-Identity") => [String] }
+header! { (XForwardedProto, "X-Forwarded-Proto") => [String] }
+
+/// The absolute URL of the root of the wiki as seen by the client, for when
+/// no base URL has been configured. Relies on the Host header, and on
+/// X-Forwarded-Proto from reverse proxies that terminate TLS. Clients can
+/// set these to anything, so this is only for responses that go back to
+/// the same client, never for content others read such as the feed.
+pub fn base_url_from_headers(headers: &Headers) -> Option<String> {
+    let host = match headers.get::<Host>() {
+        Some(x) => match x.port() {
+            Some(port) => format!("{}:{}", x.hostname(), port),
+            None => x.hostname().to_owned(),
+        },
+        None => return None,
+    };
+    let https = headers.get::<XForwardedProto>().map_or(false, |x| x.0 == "https");
+
+    Some(format!("{}://{}/", if https { "https" } else { "http" }, host))
+}
 
 #[derive(BartDisplay)]
 #[template = "templates/layout.html"]
@@ -117,6 +136,8 @@ impl Service for Site {
                 Some(mut resource) => {
                     use hyper::Method::*;
                     resource.hacky_inject_accept_header(accept_header);
+                    resource.hacky_inject_headers(&headers);
+                    resource.hacky_inject_identity(identity.clone());
                     match method {
                         Options => Box::new(futures::finished(resource.options())),
                         Head => resource.head(),
@@ -1,22 +1,49 @@
 use std;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use diesel;
 use diesel::sqlite::SqliteConnection;
 use diesel::prelude::*;
+use futures::{self, Future};
+use futures::sync::mpsc::UnboundedReceiver;
 use futures_cpupool::{self, CpuFuture};
 use r2d2::Pool;
 use r2d2_diesel::ConnectionManager;
 
+use fuzzy;
+use live_changes::{ChangeListeners, Signal};
 use merge;
 use models;
+use render_cache::RenderCache;
+use rendering;
 use schema::*;
+use search_query;
+use thumbnail;
+use webhooks::Webhooks;
 
 #[derive(Clone)]
 pub struct State {
     connection_pool: Pool<ConnectionManager<SqliteConnection>>,
     cpu_pool: futures_cpupool::CpuPool,
+    render_options: rendering::RenderOptions,
+    render_cache: Arc<RenderCache>,
+    search_title_boost: f64,
+    stats_cache: Arc<Mutex<Option<(Instant, Arc<models::Stats>)>>>,
+    webhooks: Arc<Webhooks>,
+    change_listeners: Arc<ChangeListeners>,
 }
 
+/// How long the aggregates on the statistics page may be out of date
+const STATS_CACHE_DURATION: Duration = Duration::from_secs(60);
+
+/// The number of days and weeks of edits to count, and the length of each
+/// list on the statistics page
+const STATS_DAYS: i64 = 30;
+const STATS_WEEKS: i64 = 12;
+const STATS_LIMIT: i32 = 10;
+
 pub type Error = Box<std::error::Error + Send + Sync>;
 
 pub enum SlugLookup {
@@ -40,6 +67,44 @@ struct NewRevision<'a> {
     latest: bool,
 }
 
+#[derive(Insertable)]
+#[table_name="article_includes"]
+struct NewInclude<'a> {
+    article_id: i32,
+    included_slug: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name="rendered_article_revisions"]
+struct NewRenderedRevision<'a> {
+    article_id: i32,
+    revision: i32,
+    render_options: &'a str,
+    html: &'a str,
+}
+
+#[derive(Insertable)]
+#[table_name="attachments"]
+struct NewAttachment<'a> {
+    name: &'a str,
+    revision: i32,
+    author: Option<&'a str>,
+    article_id: Option<i32>,
+    content_type: &'a str,
+    size: i32,
+    data: &'a [u8],
+    latest: bool,
+}
+
+#[derive(Insertable)]
+#[table_name="attachment_thumbnails"]
+struct NewAttachmentThumbnail<'a> {
+    attachment_sequence_number: i32,
+    width: i32,
+    content_type: &'a str,
+    data: Option<&'a [u8]>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct RebaseConflict {
     pub base_article: models::ArticleRevisionStub,
@@ -58,6 +123,61 @@ pub enum UpdateResult {
     RebaseConflict(RebaseConflict),
 }
 
+pub enum AttachmentResult {
+    Success(models::AttachmentStub),
+    /// The name is in use by a file attached to another article
+    NameTaken,
+}
+
+/// Conditions on the revision `r` for `search_query::Filters`. Each
+/// takes two bound parameters, see `filter_parameters`.
+const FILTER_SQL: &str = "\
+    AND (? IS NULL OR r.author = ?) \
+    AND (? IS NULL OR r.created >= ?) \
+    AND (? IS NULL OR r.created < ?) \
+    AND (? IS NULL OR (instr(r.title, ':') > 1 AND \
+        trim(substr(r.title, 1, instr(r.title, ':') - 1), ' ') = ? COLLATE NOCASE))";
+
+/// Current titles or slugs by prefix, served from `title_prefix_lookup`
+/// and `slugs_index`. Binds the LIKE pattern, the GLOB pattern and the
+/// limit. `get_titles_by_prefix_uses_indexes` checks the query plan.
+const TITLE_PREFIX_SQL: &str = "\
+    SELECT title, slug FROM article_revisions WHERE sequence_number IN ( \
+            SELECT sequence_number FROM article_revisions \
+                WHERE latest = 1 AND title LIKE ? ESCAPE '\\' \
+            UNION SELECT sequence_number FROM article_revisions \
+                WHERE latest = 1 AND slug GLOB ? \
+        ) \
+        ORDER BY created DESC, sequence_number DESC \
+        LIMIT ?";
+
+/// Current titles by word prefixes, served from the full text index.
+/// Binds the MATCH query and the limit.
+const TITLE_WORD_PREFIX_SQL: &str = "\
+    SELECT title, slug FROM article_search \
+        WHERE article_search MATCH ? \
+        ORDER BY rank \
+        LIMIT ?";
+
+fn filter_parameters(filters: &search_query::Filters)
+    -> (Option<String>, Option<::chrono::NaiveDateTime>, Option<::chrono::NaiveDateTime>, Option<String>)
+{
+    (
+        filters.author.clone(),
+        filters.modified_after.map(|x| x.and_hms(0, 0, 0)),
+        filters.modified_before.map(|x| x.and_hms(0, 0, 0)),
+        filters.namespace.clone(),
+    )
+}
+
+fn facet_counts(counts: Vec<(String, usize)>) -> Vec<models::FacetCount> {
+    let mut counts = counts.into_iter()
+        .map(|(value, count)| models::FacetCount { value, count })
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.count.cmp(&a.count));
+    counts
+}
+
 fn decide_slug(conn: &SqliteConnection, article_id: i32, prev_title: &str, title: &str, prev_slug: Option<&str>) -> Result<String, Error> {
     let base_slug = ::slug::slugify(title);
 
@@ -100,13 +220,40 @@ fn decide_slug(conn: &SqliteConnection, article_id: i32, prev_title: &str, title
     }
 }
 
+/// Normalize the name of an uploaded file the same way as slugs, keeping
+/// the extension
+fn attachment_name(filename: &str) -> String {
+    // Browsers may send the full path of the file
+    let filename = filename.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+
+    let (stem, extension) = match filename.rfind('.') {
+        Some(x) if x > 0 => (&filename[..x], Some(&filename[x + 1..])),
+        _ => (filename, None),
+    };
+
+    let stem = ::slug::slugify(stem);
+    let stem = if stem.is_empty() { "file" } else { &stem };
+
+    let extension = extension
+        .map(|x| x.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase())
+        .unwrap_or_default();
+
+    if extension.is_empty() {
+        stem.to_owned()
+    } else {
+        format!("{}.{}", stem, extension)
+    }
+}
+
 struct SyncState<'a> {
     db_connection: &'a diesel::SqliteConnection,
+    render_cache: &'a RenderCache,
+    webhooks: &'a Webhooks,
 }
 
 impl<'a> SyncState<'a> {
-    fn new(db_connection: &diesel::SqliteConnection) -> SyncState {
-        SyncState { db_connection }
+    fn new(db_connection: &'a diesel::SqliteConnection, render_cache: &'a RenderCache, webhooks: &'a Webhooks) -> SyncState<'a> {
+        SyncState { db_connection, render_cache, webhooks }
     }
 
     pub fn get_article_slug(&self, article_id: i32) -> Result<Option<String>, Error> {
@@ -154,6 +301,53 @@ impl<'a> SyncState<'a> {
         )
     }
 
+    /// The given revisions, each with the revision before it if there is
+    /// one. Revisions that do not exist are left out.
+    pub fn get_revisions_with_previous(&self, revisions: Vec<(i32, i32)>)
+        -> Result<Vec<(models::ArticleRevision, Option<models::ArticleRevision>)>, Error>
+    {
+        self.db_connection.transaction(|| {
+            let mut result = Vec::new();
+
+            for (article_id, revision) in revisions {
+                if let Some(x) = self.get_article_revision(article_id, revision)? {
+                    let previous = self.get_article_revision(article_id, revision - 1)?;
+                    result.push((x, previous));
+                }
+            }
+
+            Ok(result)
+        })
+    }
+
+    /// The size in bytes of the body of each of the given revisions.
+    /// Revisions that do not exist are left out.
+    pub fn get_revision_sizes(&self, revisions: Vec<(i32, i32)>) -> Result<BTreeMap<(i32, i32), i64>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{BigInt, Integer};
+
+        self.db_connection.transaction(|| {
+            let mut sizes = BTreeMap::new();
+
+            for (article_id, revision) in revisions {
+                let size = sql::<BigInt>(
+                    "SELECT length(CAST(body AS BLOB)) FROM article_revisions \
+                        WHERE article_id = ? AND revision = ?"
+                )
+                    .bind::<Integer, _>(article_id)
+                    .bind::<Integer, _>(revision)
+                    .get_result::<i64>(self.db_connection)
+                    .optional()?;
+
+                if let Some(size) = size {
+                    sizes.insert((article_id, revision), size);
+                }
+            }
+
+            Ok(sizes)
+        })
+    }
+
     fn get_article_revision_stub(&self, article_id: i32, revision: i32) -> Result<Option<models::ArticleRevisionStub>, Error> {
         use schema::article_revisions;
 
@@ -203,6 +397,299 @@ impl<'a> SyncState<'a> {
         })
     }
 
+    fn record_includes(&self, article_id: i32, body: &str) -> Result<(), Error> {
+        use schema::article_includes;
+
+        diesel::delete(
+            article_includes::table
+                .filter(article_includes::article_id.eq(article_id))
+        )
+            .execute(self.db_connection)?;
+
+        let slugs = rendering::find_includes(body);
+        let includes = slugs.iter()
+            .map(|slug| NewInclude { article_id, included_slug: slug })
+            .collect::<Vec<_>>();
+
+        if !includes.is_empty() {
+            diesel::insert(&includes)
+                .into(article_includes::table)
+                .execute(self.db_connection)?;
+        }
+
+        Ok(())
+    }
+
+    /// Invalidate cached renderings of all articles that include the given
+    /// article, directly or indirectly. Includes may refer to any slug the
+    /// article has had, since old slugs redirect to the current one.
+    ///
+    /// Call after the change is committed, so renderings started before
+    /// then are not cached, see `render_article`.
+    fn invalidate_includers(&self, article_id: i32) -> Result<(), Error> {
+        use schema::{article_includes, article_revisions, rendered_article_revisions};
+
+        let mut visited = vec![article_id];
+        let mut queue = vec![article_id];
+        let mut invalidated = vec![];
+
+        while let Some(id) = queue.pop() {
+            let slugs = article_revisions::table
+                .filter(article_revisions::article_id.eq(id))
+                .select(article_revisions::slug)
+                .load::<String>(self.db_connection)?;
+
+            let includers = article_includes::table
+                .filter(article_includes::included_slug.eq_any(slugs))
+                .select(article_includes::article_id)
+                .load::<i32>(self.db_connection)?;
+
+            for includer in includers {
+                if !visited.contains(&includer) {
+                    visited.push(includer);
+                    queue.push(includer);
+                    invalidated.push(includer);
+                }
+            }
+        }
+
+        if invalidated.is_empty() {
+            return Ok(());
+        }
+
+        self.render_cache.invalidate(&invalidated);
+
+        diesel::delete(
+            rendered_article_revisions::table
+                .filter(rendered_article_revisions::article_id.eq_any(invalidated))
+        )
+            .execute(self.db_connection)?;
+
+        Ok(())
+    }
+
+    pub fn render_article(&self, article_id: i32, revision: i32, body: &str, options: rendering::RenderOptions)
+        -> Result<String, Error>
+    {
+        use schema::rendered_article_revisions;
+
+        let generation = self.render_cache.generation();
+
+        if let Some(html) = self.render_cache.get(article_id, revision) {
+            return Ok(html);
+        }
+
+        let fingerprint = options.fingerprint();
+
+        if self.render_cache.persist() {
+            let persisted = rendered_article_revisions::table
+                .filter(rendered_article_revisions::article_id.eq(article_id))
+                .filter(rendered_article_revisions::revision.eq(revision))
+                .filter(rendered_article_revisions::render_options.eq(&fingerprint))
+                .select(rendered_article_revisions::html)
+                .first::<String>(self.db_connection)
+                .optional()?;
+
+            if let Some(html) = persisted {
+                self.render_cache.insert(generation, article_id, revision, html.clone());
+                return Ok(html);
+            }
+        }
+
+        let html = rendering::render_markdown(body, self, options)?;
+
+        if self.render_cache.persist() {
+            diesel::insert_or_replace(&NewRenderedRevision {
+                article_id,
+                revision,
+                render_options: &fingerprint,
+                html: &html,
+            })
+                .into(rendered_article_revisions::table)
+                .execute(self.db_connection)?;
+
+            // An included article may have changed while rendering. If its
+            // includers were invalidated before the rendering was stored,
+            // it must be removed again here.
+            if !self.render_cache.is_current(generation) {
+                diesel::delete(
+                    rendered_article_revisions::table
+                        .filter(rendered_article_revisions::article_id.eq(article_id))
+                        .filter(rendered_article_revisions::revision.eq(revision))
+                        .filter(rendered_article_revisions::render_options.eq(&fingerprint))
+                )
+                    .execute(self.db_connection)?;
+            }
+        }
+
+        self.render_cache.insert(generation, article_id, revision, html.clone());
+
+        Ok(html)
+    }
+
+    pub fn get_attachment(&self, name: String, revision: Option<i32>) -> Result<Option<models::Attachment>, Error> {
+        use schema::attachments;
+
+        let query = attachments::table
+            .filter(attachments::name.eq(name))
+            .into_boxed();
+
+        let query = match revision {
+            Some(revision) => query.filter(attachments::revision.eq(revision)),
+            None => query.filter(attachments::latest.eq(true)),
+        };
+
+        Ok(query
+            .first::<models::Attachment>(self.db_connection)
+            .optional()?)
+    }
+
+    /// Look up an attachment scaled down to fit the given width. Thumbnails
+    /// are generated on first request and stored in the database. Returns
+    /// the attachment along with the thumbnail width used, which is `None`
+    /// if the original is served.
+    pub fn get_resized_attachment(&self, name: String, revision: Option<i32>, width: u32)
+        -> Result<Option<(models::Attachment, Option<u32>)>, Error>
+    {
+        use schema::attachment_thumbnails;
+
+        let mut attachment = match self.get_attachment(name, revision)? {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let width = match thumbnail::thumbnail_width(width) {
+            Some(x) if thumbnail::is_resizable(&attachment.content_type) => x,
+            _ => return Ok(Some((attachment, None))),
+        };
+
+        let stored = attachment_thumbnails::table
+            .filter(attachment_thumbnails::attachment_sequence_number.eq(attachment.sequence_number))
+            .filter(attachment_thumbnails::width.eq(width as i32))
+            .select((attachment_thumbnails::content_type, attachment_thumbnails::data))
+            .first::<(String, Option<Vec<u8>>)>(self.db_connection)
+            .optional()?;
+
+        let thumbnail = match stored {
+            Some((content_type, data)) => data.map(|data| (data, content_type)),
+            None => {
+                let resized = thumbnail::resize(&attachment.data, width)?;
+
+                // Concurrent requests for the same thumbnail may both get
+                // here. They store the same thumbnail, so either may win.
+                diesel::insert_or_replace(&NewAttachmentThumbnail {
+                    attachment_sequence_number: attachment.sequence_number,
+                    width: width as i32,
+                    content_type: resized.as_ref().map_or(&attachment.content_type, |x| x.1),
+                    data: resized.as_ref().map(|x| &x.0[..]),
+                })
+                    .into(attachment_thumbnails::table)
+                    .execute(self.db_connection)?;
+
+                resized.map(|(data, content_type)| (data, content_type.to_owned()))
+            }
+        };
+
+        Ok(Some(match thumbnail {
+            Some((data, content_type)) => {
+                attachment.size = data.len() as i32;
+                attachment.data = data;
+                attachment.content_type = content_type;
+                (attachment, Some(width))
+            },
+            None => (attachment, None),
+        }))
+    }
+
+    pub fn get_article_attachments(&self, article_id: i32) -> Result<Vec<models::AttachmentStub>, Error> {
+        use schema::attachments;
+
+        Ok(attachments::table
+            .filter(attachments::article_id.eq(article_id))
+            .filter(attachments::latest.eq(true))
+            .order(attachments::name.asc())
+            .select((
+                attachments::sequence_number,
+                attachments::name,
+                attachments::revision,
+                attachments::created,
+                attachments::author,
+                attachments::article_id,
+                attachments::content_type,
+                attachments::size,
+                attachments::latest,
+            ))
+            .load::<models::AttachmentStub>(self.db_connection)?)
+    }
+
+    /// Add a file, or a new revision of the file with the same name. Names
+    /// are shared by the whole wiki, so a file attached to one article
+    /// cannot be replaced from another.
+    pub fn create_attachment(&self, filename: String, content_type: String, data: Vec<u8>, author: Option<String>, article_id: Option<i32>)
+        -> Result<AttachmentResult, Error>
+    {
+        let name = attachment_name(&filename);
+
+        self.db_connection.transaction(|| {
+            use schema::attachments;
+
+            let latest = attachments::table
+                .filter(attachments::name.eq(&name))
+                .filter(attachments::latest.eq(true))
+                .select((attachments::revision, attachments::article_id))
+                .first::<(i32, Option<i32>)>(self.db_connection)
+                .optional()?;
+
+            let latest_revision = match latest {
+                Some((_, Some(owner))) if Some(owner) != article_id =>
+                    return Ok(AttachmentResult::NameTaken),
+                Some((revision, _)) => Some(revision),
+                None => None,
+            };
+
+            if let Some(latest_revision) = latest_revision {
+                diesel::update(
+                    attachments::table
+                        .filter(attachments::name.eq(&name))
+                        .filter(attachments::revision.eq(latest_revision))
+                )
+                    .set(attachments::latest.eq(false))
+                    .execute(self.db_connection)?;
+            }
+
+            let revision = latest_revision.unwrap_or(0) + 1;
+
+            diesel::insert(&NewAttachment {
+                    name: &name,
+                    revision,
+                    author: author.as_ref().map(|x| &**x),
+                    article_id,
+                    content_type: &content_type,
+                    size: data.len() as i32,
+                    data: &data,
+                    latest: true,
+                })
+                .into(attachments::table)
+                .execute(self.db_connection)?;
+
+            Ok(AttachmentResult::Success(attachments::table
+                .filter(attachments::name.eq(&name))
+                .filter(attachments::revision.eq(revision))
+                .select((
+                    attachments::sequence_number,
+                    attachments::name,
+                    attachments::revision,
+                    attachments::created,
+                    attachments::author,
+                    attachments::article_id,
+                    attachments::content_type,
+                    attachments::size,
+                    attachments::latest,
+                ))
+                .first::<models::AttachmentStub>(self.db_connection)?))
+        })
+    }
+
     fn rebase_update(&self, article_id: i32, target_base_revision: i32, existing_base_revision: i32, title: String, body: String)
         -> Result<RebaseResult, Error>
     {
@@ -256,7 +743,7 @@ impl<'a> SyncState<'a> {
             Err("title cannot be empty")?;
         }
 
-        self.db_connection.transaction(|| {
+        let result = self.db_connection.transaction(|| {
             use schema::article_revisions;
 
             let (latest_revision, prev_title, prev_slug) = article_revisions::table
@@ -307,12 +794,27 @@ impl<'a> SyncState<'a> {
                 .into(article_revisions::table)
                 .execute(self.db_connection)?;
 
-            Ok(UpdateResult::Success(article_revisions::table
+            self.record_includes(article_id, &body)?;
+
+            let revision = article_revisions::table
                 .filter(article_revisions::article_id.eq(article_id))
                 .filter(article_revisions::revision.eq(new_revision))
-                .first::<models::ArticleRevision>(self.db_connection)?
-            ))
-        })
+                .first::<models::ArticleRevision>(self.db_connection)?;
+
+            self.queue_webhooks("update", &revision)?;
+
+            Ok(UpdateResult::Success(revision))
+        });
+
+        if let Ok(UpdateResult::Success(_)) = result {
+            // The change is committed, so report it as saved regardless
+            if let Err(err) = self.invalidate_includers(article_id) {
+                eprintln!("Unable to invalidate renderings including article {}: {}", article_id, err);
+            }
+        }
+
+        self.webhooks.notify();
+        result
     }
 
     pub fn create_article(&self, target_slug: Option<String>, title: String, body: String, author: Option<String>)
@@ -322,7 +824,7 @@ impl<'a> SyncState<'a> {
             Err("title cannot be empty")?;
         }
 
-        self.db_connection.transaction(|| {
+        let result = self.db_connection.transaction(|| {
             #[derive(Insertable)]
             #[table_name="articles"]
             struct NewArticle {
@@ -355,139 +857,930 @@ impl<'a> SyncState<'a> {
                 .into(article_revisions::table)
                 .execute(self.db_connection)?;
 
-            Ok(article_revisions::table
+            self.record_includes(article_id, &body)?;
+
+            let revision = article_revisions::table
                 .filter(article_revisions::article_id.eq(article_id))
                 .filter(article_revisions::revision.eq(new_revision))
-                .first::<models::ArticleRevision>(self.db_connection)?
-            )
-        })
+                .first::<models::ArticleRevision>(self.db_connection)?;
+
+            self.queue_webhooks("create", &revision)?;
+
+            Ok(revision)
+        });
+
+        if let Ok(ref revision) = result {
+            if let Err(err) = self.invalidate_includers(revision.article_id) {
+                eprintln!("Unable to invalidate renderings including article {}: {}", revision.article_id, err);
+            }
+        }
+
+        // Deliveries are picked up from the database, so only after commit
+        self.webhooks.notify();
+        result
     }
 
-    pub fn search_query(&self, query_string: String, limit: i32, offset: i32, snippet_size: i32) -> Result<Vec<models::SearchResult>, Error> {
-        use diesel::expression::sql_literal::sql;
-        use diesel::types::{Integer, Text};
+    /// Queue a delivery of `revision` to each webhook endpoint. Call within
+    /// the transaction making the change, so the two are committed together.
+    fn queue_webhooks(&self, event: &str, revision: &models::ArticleRevision) -> Result<(), Error> {
+        #[derive(Insertable)]
+        #[table_name="webhook_deliveries"]
+        struct NewDelivery<'a> {
+            url: &'a str,
+            payload: &'a str,
+        }
 
-        fn fts_quote(src: &str) -> String {
-            format!("\"{}\"", src.replace('\"', "\"\""))
+        if self.webhooks.urls.is_empty() {
+            return Ok(());
         }
 
-        let words = query_string
-            .split_whitespace()
-            .map(fts_quote)
-            .collect::<Vec<_>>();
+        let payload = self.webhooks.payload(event, revision);
 
-        let query = if words.len() > 1 {
-            format!("NEAR({})", words.join(" "))
-        } else if words.len() == 1 {
-            format!("{}*", words[0])
-        } else {
-            "\"\"".to_owned()
-        };
+        for url in &self.webhooks.urls {
+            diesel::insert(&NewDelivery { url, payload: &payload })
+                .into(webhook_deliveries::table)
+                .execute(self.db_connection)?;
+        }
 
-        Ok(
-            sql::<(Text, Text, Text)>(
-                "SELECT title, snippet(article_search, 1, '', '', '\u{2026}', ?), slug \
-                    FROM article_search \
-                    WHERE article_search MATCH ? \
-                    ORDER BY rank \
-                    LIMIT ? OFFSET ?"
-            )
-            .bind::<Integer, _>(snippet_size)
-            .bind::<Text, _>(query)
-            .bind::<Integer, _>(limit)
-            .bind::<Integer, _>(offset)
-            .load(self.db_connection)?)
+        Ok(())
     }
-}
 
-impl State {
-    pub fn new(connection_pool: Pool<ConnectionManager<SqliteConnection>>, cpu_pool: futures_cpupool::CpuPool) -> State {
-        State {
-            connection_pool,
-            cpu_pool,
-        }
+    pub fn get_due_webhook_deliveries(&self, limit: i32) -> Result<Vec<models::WebhookDelivery>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Bool, Nullable, Timestamp};
+
+        Ok(webhook_deliveries::table
+            .filter(webhook_deliveries::next_attempt.le(sql::<Nullable<Timestamp>>("datetime('now')")))
+            .filter(sql::<Bool>(
+                "NOT EXISTS (SELECT 1 FROM webhook_deliveries failed \
+                    WHERE failed.url = webhook_deliveries.url \
+                    AND failed.attempts > 0 AND failed.next_attempt > datetime('now'))"
+            ))
+            .order(webhook_deliveries::id.asc())
+            .limit(limit as i64)
+            .load::<models::WebhookDelivery>(self.db_connection)?)
     }
 
-    fn execute<F, T>(&self, f: F) -> CpuFuture<T, Error>
-    where
-        F: 'static + Sync + Send,
-        for <'a> F: FnOnce(SyncState<'a>) -> Result<T, Error>,
-        T: 'static + Send,
-    {
-        let connection_pool = self.connection_pool.clone();
+    /// Record the outcome of an attempt at a delivery, and schedule the next
+    /// attempt at failed deliveries until `webhooks::MAX_ATTEMPTS`
+    pub fn record_webhook_attempt(&self, id: i32, result: Result<String, String>) -> Result<(), Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
 
-        self.cpu_pool.spawn_fn(move || {
-            let db_connection = connection_pool.get()?;
+        match result {
+            Ok(status) => sql::<Integer>(
+                "UPDATE webhook_deliveries \
+                    SET attempts = attempts + 1, next_attempt = NULL, delivered = CURRENT_TIMESTAMP, last_result = ? \
+                    WHERE id = ?"
+            )
+                .bind::<Text, _>(status)
+                .bind::<Integer, _>(id)
+                .execute(self.db_connection)?,
+            Err(err) => sql::<Integer>(
+                "UPDATE webhook_deliveries \
+                    SET attempts = attempts + 1, last_result = ?, next_attempt = CASE \
+                        WHEN attempts + 1 >= ? THEN NULL \
+                        ELSE datetime('now', '+' || (1 << attempts) || ' minutes') \
+                    END \
+                    WHERE id = ?"
+            )
+                .bind::<Text, _>(err)
+                .bind::<Integer, _>(::webhooks::MAX_ATTEMPTS)
+                .bind::<Integer, _>(id)
+                .execute(self.db_connection)?,
+        };
 
-            f(SyncState::new(&*db_connection))
-        })
+        Ok(())
     }
 
-    pub fn get_article_slug(&self, article_id: i32) -> CpuFuture<Option<String>, Error> {
-        self.execute(move |state| state.get_article_slug(article_id))
-    }
+    /// Watch an article. The notification cursor of a new watcher starts at
+    /// the latest change, so earlier changes are not emailed.
+    pub fn watch_article(&self, identity: String, article_id: i32) -> Result<(), Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
 
-    pub fn get_article_revision(&self, article_id: i32, revision: i32) -> CpuFuture<Option<models::ArticleRevision>, Error> {
-        self.execute(move |state| state.get_article_revision(article_id, revision))
-    }
+        self.db_connection.transaction(|| {
+            sql::<Integer>("INSERT OR IGNORE INTO watches (identity, article_id) VALUES (?, ?)")
+                .bind::<Text, _>(identity.clone())
+                .bind::<Integer, _>(article_id)
+                .execute(self.db_connection)?;
 
-    pub fn query_article_revision_stubs<F>(&self, f: F) -> CpuFuture<Vec<models::ArticleRevisionStub>, Error>
-    where
-        F: 'static + Send + Sync,
-        for <'a> F:
-            FnOnce(article_revisions::BoxedQuery<'a, diesel::sqlite::Sqlite>) ->
-                article_revisions::BoxedQuery<'a, diesel::sqlite::Sqlite>,
-    {
-        self.execute(move |state| state.query_article_revision_stubs(f))
-    }
+            sql::<Integer>(
+                "INSERT OR IGNORE INTO notification_cursors (identity, sequence_number) \
+                    SELECT ?, COALESCE(MAX(sequence_number), 0) FROM article_revisions"
+            )
+                .bind::<Text, _>(identity.clone())
+                .execute(self.db_connection)?;
 
-    pub fn get_latest_article_revision_stubs(&self) -> CpuFuture<Vec<models::ArticleRevisionStub>, Error> {
-        self.query_article_revision_stubs(|query| {
-            query
-                .filter(article_revisions::latest.eq(true))
-                .order(article_revisions::title.asc())
+            Ok(())
         })
     }
 
-    pub fn lookup_slug(&self, slug: String) -> CpuFuture<SlugLookup, Error> {
-        self.execute(move |state| state.lookup_slug(slug))
-    }
-
-    pub fn update_article(&self, article_id: i32, base_revision: i32, title: String, body: String, author: Option<String>)
-        -> CpuFuture<UpdateResult, Error>
-    {
-        self.execute(move |state| state.update_article(article_id, base_revision, title, body, author))
-    }
+    pub fn unwatch_article(&self, identity: String, article_id: i32) -> Result<(), Error> {
+        diesel::delete(
+            watches::table
+                .filter(watches::identity.eq(identity))
+                .filter(watches::article_id.eq(article_id))
+        )
+            .execute(self.db_connection)?;
 
-    pub fn create_article(&self, target_slug: Option<String>, title: String, body: String, author: Option<String>)
-        -> CpuFuture<models::ArticleRevision, Error>
-    {
-        self.execute(move |state| state.create_article(target_slug, title, body, author))
+        Ok(())
     }
 
-    pub fn search_query(&self, query_string: String, limit: i32, offset: i32, snippet_size: i32) -> CpuFuture<Vec<models::SearchResult>, Error> {
-        self.execute(move |state| state.search_query(query_string, limit, offset, snippet_size))
+    pub fn is_watching(&self, identity: String, article_id: i32) -> Result<bool, Error> {
+        Ok(watches::table
+            .filter(watches::identity.eq(identity))
+            .filter(watches::article_id.eq(article_id))
+            .count()
+            .first::<i64>(self.db_connection)? != 0)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use db;
+    pub fn get_watched_articles(&self, identity: String) -> Result<Vec<models::WatchedArticle>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
 
-    impl UpdateResult {
-        pub fn unwrap(self) -> models::ArticleRevision {
-            match self {
-                UpdateResult::Success(x) => x,
-                _ => panic!("Expected success")
-            }
-        }
+        Ok(sql::<(Integer, Text, Text)>(
+            "SELECT r.article_id, r.title, r.slug FROM watches w \
+                JOIN article_revisions r ON r.article_id = w.article_id AND r.latest = 1 \
+                WHERE w.identity = ? \
+                ORDER BY r.title COLLATE NOCASE"
+        )
+            .bind::<Text, _>(identity)
+            .load::<models::WatchedArticle>(self.db_connection)?)
     }
 
-    macro_rules! init {
+    /// The changes to watched articles since each identity was last
+    /// notified, at most `limit` per identity. Changes an identity made
+    /// itself are left out.
+    pub fn get_notification_digests(&self, limit: i32) -> Result<Vec<models::NotificationDigest>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
+
+        self.db_connection.transaction(|| {
+            let pending = sql::<(Text, Integer, Integer, Integer)>(
+                "SELECT c.identity, r.sequence_number, r.article_id, r.revision \
+                    FROM notification_cursors c \
+                    JOIN watches w ON w.identity = c.identity \
+                    JOIN article_revisions r ON r.article_id = w.article_id AND r.sequence_number > c.sequence_number \
+                    WHERE r.author IS NULL OR r.author != c.identity \
+                    ORDER BY c.identity, r.sequence_number"
+            )
+                .load::<(String, i32, i32, i32)>(self.db_connection)?;
+
+            let mut grouped: Vec<(String, i32, Vec<(i32, i32)>)> = Vec::new();
+            for (identity, sequence_number, article_id, revision) in pending {
+                let is_same_identity = grouped.last().map(|x| x.0 == identity).unwrap_or(false);
+                if !is_same_identity {
+                    grouped.push((identity, sequence_number, vec![]));
+                }
+
+                let digest = grouped.last_mut().expect("Pushed above");
+                if digest.2.len() < limit as usize {
+                    digest.1 = sequence_number;
+                    digest.2.push((article_id, revision));
+                }
+            }
+
+            grouped.into_iter()
+                .map(|(identity, up_to, revisions)| Ok(models::NotificationDigest {
+                    identity,
+                    changes: self.get_revisions_with_previous(revisions)?,
+                    up_to,
+                }))
+                .collect()
+        })
+    }
+
+    pub fn advance_notification_cursor(&self, identity: String, sequence_number: i32) -> Result<(), Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
+
+        sql::<Integer>(
+            "UPDATE notification_cursors SET sequence_number = MAX(sequence_number, ?) WHERE identity = ?"
+        )
+            .bind::<Integer, _>(sequence_number)
+            .bind::<Text, _>(identity)
+            .execute(self.db_connection)?;
+
+        Ok(())
+    }
+
+    /// Full text search. Matches in the title weigh `title_boost` times as
+    /// much as matches in the body, and articles with the query as their
+    /// exact title or slug come first.
+    pub fn search_query(&self, query_string: String, filters: &search_query::Filters, limit: i32, offset: i32, snippet_size: i32, title_boost: f64) -> Result<Vec<models::SearchResult>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Double, Integer, Nullable, Text, Timestamp};
+
+        let query = match search_query::compile(&query_string) {
+            Some(x) => x,
+            None => return Ok(vec![]),
+        };
+
+        let exact_title = search_query::plain_text(&query_string);
+        let exact_slug = ::slug::slugify(&exact_title);
+
+        use models::{HIGHLIGHT_START, HIGHLIGHT_END};
+
+        let (author, after, before, namespace) = filter_parameters(filters);
+
+        let rows = sql::<(Text, Text, Text)>(&format!(
+            "SELECT highlight(article_search, 0, ?, ?), snippet(article_search, 1, ?, ?, '\u{2026}', ?), article_search.slug \
+                FROM article_search \
+                JOIN article_revisions r ON r.slug = article_search.slug AND r.latest = 1 \
+                WHERE article_search MATCH ? {} \
+                ORDER BY (article_search.title = ? COLLATE NOCASE OR article_search.slug = ?) DESC, bm25(article_search, ?, 1.0) \
+                LIMIT ? OFFSET ?",
+            FILTER_SQL
+        ))
+            .bind::<Text, _>(HIGHLIGHT_START.to_string())
+            .bind::<Text, _>(HIGHLIGHT_END.to_string())
+            .bind::<Text, _>(HIGHLIGHT_START.to_string())
+            .bind::<Text, _>(HIGHLIGHT_END.to_string())
+            .bind::<Integer, _>(snippet_size)
+            .bind::<Text, _>(query)
+            .bind::<Nullable<Text>, _>(author.clone())
+            .bind::<Nullable<Text>, _>(author)
+            .bind::<Nullable<Timestamp>, _>(after)
+            .bind::<Nullable<Timestamp>, _>(after)
+            .bind::<Nullable<Timestamp>, _>(before)
+            .bind::<Nullable<Timestamp>, _>(before)
+            .bind::<Nullable<Text>, _>(namespace.clone())
+            .bind::<Nullable<Text>, _>(namespace)
+            .bind::<Text, _>(exact_title)
+            .bind::<Text, _>(exact_slug)
+            .bind::<Double, _>(title_boost)
+            .bind::<Integer, _>(limit)
+            .bind::<Integer, _>(offset)
+            .load::<(String, String, String)>(self.db_connection)?;
+
+        Ok(rows.into_iter()
+            .map(|(title, snippet, slug)| models::SearchResult::from_marked(&title, &snippet, slug))
+            .collect())
+    }
+
+    /// Counts of all the hits of a search, by facet
+    pub fn search_facets(&self, query_string: String, filters: &search_query::Filters) -> Result<models::SearchFacets, Error> {
+        use chrono::Datelike;
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Nullable, Text, Timestamp};
+
+        let query = match search_query::compile(&query_string) {
+            Some(x) => x,
+            None => return Ok(models::SearchFacets::default()),
+        };
+
+        let (author, after, before, namespace) = filter_parameters(filters);
+
+        let rows = sql::<(Nullable<Text>, Text, Timestamp)>(&format!(
+            "SELECT r.author, r.title, r.created \
+                FROM article_search \
+                JOIN article_revisions r ON r.slug = article_search.slug AND r.latest = 1 \
+                WHERE article_search MATCH ? {} \
+                ORDER BY r.sequence_number",
+            FILTER_SQL
+        ))
+            .bind::<Text, _>(query)
+            .bind::<Nullable<Text>, _>(author.clone())
+            .bind::<Nullable<Text>, _>(author)
+            .bind::<Nullable<Timestamp>, _>(after)
+            .bind::<Nullable<Timestamp>, _>(after)
+            .bind::<Nullable<Timestamp>, _>(before)
+            .bind::<Nullable<Timestamp>, _>(before)
+            .bind::<Nullable<Text>, _>(namespace.clone())
+            .bind::<Nullable<Text>, _>(namespace)
+            .load::<(Option<String>, String, ::chrono::NaiveDateTime)>(self.db_connection)?;
+
+        let mut authors = BTreeMap::new();
+        let mut namespaces = BTreeMap::new();
+        let mut years = BTreeMap::new();
+
+        for &(ref author, ref title, ref created) in &rows {
+            if let &Some(ref author) = author {
+                *authors.entry(author.clone()).or_insert(0) += 1;
+            }
+            if let Some(namespace) = models::namespace(title) {
+                // Namespaces are case insensitive, like the filter
+                namespaces.entry(namespace.to_lowercase())
+                    .or_insert((namespace.to_owned(), 0))
+                    .1 += 1;
+            }
+            *years.entry(created.year().to_string()).or_insert(0) += 1;
+        }
+
+        Ok(models::SearchFacets {
+            authors: facet_counts(authors.into_iter().collect()),
+            namespaces: facet_counts(namespaces.into_iter().map(|(_, x)| x).collect()),
+            years: facet_counts(years.into_iter().collect()),
+        })
+    }
+
+    /// Titles of current articles for autocompletion. Titles or slugs
+    /// starting with `prefix` come first, most recently changed first,
+    /// then titles with words starting with the words of `prefix`, best
+    /// match first.
+    pub fn get_titles_by_prefix(&self, prefix: String, limit: i32) -> Result<Vec<models::ArticleTitle>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
+
+        let prefix = prefix.trim();
+        if prefix.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let like_pattern = format!(
+            "{}%",
+            prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+        );
+        // Slugs only contain characters that are not special to GLOB
+        let slug_pattern = format!("{}*", ::slug::slugify(prefix));
+
+        let mut titles = sql::<(Text, Text)>(TITLE_PREFIX_SQL)
+            .bind::<Text, _>(like_pattern)
+            .bind::<Text, _>(slug_pattern)
+            .bind::<Integer, _>(limit)
+            .load::<models::ArticleTitle>(self.db_connection)?;
+
+        if titles.len() < limit as usize {
+            if let Some(query) = search_query::title_word_prefixes(prefix) {
+                let word_matches = sql::<(Text, Text)>(TITLE_WORD_PREFIX_SQL)
+                    .bind::<Text, _>(query)
+                    .bind::<Integer, _>(limit)
+                    .load::<models::ArticleTitle>(self.db_connection)?;
+
+                for title in word_matches {
+                    if titles.len() == limit as usize {
+                        break;
+                    }
+                    if !titles.contains(&title) {
+                        titles.push(title);
+                    }
+                }
+            }
+        }
+
+        Ok(titles)
+    }
+
+    /// Current articles with a title or slug similar to `text`, best match
+    /// first, for when `text` does not match any article exactly
+    pub fn suggest_titles(&self, text: String, limit: usize) -> Result<Vec<models::ArticleTitle>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
+
+        let query = ::slug::slugify(&text);
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // Only score the titles with a length that can match, which the
+        // indexes slug_length_lookup and title_length_lookup find quickly
+        let (shortest, longest) = fuzzy::candidate_lengths(&query);
+
+        let titles = sql::<(Text, Text)>(
+            "SELECT title, slug FROM article_revisions WHERE sequence_number IN ( \
+                    SELECT sequence_number FROM article_revisions \
+                        WHERE latest = 1 AND length(slug) BETWEEN ? AND ? \
+                    UNION SELECT sequence_number FROM article_revisions \
+                        WHERE latest = 1 AND length(title) BETWEEN ? AND ? \
+                )"
+        )
+            .bind::<Integer, _>(shortest as i32)
+            .bind::<Integer, _>(longest as i32)
+            .bind::<Integer, _>(shortest as i32)
+            .bind::<Integer, _>(longest as i32)
+            .load::<models::ArticleTitle>(self.db_connection)?;
+
+        let mut scored = titles.into_iter()
+            .filter_map(|x| {
+                let slug_score = fuzzy::score(&query, &x.slug);
+                let title_score = fuzzy::score(&query, &::slug::slugify(&x.title));
+                let score = match (slug_score, title_score) {
+                    (Some(a), Some(b)) => Some(::std::cmp::min(a, b)),
+                    (a, b) => a.or(b),
+                };
+                score.map(|score| (score, x))
+            })
+            .collect::<Vec<_>>();
+
+        scored.sort_by(|a, b| a.0.cmp(&b.0));
+        scored.truncate(limit);
+
+        Ok(scored.into_iter().map(|(_, x)| x).collect())
+    }
+
+    /// Everything about the contributions of the given author, `limit` of
+    /// each list. `None` if they have not made any edits and have no
+    /// profile article.
+    pub fn get_user_contributions(&self, author: String, limit: i32) -> Result<Option<models::UserContributions>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{BigInt, Integer, Nullable, Text, Timestamp};
+
+        let (edits, first_edit, last_edit) = sql::<(BigInt, Nullable<Timestamp>, Nullable<Timestamp>)>(
+            "SELECT COUNT(*), MIN(created), MAX(created) FROM article_revisions WHERE author = ?"
+        )
+            .bind::<Text, _>(author.clone())
+            .get_result::<(i64, Option<::chrono::NaiveDateTime>, Option<::chrono::NaiveDateTime>)>(self.db_connection)?;
+
+        // Slugs are derived from titles, so the slug index narrows this down
+        // to the few articles with similar titles. The oldest one wins if
+        // several have the same title.
+        let profile_title = models::profile_title(&author);
+        let profile = sql::<(Integer, Integer)>(
+            "SELECT article_id, revision FROM article_revisions \
+                WHERE latest = 1 AND slug GLOB ? AND title = ? \
+                ORDER BY article_id \
+                LIMIT 1"
+        )
+            // Slugs only contain characters that are not special to GLOB
+            .bind::<Text, _>(format!("{}*", ::slug::slugify(&profile_title)))
+            .bind::<Text, _>(profile_title)
+            .get_result::<(i32, i32)>(self.db_connection)
+            .optional()?;
+        let profile = match profile {
+            Some((article_id, revision)) => self.get_article_revision(article_id, revision)?,
+            None => None,
+        };
+
+        if edits == 0 && profile.is_none() {
+            return Ok(None);
+        }
+
+        let most_edited = sql::<(Text, Text, BigInt)>(
+            "SELECT r.title, r.slug, counts.revisions FROM ( \
+                    SELECT article_id, COUNT(*) AS revisions FROM article_revisions \
+                        WHERE author = ? GROUP BY article_id \
+                ) counts \
+                JOIN article_revisions r ON r.article_id = counts.article_id AND r.latest = 1 \
+                ORDER BY counts.revisions DESC, r.created DESC \
+                LIMIT ?"
+        )
+            .bind::<Text, _>(author.clone())
+            .bind::<Integer, _>(limit)
+            .load::<models::EditedArticle>(self.db_connection)?;
+
+        let recent = self.query_article_revision_stubs(move |query| {
+            query
+                .filter(article_revisions::author.eq(author))
+                .order(article_revisions::sequence_number.desc())
+                .limit(limit as i64)
+        })?;
+
+        Ok(Some(models::UserContributions {
+            edits,
+            first_edit,
+            last_edit,
+            most_edited,
+            recent,
+            profile,
+        }))
+    }
+
+    pub fn get_stats(&self) -> Result<models::Stats, Error> {
+        use chrono::{Datelike, NaiveDate, Utc};
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{BigInt, Integer, Text};
+
+        let (articles, revisions) = sql::<(BigInt, BigInt)>(
+            "SELECT COALESCE(SUM(latest), 0), COUNT(*) FROM article_revisions"
+        )
+            .get_result::<(i64, i64)>(self.db_connection)?;
+
+        let today = Utc::today().naive_utc();
+
+        // Count edits by the first day of each period in SQL, then fill in
+        // the periods without edits
+        let edits_per = |period_start: &str, first: NaiveDate, days: i64| -> Result<Vec<(NaiveDate, i64)>, Error> {
+            let counts = sql::<(Text, BigInt)>(&format!(
+                "SELECT {0}, COUNT(*) FROM article_revisions \
+                    WHERE created >= ? \
+                    GROUP BY {0}",
+                period_start
+            ))
+                .bind::<Text, _>(first.format("%Y-%m-%d").to_string())
+                .load::<(String, i64)>(self.db_connection)?
+                .into_iter()
+                .map(|(date, count)| Ok((NaiveDate::parse_from_str(&date, "%Y-%m-%d")?, count)))
+                .collect::<Result<BTreeMap<_, _>, Error>>()?;
+
+            let mut periods = Vec::new();
+            let mut date = first;
+            while date <= today {
+                periods.push((date, counts.get(&date).cloned().unwrap_or(0)));
+                date = date + ::chrono::Duration::days(days);
+            }
+            Ok(periods)
+        };
+
+        let edits_per_day = edits_per(
+            "date(created)",
+            today - ::chrono::Duration::days(STATS_DAYS - 1),
+            1
+        )?;
+        let this_monday = today - ::chrono::Duration::days(today.weekday().num_days_from_monday() as i64);
+        let edits_per_week = edits_per(
+            "date(created, 'weekday 0', '-6 days')",
+            this_monday - ::chrono::Duration::weeks(STATS_WEEKS - 1),
+            7
+        )?;
+
+        let top_contributors = sql::<(Text, BigInt)>(
+            "SELECT author, COUNT(*) AS edits FROM article_revisions \
+                WHERE author IS NOT NULL \
+                GROUP BY author \
+                ORDER BY edits DESC, MAX(sequence_number) DESC \
+                LIMIT ?"
+        )
+            .bind::<Integer, _>(STATS_LIMIT)
+            .load::<models::AuthorEdits>(self.db_connection)?;
+
+        let largest = sql::<(Text, Text, BigInt)>(
+            "SELECT title, slug, length(CAST(body AS BLOB)) AS bytes FROM article_revisions \
+                WHERE latest = 1 \
+                ORDER BY bytes DESC, sequence_number DESC \
+                LIMIT ?"
+        )
+            .bind::<Integer, _>(STATS_LIMIT)
+            .load::<models::ArticleSize>(self.db_connection)?;
+
+        // The growth of each article since the last revision before the
+        // period, or since nothing for articles created during it
+        let fastest_growing = sql::<(Text, Text, BigInt)>(
+            "SELECT r.title, r.slug, length(CAST(r.body AS BLOB)) - COALESCE(( \
+                    SELECT length(CAST(o.body AS BLOB)) FROM article_revisions o \
+                        WHERE o.article_id = r.article_id AND o.created < datetime('now', '-1 month') \
+                        ORDER BY o.revision DESC \
+                        LIMIT 1 \
+                ), 0) AS growth \
+                FROM article_revisions r \
+                WHERE r.latest = 1 AND r.created >= datetime('now', '-1 month') AND growth > 0 \
+                ORDER BY growth DESC, r.sequence_number DESC \
+                LIMIT ?"
+        )
+            .bind::<Integer, _>(STATS_LIMIT)
+            .load::<models::ArticleSize>(self.db_connection)?;
+
+        Ok(models::Stats {
+            articles,
+            revisions,
+            edits_per_day,
+            edits_per_week,
+            top_contributors,
+            largest,
+            fastest_growing,
+        })
+    }
+
+    /// Recently changed, most edited and randomly picked current articles,
+    /// `limit` of each
+    pub fn get_search_front_page(&self, limit: i32) -> Result<models::SearchFrontPage, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{BigInt, Integer, Text};
+
+        let recent = article_revisions::table
+            .filter(article_revisions::latest.eq(true))
+            .order((article_revisions::created.desc(), article_revisions::sequence_number.desc()))
+            .limit(limit as i64)
+            .select((article_revisions::title, article_revisions::slug))
+            .load::<models::ArticleTitle>(self.db_connection)?;
+
+        let most_edited = sql::<(Text, Text, BigInt)>(
+            "SELECT r.title, r.slug, counts.revisions FROM ( \
+                    SELECT article_id, COUNT(*) AS revisions FROM article_revisions GROUP BY article_id \
+                ) counts \
+                JOIN article_revisions r ON r.article_id = counts.article_id AND r.latest = 1 \
+                ORDER BY counts.revisions DESC, r.created DESC \
+                LIMIT ?"
+        )
+            .bind::<Integer, _>(limit)
+            .load::<models::EditedArticle>(self.db_connection)?;
+
+        let random = sql::<(Text, Text)>(
+            "SELECT title, slug FROM article_revisions WHERE latest = 1 ORDER BY RANDOM() LIMIT ?"
+        )
+            .bind::<Integer, _>(limit)
+            .load::<models::ArticleTitle>(self.db_connection)?;
+
+        Ok(models::SearchFrontPage { recent, most_edited, random })
+    }
+
+    /// Full text search in all revisions, most recent first
+    pub fn search_history_query(&self, query_string: String, filters: &search_query::Filters, limit: i32, offset: i32, snippet_size: i32) -> Result<Vec<models::RevisionSearchResult>, Error> {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Nullable, Text, Timestamp};
+        use models::{HIGHLIGHT_START, HIGHLIGHT_END};
+
+        let query = match search_query::compile(&query_string) {
+            Some(x) => x,
+            None => return Ok(vec![]),
+        };
+
+        let (author, after, before, namespace) = filter_parameters(filters);
+
+        let rows = sql::<(Integer, Integer, Timestamp, Nullable<Text>, Text, Text)>(&format!(
+            "SELECT r.article_id, r.revision, r.created, r.author, \
+                    highlight(article_revision_search, 0, ?, ?), \
+                    snippet(article_revision_search, 1, ?, ?, '\u{2026}', ?) \
+                FROM article_revision_search \
+                JOIN article_revisions r ON r.sequence_number = article_revision_search.rowid \
+                WHERE article_revision_search MATCH ? {} \
+                ORDER BY r.sequence_number DESC \
+                LIMIT ? OFFSET ?",
+            FILTER_SQL
+        ))
+            .bind::<Text, _>(HIGHLIGHT_START.to_string())
+            .bind::<Text, _>(HIGHLIGHT_END.to_string())
+            .bind::<Text, _>(HIGHLIGHT_START.to_string())
+            .bind::<Text, _>(HIGHLIGHT_END.to_string())
+            .bind::<Integer, _>(snippet_size)
+            .bind::<Text, _>(query)
+            .bind::<Nullable<Text>, _>(author.clone())
+            .bind::<Nullable<Text>, _>(author)
+            .bind::<Nullable<Timestamp>, _>(after)
+            .bind::<Nullable<Timestamp>, _>(after)
+            .bind::<Nullable<Timestamp>, _>(before)
+            .bind::<Nullable<Timestamp>, _>(before)
+            .bind::<Nullable<Text>, _>(namespace.clone())
+            .bind::<Nullable<Text>, _>(namespace)
+            .bind::<Integer, _>(limit)
+            .bind::<Integer, _>(offset)
+            .load::<(i32, i32, ::chrono::NaiveDateTime, Option<String>, String, String)>(self.db_connection)?;
+
+        Ok(rows.into_iter()
+            .map(|(article_id, revision, created, author, title, snippet)|
+                models::RevisionSearchResult::from_marked(article_id, revision, created, author, &title, &snippet)
+            )
+            .collect())
+    }
+}
+
+impl<'a> rendering::IncludeSource for SyncState<'a> {
+    fn lookup_include(&self, slug: &str) -> Result<Option<String>, Error> {
+        use schema::article_revisions;
+
+        let slug = match self.lookup_slug(slug.to_owned())? {
+            SlugLookup::Miss => return Ok(None),
+            SlugLookup::Hit { .. } => slug.to_owned(),
+            SlugLookup::Redirect(slug) => slug,
+        };
+
+        Ok(article_revisions::table
+            .filter(article_revisions::slug.eq(slug))
+            .filter(article_revisions::latest.eq(true))
+            .select(article_revisions::body)
+            .first::<String>(self.db_connection)
+            .optional()?)
+    }
+}
+
+impl State {
+    pub fn new(connection_pool: Pool<ConnectionManager<SqliteConnection>>, cpu_pool: futures_cpupool::CpuPool, render_options: rendering::RenderOptions, render_cache: RenderCache, search_title_boost: f64, webhooks: Arc<Webhooks>, change_listeners: Arc<ChangeListeners>) -> State {
+        State {
+            connection_pool,
+            cpu_pool,
+            render_options,
+            render_cache: Arc::new(render_cache),
+            search_title_boost,
+            stats_cache: Arc::new(Mutex::new(None)),
+            webhooks,
+            change_listeners,
+        }
+    }
+
+    fn execute<F, T>(&self, f: F) -> CpuFuture<T, Error>
+    where
+        F: 'static + Sync + Send,
+        for <'a> F: FnOnce(SyncState<'a>) -> Result<T, Error>,
+        T: 'static + Send,
+    {
+        let connection_pool = self.connection_pool.clone();
+        let render_cache = self.render_cache.clone();
+        let webhooks = self.webhooks.clone();
+
+        self.cpu_pool.spawn_fn(move || {
+            let db_connection = connection_pool.get()?;
+
+            f(SyncState::new(&*db_connection, &render_cache, &webhooks))
+        })
+    }
+
+    pub fn get_article_slug(&self, article_id: i32) -> CpuFuture<Option<String>, Error> {
+        self.execute(move |state| state.get_article_slug(article_id))
+    }
+
+    pub fn get_article_revision(&self, article_id: i32, revision: i32) -> CpuFuture<Option<models::ArticleRevision>, Error> {
+        self.execute(move |state| state.get_article_revision(article_id, revision))
+    }
+
+    pub fn query_article_revision_stubs<F>(&self, f: F) -> CpuFuture<Vec<models::ArticleRevisionStub>, Error>
+    where
+        F: 'static + Send + Sync,
+        for <'a> F:
+            FnOnce(article_revisions::BoxedQuery<'a, diesel::sqlite::Sqlite>) ->
+                article_revisions::BoxedQuery<'a, diesel::sqlite::Sqlite>,
+    {
+        self.execute(move |state| state.query_article_revision_stubs(f))
+    }
+
+    pub fn get_revisions_with_previous(&self, revisions: Vec<(i32, i32)>)
+        -> CpuFuture<Vec<(models::ArticleRevision, Option<models::ArticleRevision>)>, Error>
+    {
+        self.execute(move |state| state.get_revisions_with_previous(revisions))
+    }
+
+    pub fn get_revision_sizes(&self, revisions: Vec<(i32, i32)>) -> CpuFuture<BTreeMap<(i32, i32), i64>, Error> {
+        self.execute(move |state| state.get_revision_sizes(revisions))
+    }
+
+    pub fn get_latest_article_revision_stubs(&self) -> CpuFuture<Vec<models::ArticleRevisionStub>, Error> {
+        self.query_article_revision_stubs(|query| {
+            query
+                .filter(article_revisions::latest.eq(true))
+                .order(article_revisions::title.asc())
+        })
+    }
+
+    pub fn lookup_slug(&self, slug: String) -> CpuFuture<SlugLookup, Error> {
+        self.execute(move |state| state.lookup_slug(slug))
+    }
+
+    pub fn update_article(&self, article_id: i32, base_revision: i32, title: String, body: String, author: Option<String>)
+        -> CpuFuture<UpdateResult, Error>
+    {
+        let change_listeners = self.change_listeners.clone();
+
+        self.execute(move |state| {
+            let result = state.update_article(article_id, base_revision, title, body, author);
+            change_listeners.notify();
+            result
+        })
+    }
+
+    pub fn create_article(&self, target_slug: Option<String>, title: String, body: String, author: Option<String>)
+        -> CpuFuture<models::ArticleRevision, Error>
+    {
+        let change_listeners = self.change_listeners.clone();
+
+        self.execute(move |state| {
+            let result = state.create_article(target_slug, title, body, author);
+            change_listeners.notify();
+            result
+        })
+    }
+
+    /// Receive a signal after every attempt at changing an article and
+    /// periodically to keep the connection alive. Stop listening by dropping
+    /// the receiver.
+    pub fn listen_for_changes(&self) -> UnboundedReceiver<Signal> {
+        self.change_listeners.listen()
+    }
+
+    /// Run `f` to completion on the CPU pool without waiting for the result
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: Future<Item=(), Error=()> + Send + 'static,
+    {
+        self.cpu_pool.spawn(f).forget();
+    }
+
+    pub fn watch_article(&self, identity: String, article_id: i32) -> CpuFuture<(), Error> {
+        self.execute(move |state| state.watch_article(identity, article_id))
+    }
+
+    pub fn unwatch_article(&self, identity: String, article_id: i32) -> CpuFuture<(), Error> {
+        self.execute(move |state| state.unwatch_article(identity, article_id))
+    }
+
+    pub fn is_watching(&self, identity: String, article_id: i32) -> CpuFuture<bool, Error> {
+        self.execute(move |state| state.is_watching(identity, article_id))
+    }
+
+    pub fn get_watched_articles(&self, identity: String) -> CpuFuture<Vec<models::WatchedArticle>, Error> {
+        self.execute(move |state| state.get_watched_articles(identity))
+    }
+
+    pub fn get_notification_digests(&self, limit: i32) -> CpuFuture<Vec<models::NotificationDigest>, Error> {
+        self.execute(move |state| state.get_notification_digests(limit))
+    }
+
+    pub fn advance_notification_cursor(&self, identity: String, sequence_number: i32) -> CpuFuture<(), Error> {
+        self.execute(move |state| state.advance_notification_cursor(identity, sequence_number))
+    }
+
+    pub fn get_due_webhook_deliveries(&self, limit: i32) -> CpuFuture<Vec<models::WebhookDelivery>, Error> {
+        self.execute(move |state| state.get_due_webhook_deliveries(limit))
+    }
+
+    pub fn record_webhook_attempt(&self, id: i32, result: Result<String, String>) -> CpuFuture<(), Error> {
+        self.execute(move |state| state.record_webhook_attempt(id, result))
+    }
+
+    pub fn search_query(&self, query_string: String, filters: search_query::Filters, limit: i32, offset: i32, snippet_size: i32) -> CpuFuture<Vec<models::SearchResult>, Error> {
+        let title_boost = self.search_title_boost;
+        self.execute(move |state| state.search_query(query_string, &filters, limit, offset, snippet_size, title_boost))
+    }
+
+    pub fn search_facets(&self, query_string: String, filters: search_query::Filters) -> CpuFuture<models::SearchFacets, Error> {
+        self.execute(move |state| state.search_facets(query_string, &filters))
+    }
+
+    pub fn get_titles_by_prefix(&self, prefix: String, limit: i32) -> CpuFuture<Vec<models::ArticleTitle>, Error> {
+        self.execute(move |state| state.get_titles_by_prefix(prefix, limit))
+    }
+
+    pub fn suggest_titles(&self, text: String, limit: usize) -> CpuFuture<Vec<models::ArticleTitle>, Error> {
+        self.execute(move |state| state.suggest_titles(text, limit))
+    }
+
+    pub fn get_user_contributions(&self, author: String, limit: i32) -> CpuFuture<Option<models::UserContributions>, Error> {
+        self.execute(move |state| state.get_user_contributions(author, limit))
+    }
+
+    /// Statistics, computed at most once per `STATS_CACHE_DURATION`
+    pub fn get_stats(&self) -> Box<Future<Item=Arc<models::Stats>, Error=Error>> {
+        if let Some((ref computed, ref stats)) = *self.stats_cache.lock().unwrap() {
+            if computed.elapsed() < STATS_CACHE_DURATION {
+                return Box::new(futures::finished(stats.clone()));
+            }
+        }
+
+        let stats_cache = self.stats_cache.clone();
+        Box::new(self.execute(move |state| state.get_stats())
+            .map(move |stats| {
+                let stats = Arc::new(stats);
+                *stats_cache.lock().unwrap() = Some((Instant::now(), stats.clone()));
+                stats
+            }))
+    }
+
+    pub fn get_search_front_page(&self, limit: i32) -> CpuFuture<models::SearchFrontPage, Error> {
+        self.execute(move |state| state.get_search_front_page(limit))
+    }
+
+    pub fn search_history_query(&self, query_string: String, filters: search_query::Filters, limit: i32, offset: i32, snippet_size: i32) -> CpuFuture<Vec<models::RevisionSearchResult>, Error> {
+        self.execute(move |state| state.search_history_query(query_string, &filters, limit, offset, snippet_size))
+    }
+
+    pub fn get_attachment(&self, name: String, revision: Option<i32>) -> CpuFuture<Option<models::Attachment>, Error> {
+        self.execute(move |state| state.get_attachment(name, revision))
+    }
+
+    pub fn get_resized_attachment(&self, name: String, revision: Option<i32>, width: u32)
+        -> CpuFuture<Option<(models::Attachment, Option<u32>)>, Error>
+    {
+        self.execute(move |state| state.get_resized_attachment(name, revision, width))
+    }
+
+    pub fn get_article_attachments(&self, article_id: i32) -> CpuFuture<Vec<models::AttachmentStub>, Error> {
+        self.execute(move |state| state.get_article_attachments(article_id))
+    }
+
+    pub fn create_attachment(&self, filename: String, content_type: String, data: Vec<u8>, author: Option<String>, article_id: Option<i32>)
+        -> CpuFuture<AttachmentResult, Error>
+    {
+        self.execute(move |state| state.create_attachment(filename, content_type, data, author, article_id))
+    }
+
+    pub fn render_article(&self, article_id: i32, revision: i32, body: String) -> CpuFuture<String, Error> {
+        let options = self.render_options;
+        self.execute(move |state| state.render_article(article_id, revision, &body, options))
+    }
+
+    pub fn render_markdown(&self, src: String) -> CpuFuture<String, Error> {
+        let options = self.render_options;
+        self.execute(move |state| rendering::render_markdown(&src, &state, options))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use db;
+
+    impl UpdateResult {
+        pub fn unwrap(self) -> models::ArticleRevision {
+            match self {
+                UpdateResult::Success(x) => x,
+                _ => panic!("Expected success")
+            }
+        }
+    }
+
+    impl AttachmentResult {
+        pub fn unwrap(self) -> models::AttachmentStub {
+            match self {
+                AttachmentResult::Success(x) => x,
+                _ => panic!("Expected success")
+            }
+        }
+    }
+
+    macro_rules! init {
         ($state:ident) => {
             let db = db::test_connection();
-            let $state = SyncState::new(&db);
+            let render_cache = RenderCache::new(10, true);
+            let webhooks = Webhooks::default();
+            let $state = SyncState::new(&db, &render_cache, &webhooks);
         }
     }
 
@@ -614,4 +1907,604 @@ mod test {
             }
         };
     }
+
+    #[test]
+    fn update_article_records_includes() {
+        use schema::article_includes;
+
+        init!(state);
+
+        let article = state.create_article(None, "Title".into(), "{{include:a}}\n".into(), None).unwrap();
+        state.update_article(article.article_id, article.revision, article.title.clone(), "{{include:b}}\n".into(), None).unwrap().unwrap();
+
+        let includes = article_includes::table
+            .filter(article_includes::article_id.eq(article.article_id))
+            .select(article_includes::included_slug)
+            .load::<String>(state.db_connection)
+            .unwrap();
+
+        assert_eq!(vec!["b".to_owned()], includes);
+    }
+
+    #[test]
+    fn render_markdown_with_include() {
+        init!(state);
+
+        state.create_article(None, "Contacts".into(), "Call Bob".into(), None).unwrap();
+
+        let rendered = rendering::render_markdown("{{include:contacts}}", &state, Default::default()).unwrap();
+        assert!(rendered.contains("<p>Call Bob</p>"));
+    }
+
+    #[test]
+    fn render_article_is_invalidated_when_included_article_changes() {
+        init!(state);
+
+        let included = state.create_article(None, "Contacts".into(), "Call Bob".into(), None).unwrap();
+        let article = state.create_article(None, "Home".into(), "{{include:contacts}}\n".into(), None).unwrap();
+
+        let rendered = state.render_article(article.article_id, article.revision, &article.body, Default::default()).unwrap();
+        assert!(rendered.contains("Call Bob"));
+
+        state.update_article(included.article_id, included.revision, included.title.clone(), "Call Alice".into(), None).unwrap().unwrap();
+
+        let rendered = state.render_article(article.article_id, article.revision, &article.body, Default::default()).unwrap();
+        assert!(rendered.contains("Call Alice"));
+    }
+
+    #[test]
+    fn render_article_is_persisted() {
+        let db = db::test_connection();
+        let webhooks = Webhooks::default();
+
+        let article = {
+            let render_cache = RenderCache::new(10, true);
+            let state = SyncState::new(&db, &render_cache, &webhooks);
+            let article = state.create_article(None, "Title".into(), "*Body*".into(), None).unwrap();
+            state.render_article(article.article_id, article.revision, &article.body, Default::default()).unwrap();
+            article
+        };
+
+        let render_cache = RenderCache::new(10, true);
+        let state = SyncState::new(&db, &render_cache, &webhooks);
+
+        // A persisted hit does not look at the given body
+        assert_eq!(
+            "<p><em>Body</em></p>\n",
+            state.render_article(article.article_id, article.revision, "", Default::default()).unwrap()
+        );
+
+        // Persisted renderings are only used with the same rendering options
+        let render_cache = RenderCache::new(10, true);
+        let state = SyncState::new(&db, &render_cache, &webhooks);
+        let options = rendering::RenderOptions { allow_html: true };
+        assert_eq!(
+            "",
+            state.render_article(article.article_id, article.revision, "", options).unwrap()
+        );
+    }
+
+    #[test]
+    fn attachment_name_is_normalized() {
+        assert_eq!("my-screenshot.png", attachment_name("C:\\Users\\me\\My Screenshot.PNG"));
+        assert_eq!("report.pdf", attachment_name("/tmp/report.pdf"));
+        assert_eq!("file.txt", attachment_name("???.txt"));
+        assert_eq!("gitignore", attachment_name(".gitignore"));
+        assert_eq!("notes", attachment_name("notes"));
+    }
+
+    #[test]
+    fn create_attachment_adds_revisions() {
+        init!(state);
+
+        let article = state.create_article(None, "Title".into(), "Body".into(), None).unwrap();
+
+        let first = state.create_attachment("a.txt".into(), "text/plain".into(), b"one".to_vec(), None, Some(article.article_id)).unwrap().unwrap();
+        let second = state.create_attachment("a.txt".into(), "text/plain".into(), b"two".to_vec(), None, Some(article.article_id)).unwrap().unwrap();
+        assert_eq!(1, first.revision);
+        assert_eq!(2, second.revision);
+
+        let latest = state.get_attachment("a.txt".into(), None).unwrap().unwrap();
+        assert_eq!(b"two".to_vec(), latest.data);
+
+        let old = state.get_attachment("a.txt".into(), Some(1)).unwrap().unwrap();
+        assert_eq!(b"one".to_vec(), old.data);
+        assert!(!old.latest);
+
+        let attachments = state.get_article_attachments(article.article_id).unwrap();
+        assert_eq!(vec![second], attachments);
+    }
+
+    #[test]
+    fn create_attachment_keeps_names_of_other_articles() {
+        init!(state);
+
+        let a = state.create_article(None, "A".into(), "Body".into(), None).unwrap();
+        let b = state.create_article(None, "B".into(), "Body".into(), None).unwrap();
+
+        state.create_attachment("a.txt".into(), "text/plain".into(), b"a".to_vec(), None, Some(a.article_id)).unwrap().unwrap();
+        assert_matches!(
+            state.create_attachment("a.txt".into(), "text/plain".into(), b"b".to_vec(), None, Some(b.article_id)),
+            Ok(AttachmentResult::NameTaken)
+        );
+        assert_matches!(
+            state.create_attachment("a.txt".into(), "text/plain".into(), b"b".to_vec(), None, None),
+            Ok(AttachmentResult::NameTaken)
+        );
+
+        let latest = state.get_attachment("a.txt".into(), None).unwrap().unwrap();
+        assert_eq!(b"a".to_vec(), latest.data);
+        assert_eq!(Some(a.article_id), latest.article_id);
+    }
+
+    #[test]
+    fn get_resized_attachment_stores_thumbnail() {
+        use std::io::Cursor;
+        use image::{DynamicImage, ImageBuffer, ImageFormat};
+
+        init!(state);
+
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(ImageBuffer::new(1000, 10))
+            .save(&mut png, ImageFormat::PNG).unwrap();
+        state.create_attachment("a.png".into(), "image/png".into(), png.into_inner(), None, None).unwrap().unwrap();
+
+        let (thumbnail, width) = state.get_resized_attachment("a.png".into(), None, 300).unwrap().unwrap();
+        assert_eq!(Some(400), width);
+        assert_eq!("image/png", thumbnail.content_type);
+
+        let (stored, _) = state.get_resized_attachment("a.png".into(), None, 400).unwrap().unwrap();
+        assert_eq!(thumbnail.data, stored.data);
+
+        let (original, width) = state.get_resized_attachment("a.png".into(), None, 1600).unwrap().unwrap();
+        assert_eq!(None, width);
+        assert_eq!(state.get_attachment("a.png".into(), None).unwrap().unwrap().data, original.data);
+    }
+
+    #[test]
+    fn get_revisions_with_previous() {
+        init!(state);
+
+        let article = state.create_article(None, "Title".into(), "one".into(), None).unwrap();
+        state.update_article(article.article_id, 1, "Title".into(), "two".into(), None).unwrap();
+
+        let revisions = state.get_revisions_with_previous(vec![
+            (article.article_id, 2),
+            (article.article_id, 1),
+            (article.article_id, 3),
+        ]).unwrap();
+
+        assert_eq!(2, revisions.len());
+        assert_eq!("two", revisions[0].0.body);
+        assert_eq!(Some("one"), revisions[0].1.as_ref().map(|x| &x.body[..]));
+        assert_eq!("one", revisions[1].0.body);
+        assert!(revisions[1].1.is_none());
+    }
+
+    #[test]
+    fn get_revision_sizes() {
+        init!(state);
+
+        let article = state.create_article(None, "Title".into(), "one".into(), None).unwrap();
+        state.update_article(article.article_id, 1, "Title".into(), "blåbær".into(), None).unwrap();
+
+        let sizes = state.get_revision_sizes(vec![
+            (article.article_id, 0),
+            (article.article_id, 1),
+            (article.article_id, 2),
+        ]).unwrap();
+
+        assert_eq!(2, sizes.len());
+        assert_eq!(Some(&3), sizes.get(&(article.article_id, 1)));
+        assert_eq!(Some(&8), sizes.get(&(article.article_id, 2)));
+    }
+
+    #[test]
+    fn get_user_contributions() {
+        init!(state);
+
+        let a = state.create_article(None, "A".into(), "1".into(), Some("alice".into())).unwrap();
+        state.update_article(a.article_id, 1, "A".into(), "2".into(), Some("alice".into())).unwrap();
+        state.create_article(None, "B".into(), "".into(), Some("alice".into())).unwrap();
+        state.create_article(None, "C".into(), "".into(), Some("bob".into())).unwrap();
+
+        let alice = state.get_user_contributions("alice".into(), 1).unwrap().unwrap();
+        assert_eq!(3, alice.edits);
+        assert!(alice.first_edit <= alice.last_edit);
+        assert_eq!(vec![("A", 2)], alice.most_edited.iter().map(|x| (&x.title[..], x.revisions)).collect::<Vec<_>>());
+        assert_eq!(vec!["B"], alice.recent.iter().map(|x| &x.title[..]).collect::<Vec<_>>());
+        assert!(alice.profile.is_none());
+
+        assert!(state.get_user_contributions("carol".into(), 1).unwrap().is_none());
+
+        state.create_article(None, "User: Carol".into(), "Hi".into(), Some("bob".into())).unwrap();
+        assert!(state.get_user_contributions("carol".into(), 1).unwrap().is_none());
+
+        state.create_article(None, "User: carol".into(), "Hello".into(), Some("bob".into())).unwrap();
+        state.create_article(None, "User: carol".into(), "Impostor".into(), Some("bob".into())).unwrap();
+        let carol = state.get_user_contributions("carol".into(), 1).unwrap().unwrap();
+        assert_eq!(0, carol.edits);
+        assert_eq!(Some("Hello"), carol.profile.as_ref().map(|x| &x.body[..]));
+
+        let upper_carol = state.get_user_contributions("Carol".into(), 1).unwrap().unwrap();
+        assert_eq!(Some("Hi"), upper_carol.profile.as_ref().map(|x| &x.body[..]));
+    }
+
+    #[test]
+    fn search_query_language() {
+        init!(state);
+
+        state.create_article(None, "Lorem".into(), "ipsum dolor sit amet".into(), None).unwrap();
+        state.create_article(None, "Dolor".into(), "consectetur adipiscing".into(), None).unwrap();
+
+        let titles = |query: &str| state.search_query(query.into(), &Default::default(), 10, 0, 8, 1.).unwrap()
+            .into_iter()
+            .map(|x| x.title)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["Lorem"], titles("\"ipsum dolor\""));
+        assert_eq!(vec!["Dolor"], titles("dolor -ipsum"));
+        assert_eq!(vec!["Dolor"], titles("title:dol"));
+        assert_eq!(2, titles("ipsum OR consectetur").len());
+        assert_eq!(Vec::<String>::new(), titles("-ipsum"));
+        assert_eq!(Vec::<String>::new(), titles("NEAR(\" title:*"));
+    }
+
+    #[test]
+    fn search_results_are_highlighted() {
+        init!(state);
+
+        state.create_article(None, "Dolor <b>".into(), "Lørem ipsum dolor & dolorem".into(), None).unwrap();
+
+        let hits = state.search_query("dolor".into(), &Default::default(), 10, 0, 8, 1.).unwrap();
+        assert_eq!(1, hits.len());
+
+        let hit = &hits[0];
+        assert_eq!("Dolor <b>", hit.title);
+        assert_eq!(vec![models::Highlight { start: 0, end: 5 }], hit.title_highlights);
+        assert_eq!("<mark>Dolor</mark> &lt;b&gt;", hit.title_html());
+
+        assert_eq!("Lørem ipsum dolor & dolorem", hit.snippet);
+        assert_eq!(
+            vec![models::Highlight { start: 12, end: 17 }, models::Highlight { start: 20, end: 27 }],
+            hit.snippet_highlights
+        );
+        assert_eq!("Lørem ipsum <mark>dolor</mark> &amp; <mark>dolorem</mark>", hit.snippet_html());
+    }
+
+    #[test]
+    fn highlight_markers_are_not_indexed() {
+        init!(state);
+
+        let article = state.create_article(None, "Title".into(), "a \u{E001}b dolor \u{E000}c".into(), None).unwrap();
+
+        let hits = state.search_query("dolor".into(), &Default::default(), 10, 0, 8, 1.).unwrap();
+        assert_eq!("a b <mark>dolor</mark> c", hits[0].snippet_html());
+
+        state.update_article(article.article_id, article.revision, "Title".into(), "Gone".into(), None).unwrap();
+
+        let hits = state.search_history_query("dolor".into(), &Default::default(), 10, 0, 8).unwrap();
+        assert_eq!("a b <mark>dolor</mark> c", hits[0].snippet_html());
+    }
+
+    #[test]
+    fn search_ranking() {
+        init!(state);
+
+        // (title, body) of the articles, and the expected top results for
+        // queries against them
+        let fixture = [
+            ("Database", "We use postgres. Postgres backups run nightly, see postgres \
+                tuning for postgres configuration. Postgres, postgres, postgres."),
+            ("Postgres", "The relational database."),
+            ("Postgres tuning", "Settings for the database server."),
+            ("Backups", "Nightly backups of the postgres database."),
+            ("Getting started", "Read about the database and backups first."),
+        ];
+        let expected = [
+            ("postgres", &["Postgres", "Postgres tuning", "Database"][..]),
+            ("postgres tuning", &["Postgres tuning"][..]),
+            ("backups", &["Backups"][..]),
+            ("getting started", &["Getting started"][..]),
+            ("database", &["Database"][..]),
+        ];
+
+        for &(title, body) in &fixture {
+            state.create_article(None, title.into(), body.into(), None).unwrap();
+        }
+
+        for &(query, top) in &expected {
+            let titles = state.search_query(query.into(), &Default::default(), 10, 0, 8, 10.).unwrap()
+                .into_iter()
+                .map(|x| x.title)
+                .collect::<Vec<_>>();
+
+            assert_eq!(top, &titles[..top.len()], "Top results for {:?}", query);
+        }
+    }
+
+    #[test]
+    fn search_filters_and_facets() {
+        use chrono::{Datelike, Duration, Utc};
+        use search_query::Filters;
+
+        init!(state);
+
+        state.create_article(None, "Ops: Deploys".into(), "deploy".into(), Some("Alice".into())).unwrap();
+        state.create_article(None, "ops : Rollbacks".into(), "deploy".into(), Some("Bob".into())).unwrap();
+        state.create_article(None, "Deploying".into(), "deploy".into(), Some("Alice".into())).unwrap();
+
+        let titles = |filters: Filters| state.search_query("deploy".into(), &filters, 10, 0, 8, 1.).unwrap()
+            .into_iter()
+            .map(|x| x.title)
+            .collect::<Vec<_>>();
+
+        let tomorrow = (Utc::now() + Duration::days(1)).naive_utc().date();
+
+        assert_eq!(3, titles(Filters::default()).len());
+        assert_eq!(2, titles(Filters { author: Some("Alice".into()), ..Filters::default() }).len());
+        assert_eq!(
+            vec!["Ops: Deploys"],
+            titles(Filters { author: Some("Alice".into()), namespace: Some("OPS".into()), ..Filters::default() })
+        );
+        assert_eq!(0, titles(Filters { modified_after: Some(tomorrow), ..Filters::default() }).len());
+        assert_eq!(3, titles(Filters { modified_before: Some(tomorrow), ..Filters::default() }).len());
+
+        let facets = state.search_facets("deploy".into(), &Filters::default()).unwrap();
+        assert_eq!(
+            vec![models::FacetCount { value: "Alice".into(), count: 2 }, models::FacetCount { value: "Bob".into(), count: 1 }],
+            facets.authors
+        );
+        assert_eq!(
+            vec![models::FacetCount { value: "Ops".into(), count: 2 }],
+            facets.namespaces
+        );
+        assert_eq!(vec![models::FacetCount { value: Utc::now().year().to_string(), count: 3 }], facets.years);
+    }
+
+    #[test]
+    fn search_filters_on_upgraded_database() {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::Integer;
+        use search_query::Filters;
+
+        init!(state);
+
+        state.create_article(None, "Deploys".into(), "deploy".into(), Some("Alice".into())).unwrap();
+        state.create_article(None, "Rollbacks".into(), "deploy".into(), Some("Bob".into())).unwrap();
+
+        // Migration 20171025121618_fix_article_search_triggers refilled the
+        // index without rowids, so on older databases they are not article ids
+        sql::<Integer>("DELETE FROM article_search").execute(state.db_connection).unwrap();
+        sql::<Integer>(
+            "INSERT INTO article_search(title, body, slug) \
+                SELECT title, body, slug FROM article_revisions WHERE latest = 1 ORDER BY article_id DESC"
+        ).execute(state.db_connection).unwrap();
+
+        let hits = state.search_query("deploy".into(), &Filters { author: Some("Alice".into()), ..Filters::default() }, 10, 0, 8, 1.).unwrap();
+        assert_eq!(vec!["Deploys"], hits.into_iter().map(|x| x.title).collect::<Vec<_>>());
+
+        let facets = state.search_facets("deploy".into(), &Filters::default()).unwrap();
+        assert_eq!(
+            vec![models::FacetCount { value: "Alice".into(), count: 1 }, models::FacetCount { value: "Bob".into(), count: 1 }],
+            facets.authors
+        );
+    }
+
+    #[test]
+    fn search_history_finds_removed_text() {
+        init!(state);
+
+        let article = state.create_article(None, "Title".into(), "Some secret text".into(), Some("a".into())).unwrap();
+        state.update_article(article.article_id, article.revision, "Title".into(), "Some text".into(), Some("b".into())).unwrap();
+
+        assert!(state.search_query("secret".into(), &Default::default(), 10, 0, 8, 1.).unwrap().is_empty());
+
+        let hits = state.search_history_query("secret".into(), &Default::default(), 10, 0, 8).unwrap();
+        assert_eq!(1, hits.len());
+        assert_eq!(article.revision, hits[0].revision);
+        assert_eq!(Some("a".to_owned()), hits[0].author);
+        assert_eq!("Some <mark>secret</mark> text", hits[0].snippet_html());
+
+        let hits = state.search_history_query("text".into(), &Default::default(), 10, 0, 8).unwrap();
+        assert_eq!(vec![2, 1], hits.iter().map(|x| x.revision).collect::<Vec<_>>());
+
+        let filters = search_query::Filters { author: Some("b".into()), ..Default::default() };
+        let hits = state.search_history_query("text".into(), &filters, 10, 0, 8).unwrap();
+        assert_eq!(vec![2], hits.iter().map(|x| x.revision).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn get_titles_by_prefix_ranking() {
+        init!(state);
+
+        for title in &["Postgres tuning", "Tuning", "Tunnel_vision", "Other", "Tune 100%"] {
+            state.create_article(None, (*title).into(), "".into(), None).unwrap();
+        }
+
+        let titles = |prefix: &str| state.get_titles_by_prefix(prefix.into(), 10).unwrap()
+            .into_iter()
+            .map(|x| x.title)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["Tune 100%", "Tunnel_vision", "Tuning", "Postgres tuning"], titles("tun"));
+        assert_eq!(vec!["Tunnel_vision"], titles("tunnel_"));
+        assert_eq!(vec!["Tune 100%"], titles("Tune 100%"));
+        assert_eq!(vec!["Postgres tuning"], titles("postgres-t"));
+        assert_eq!(Vec::<String>::new(), titles("t%g"));
+        assert_eq!(Vec::<String>::new(), titles(" "));
+    }
+
+    #[test]
+    fn get_titles_by_prefix_uses_indexes() {
+        use diesel::expression::sql_literal::sql;
+        use diesel::types::{Integer, Text};
+
+        init!(state);
+
+        let plan = sql::<(Integer, Integer, Integer, Text)>(&format!("EXPLAIN QUERY PLAN {}", TITLE_PREFIX_SQL))
+            .bind::<Text, _>("tun%")
+            .bind::<Text, _>("tun*")
+            .bind::<Integer, _>(10)
+            .load::<(i32, i32, i32, String)>(state.db_connection).unwrap()
+            .into_iter()
+            .map(|(_, _, _, detail)| detail)
+            .collect::<Vec<_>>();
+
+        assert!(plan.iter().any(|x| x.contains("USING INDEX title_prefix_lookup")), "{:?}", plan);
+        assert!(plan.iter().any(|x| x.contains("USING INDEX slugs_index")), "{:?}", plan);
+        assert!(!plan.iter().any(|x| x.starts_with("SCAN")), "{:?}", plan);
+    }
+
+    #[test]
+    fn search_front_page() {
+        init!(state);
+
+        let a = state.create_article(None, "Edited".into(), "1".into(), None).unwrap();
+        state.create_article(None, "Recent".into(), "".into(), None).unwrap();
+        state.update_article(a.article_id, 1, "Edited".into(), "2".into(), None).unwrap();
+
+        let front_page = state.get_search_front_page(1).unwrap();
+        assert_eq!(vec!["Edited"], front_page.recent.iter().map(|x| &x.title[..]).collect::<Vec<_>>());
+        assert_eq!(
+            vec![("Edited", 2)],
+            front_page.most_edited.iter().map(|x| (&x.title[..], x.revisions)).collect::<Vec<_>>()
+        );
+        assert_eq!(1, front_page.random.len());
+
+        assert_eq!(2, state.get_search_front_page(10).unwrap().random.len());
+    }
+
+    #[test]
+    fn stats() {
+        init!(state);
+
+        let a = state.create_article(None, "Large".into(), "øøø".into(), Some("alice".into())).unwrap();
+        state.create_article(None, "Small".into(), "ab".into(), Some("alice".into())).unwrap();
+        state.update_article(a.article_id, 1, "Large".into(), "øøøø".into(), Some("bob".into())).unwrap();
+
+        let stats = state.get_stats().unwrap();
+        assert_eq!((2, 3), (stats.articles, stats.revisions));
+
+        assert_eq!(STATS_DAYS as usize, stats.edits_per_day.len());
+        assert_eq!(Some(3), stats.edits_per_day.last().map(|x| x.1));
+        assert_eq!(3, stats.edits_per_day.iter().map(|x| x.1).sum::<i64>());
+        assert_eq!(STATS_WEEKS as usize, stats.edits_per_week.len());
+        assert_eq!(Some(3), stats.edits_per_week.last().map(|x| x.1));
+
+        assert_eq!(
+            vec![("alice", 2), ("bob", 1)],
+            stats.top_contributors.iter().map(|x| (&x.author[..], x.edits)).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![("Large", 8), ("Small", 2)],
+            stats.largest.iter().map(|x| (&x.title[..], x.bytes)).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![("Large", 8), ("Small", 2)],
+            stats.fastest_growing.iter().map(|x| (&x.title[..], x.bytes)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn webhook_deliveries_are_queued_and_retried() {
+        let db = db::test_connection();
+        let render_cache = RenderCache::new(10, true);
+        let webhooks = Webhooks::new(
+            vec!["http://a.example.com/".into(), "http://b.example.com/".into()],
+            "secret".into(),
+            None
+        );
+        let state = SyncState::new(&db, &render_cache, &webhooks);
+
+        let article = state.create_article(None, "Title".into(), "".into(), Some("alice".into())).unwrap();
+        state.update_article(article.article_id, 1, "Title".into(), "Body".into(), None).unwrap().unwrap();
+
+        let due = state.get_due_webhook_deliveries(10).unwrap();
+        assert_eq!(4, due.len());
+        assert_eq!("http://a.example.com/", due[0].url);
+        assert_eq!("http://b.example.com/", due[1].url);
+        assert!(due[0].payload.contains(r#""event":"create""#));
+        assert!(due[2].payload.contains(r#""diff_link":"_diff/1?from=1&to=2""#));
+
+        state.record_webhook_attempt(due[0].id, Ok("200 OK".into())).unwrap();
+        state.record_webhook_attempt(due[1].id, Err("Timed out".into())).unwrap();
+
+        // Neither is due any more: one is done and the other waits for a
+        // retry, along with the rest of the deliveries to its endpoint
+        let due_ids = state.get_due_webhook_deliveries(10).unwrap().into_iter().map(|x| x.id).collect::<Vec<_>>();
+        assert_eq!(vec![due[2].id], due_ids);
+
+        let delivered = webhook_deliveries::table.find(due[0].id).first::<models::WebhookDelivery>(&db).unwrap();
+        assert_eq!((1, None, Some("200 OK".into())), (delivered.attempts, delivered.next_attempt, delivered.last_result));
+        assert!(delivered.delivered.is_some());
+
+        let failed = webhook_deliveries::table.find(due[1].id).first::<models::WebhookDelivery>(&db).unwrap();
+        assert_eq!((1, Some("Timed out".into())), (failed.attempts, failed.last_result));
+        assert!(failed.next_attempt.is_some() && failed.delivered.is_none());
+
+        // Give up after the last attempt
+        for _ in 1..::webhooks::MAX_ATTEMPTS {
+            state.record_webhook_attempt(due[1].id, Err("Timed out".into())).unwrap();
+        }
+        let failed = webhook_deliveries::table.find(due[1].id).first::<models::WebhookDelivery>(&db).unwrap();
+        assert_eq!((::webhooks::MAX_ATTEMPTS, None), (failed.attempts, failed.next_attempt));
+
+        let due_ids = state.get_due_webhook_deliveries(10).unwrap().into_iter().map(|x| x.id).collect::<Vec<_>>();
+        assert_eq!(vec![due[2].id, due[3].id], due_ids);
+    }
+
+    #[test]
+    fn watchlist_and_notification_digests() {
+        init!(state);
+
+        let watched = state.create_article(None, "Watched".into(), "1".into(), Some("bob".into())).unwrap();
+        state.watch_article("alice".into(), watched.article_id).unwrap();
+        state.watch_article("alice".into(), watched.article_id).unwrap();
+
+        // Changes from before watching are not included
+        assert!(state.get_notification_digests(10).unwrap().is_empty());
+
+        let bobs = state.update_article(watched.article_id, 1, "Watched".into(), "2".into(), Some("bob".into())).unwrap().unwrap();
+        state.update_article(watched.article_id, 2, "Watched".into(), "3".into(), Some("alice".into())).unwrap().unwrap();
+        state.create_article(None, "Other".into(), "".into(), Some("bob".into())).unwrap();
+
+        let digests = state.get_notification_digests(10).unwrap();
+        assert_eq!(1, digests.len());
+        assert_eq!("alice", digests[0].identity);
+        assert_eq!(bobs.sequence_number, digests[0].up_to);
+        assert_eq!(
+            vec![(2, Some(1))],
+            digests[0].changes.iter().map(|x| (x.0.revision, x.1.as_ref().map(|x| x.revision))).collect::<Vec<_>>()
+        );
+
+        state.advance_notification_cursor("alice".into(), digests[0].up_to).unwrap();
+        assert!(state.get_notification_digests(10).unwrap().is_empty());
+
+        assert_eq!(
+            vec!["Watched"],
+            state.get_watched_articles("alice".into()).unwrap().into_iter().map(|x| x.title).collect::<Vec<_>>()
+        );
+        assert!(state.is_watching("alice".into(), watched.article_id).unwrap());
+        state.unwatch_article("alice".into(), watched.article_id).unwrap();
+        assert!(state.get_watched_articles("alice".into()).unwrap().is_empty());
+        assert!(!state.is_watching("alice".into(), watched.article_id).unwrap());
+    }
+
+    #[test]
+    fn suggest_titles_for_typos() {
+        init!(state);
+
+        for title in &["Kubernetes", "Kubernetes ingress", "Postgres", "Kubernetis"] {
+            state.create_article(None, (*title).into(), "".into(), None).unwrap();
+        }
+
+        let titles = state.suggest_titles("kuberentes".into(), 5).unwrap()
+            .into_iter()
+            .map(|x| x.title)
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["Kubernetes", "Kubernetis"], titles);
+
+        assert!(state.suggest_titles("???".into(), 5).unwrap().is_empty());
+    }
 }
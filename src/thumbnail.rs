@@ -0,0 +1,139 @@
+use std;
+use std::io::Cursor;
+
+use image::{self, FilterType, ImageDecoder, ImageFormat};
+
+pub type Error = Box<std::error::Error + Send + Sync>;
+
+/// The widths thumbnails are generated for. Other requested widths are
+/// rounded up to the nearest of these, to bound the number of variants.
+pub const WIDTHS: &[u32] = &[400, 800, 1600];
+
+/// Images with more pixels than this are not resized, as decoding them
+/// would take too much memory
+const MAX_PIXELS: u64 = 50_000_000;
+
+/// The thumbnail width to use for a requested width, or `None` if the
+/// original should be used
+pub fn thumbnail_width(requested: u32) -> Option<u32> {
+    WIDTHS.iter().cloned().find(|&x| x >= requested)
+}
+
+pub fn is_resizable(content_type: &str) -> bool {
+    match content_type.split(';').next().unwrap_or("").trim() {
+        "image/png" | "image/jpeg" => true,
+        _ => false,
+    }
+}
+
+/// Read the dimensions from the header, without decoding the image
+fn dimensions(data: &[u8], format: ImageFormat) -> Option<(u32, u32)> {
+    match format {
+        ImageFormat::PNG => image::png::PNGDecoder::new(Cursor::new(data)).dimensions().ok(),
+        ImageFormat::JPEG => image::jpeg::JPEGDecoder::new(Cursor::new(data)).dimensions().ok(),
+        _ => None,
+    }
+}
+
+/// Scale the image in `data` down to the given width, keeping the format
+/// and the aspect ratio. Returns the encoded image and its content type, or
+/// `None` if the image is not wider than `width`, is too large or cannot be
+/// decoded.
+pub fn resize(data: &[u8], width: u32) -> Result<Option<(Vec<u8>, &'static str)>, Error> {
+    let (format, content_type) = match image::guess_format(data) {
+        Ok(ImageFormat::PNG) => (ImageFormat::PNG, "image/png"),
+        Ok(ImageFormat::JPEG) => (ImageFormat::JPEG, "image/jpeg"),
+        _ => return Ok(None),
+    };
+
+    let (original_width, original_height) = match dimensions(data, format) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    if original_width <= width || original_width as u64 * original_height as u64 > MAX_PIXELS {
+        return Ok(None);
+    }
+
+    let original = match image::load_from_memory_with_format(data, format) {
+        Ok(x) => x,
+        Err(_) => return Ok(None),
+    };
+
+    let height = original_height as u64 * width as u64 / original_width as u64;
+    let height = std::cmp::max(1, height as u32);
+    let resized = original.resize_exact(width, height, FilterType::Lanczos3);
+
+    let mut buf = Cursor::new(Vec::new());
+    resized.save(&mut buf, format)?;
+
+    Ok(Some((buf.into_inner(), content_type)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use image::{DynamicImage, GenericImage, ImageBuffer};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::new(width, height));
+        let mut buf = Cursor::new(Vec::new());
+        image.save(&mut buf, ImageFormat::PNG).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn thumbnail_width_rounds_up() {
+        assert_eq!(Some(400), thumbnail_width(1));
+        assert_eq!(Some(800), thumbnail_width(800));
+        assert_eq!(Some(1600), thumbnail_width(801));
+        assert_eq!(None, thumbnail_width(1601));
+    }
+
+    #[test]
+    fn resize_keeps_aspect_ratio() {
+        let (data, content_type) = resize(&png(1000, 500), 400).unwrap().unwrap();
+        assert_eq!("image/png", content_type);
+
+        let resized = image::load_from_memory(&data).unwrap();
+        assert_eq!((400, 200), resized.dimensions());
+    }
+
+    #[test]
+    fn resize_does_not_enlarge() {
+        assert!(resize(&png(300, 100), 400).unwrap().is_none());
+    }
+
+    #[test]
+    fn resize_refuses_huge_images() {
+        fn crc(data: &[u8]) -> u32 {
+            let mut crc = !0u32;
+            for &byte in data {
+                crc ^= byte as u32;
+                for _ in 0..8 {
+                    crc = (crc >> 1) ^ (0xedb88320 & (!(crc & 1)).wrapping_add(1));
+                }
+            }
+            !crc
+        }
+
+        // Claim 50000×50000 pixels in the IHDR chunk of a small image
+        let mut data = png(1000, 10);
+        for i in 0..8 {
+            data[16 + i] = [0, 0, 0xc3, 0x50][i % 4];
+        }
+        let checksum = crc(&data[12..29]);
+        for i in 0..4 {
+            data[29 + i] = (checksum >> (24 - 8 * i)) as u8;
+        }
+
+        assert_eq!(Some((50000, 50000)), dimensions(&data, ImageFormat::PNG));
+        assert!(resize(&data, 400).unwrap().is_none());
+    }
+
+    #[test]
+    fn resize_ignores_other_data() {
+        assert!(resize(b"GIF89a", 400).unwrap().is_none());
+        assert!(resize(b"not an image", 400).unwrap().is_none());
+    }
+}
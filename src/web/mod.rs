@@ -1,5 +1,6 @@
 mod resource;
 mod lookup;
+pub mod multipart;
 
 pub use self::resource::*;
 pub use self::lookup::*;
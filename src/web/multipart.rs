@@ -0,0 +1,194 @@
+//! Minimal parser for `multipart/form-data` request bodies, as submitted by
+//! HTML forms with file inputs
+
+use hyper::mime::{self, Mime};
+
+pub type Error = Box<::std::error::Error + Send + Sync>;
+
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: &'a [u8],
+}
+
+pub fn boundary(content_type: &Mime) -> Option<String> {
+    if content_type.type_() != mime::MULTIPART || content_type.subtype() != mime::FORM_DATA {
+        return None;
+    }
+
+    content_type.get_param(mime::BOUNDARY).map(|x| x.as_str().to_owned())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+
+/// Split a header parameter list such as `form-data; name="a"` into the
+/// parameters, unquoting quoted values
+fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = value;
+
+    // Skip the disposition type
+    match rest.find(';') {
+        Some(x) => rest = &rest[x + 1..],
+        None => return params,
+    }
+
+    loop {
+        rest = rest.trim_left();
+        let eq = match rest.find('=') {
+            Some(x) => x,
+            None => break,
+        };
+        let key = rest[..eq].trim().to_lowercase();
+        rest = rest[eq + 1..].trim_left();
+
+        let mut value = String::new();
+        if rest.starts_with('"') {
+            let mut chars = rest[1..].char_indices();
+            let mut end = rest.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => if let Some((_, c)) = chars.next() { value.push(c) },
+                    '"' => { end = i + 2; break },
+                    c => value.push(c),
+                }
+            }
+            rest = &rest[end..];
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            value.push_str(rest[..end].trim());
+            rest = &rest[end..];
+        }
+
+        params.push((key, value));
+
+        match rest.find(';') {
+            Some(x) => rest = &rest[x + 1..],
+            None => break,
+        }
+    }
+
+    params
+}
+
+fn parse_part(src: &[u8]) -> Result<Part, Error> {
+    let header_end = find(src, b"\r\n\r\n").ok_or("Missing end of part headers")?;
+    let headers = String::from_utf8_lossy(&src[..header_end]);
+    let data = &src[header_end + 4..];
+
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+
+    for line in headers.split("\r\n") {
+        let colon = match line.find(':') {
+            Some(x) => x,
+            None => continue,
+        };
+        let (key, value) = (line[..colon].trim().to_lowercase(), line[colon + 1..].trim());
+
+        match &key[..] {
+            "content-disposition" => {
+                for (param, value) in parameters(value) {
+                    match &param[..] {
+                        "name" => name = Some(value),
+                        "filename" => filename = Some(value),
+                        _ => (),
+                    }
+                }
+            },
+            "content-type" => content_type = Some(value.to_owned()),
+            _ => (),
+        }
+    }
+
+    Ok(Part {
+        name: name.ok_or("Missing name of form field")?,
+        filename,
+        content_type,
+        data,
+    })
+}
+
+pub fn parse<'a>(body: &'a [u8], boundary: &str) -> Result<Vec<Part<'a>>, Error> {
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+    // The first delimiter is not required to be preceded by CRLF
+    let start = find(body, &delimiter[2..]).ok_or("Missing multipart boundary")?;
+    let mut rest = &body[start + delimiter.len() - 2..];
+
+    let mut parts = Vec::new();
+
+    loop {
+        if rest.starts_with(b"--") {
+            break;
+        }
+        if !rest.starts_with(b"\r\n") {
+            return Err("Malformed multipart boundary".into());
+        }
+        rest = &rest[2..];
+
+        let end = find(rest, &delimiter).ok_or("Missing closing multipart boundary")?;
+        parts.push(parse_part(&rest[..end])?);
+        rest = &rest[end + delimiter.len()..];
+    }
+
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn boundary_from_content_type() {
+        let content_type = "multipart/form-data; boundary=----x1".parse().unwrap();
+        assert_eq!(Some("----x1".to_owned()), boundary(&content_type));
+
+        let content_type = "application/x-www-form-urlencoded".parse().unwrap();
+        assert_eq!(None, boundary(&content_type));
+    }
+
+    #[test]
+    fn parse_form() {
+        let body =
+            b"--xyz\r\n\
+            Content-Disposition: form-data; name=\"article_id\"\r\n\
+            \r\n\
+            12\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".png\"\r\n\
+            Content-Type: image/png\r\n\
+            \r\n\
+            \x89PNG\r\n--xy\r\n\
+            --xyz--\r\n";
+
+        assert_eq!(
+            vec![
+                Part {
+                    name: "article_id".into(),
+                    filename: None,
+                    content_type: None,
+                    data: b"12",
+                },
+                Part {
+                    name: "file".into(),
+                    filename: Some("a \"b\".png".into()),
+                    content_type: Some("image/png".into()),
+                    data: b"\x89PNG\r\n--xy",
+                },
+            ],
+            parse(body, "xyz").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let body = b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ndata";
+        assert!(parse(body, "xyz").is_err());
+    }
+}
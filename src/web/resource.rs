@@ -60,4 +60,13 @@ pub trait Resource {
         // This function is a complete hack, searching for the appropriate
         // architecture.
     }
+
+    fn hacky_inject_headers(&mut self, _: &header::Headers) {
+        // Same hack as above, for resources that need other request headers
+    }
+
+    fn hacky_inject_identity(&mut self, _: Option<String>) {
+        // Same hack as above, for resources that show different things to
+        // different identities
+    }
 }
@@ -0,0 +1,323 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use hmac::{Hmac, Mac};
+use hyper;
+use hyper::client::{Client, HttpConnector};
+use hyper::header::{ContentLength, ContentType};
+use serde_json;
+use sha2::Sha256;
+use tokio_core::reactor::{Core, Handle, Timeout};
+
+use mimes::*;
+use models;
+use state::State;
+
+header! { (XSausagewikiDelivery, "X-Sausagewiki-Delivery") => [i32] }
+header! { (XSausagewikiSignature, "X-Sausagewiki-Signature") => [String] }
+
+/// Give up on a delivery after this many failed attempts. The delay before
+/// each retry doubles, starting at one minute, so the last attempt happens
+/// about eight and a half hours after the first.
+pub const MAX_ATTEMPTS: i32 = 10;
+
+/// How long to wait for an endpoint to respond
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to look for deliveries that are due for a retry
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+const BATCH_SIZE: i32 = 20;
+
+/// The configured webhook endpoints. Changes to articles are queued for
+/// each of them in the database, and a background thread started by
+/// `spawn` delivers them.
+#[derive(Default)]
+pub struct Webhooks {
+    pub urls: Vec<String>,
+    secret: String,
+    base_url: Option<String>,
+    wake: Mutex<Option<Sender<()>>>,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'a str,
+    article_id: i32,
+    revision: i32,
+    slug: &'a str,
+    title: &'a str,
+    author: Option<&'a str>,
+    link: String,
+    diff_link: Option<String>,
+}
+
+impl Webhooks {
+    pub fn new(urls: Vec<String>, secret: String, base_url: Option<String>) -> Webhooks {
+        Webhooks {
+            urls,
+            secret,
+            base_url,
+            wake: Mutex::new(None),
+        }
+    }
+
+    /// The JSON body announcing `revision`. `event` is "create" or "update".
+    /// Links are absolute when the base URL of the wiki is configured.
+    pub fn payload(&self, event: &str, revision: &models::ArticleRevision) -> String {
+        let base_url = self.base_url.as_ref().map(|x| &x[..]).unwrap_or("");
+
+        serde_json::to_string(&Payload {
+            event,
+            article_id: revision.article_id,
+            revision: revision.revision,
+            slug: &revision.slug,
+            title: &revision.title,
+            author: revision.author.as_ref().map(|x| &x[..]),
+            link: format!("{}{}", base_url, revision.link()),
+            diff_link: if revision.revision > 1 {
+                Some(format!("{}_diff/{}?from={}&to={}",
+                    base_url, revision.article_id, revision.revision - 1, revision.revision))
+            } else {
+                None
+            },
+        }).expect("Should never fail")
+    }
+
+    /// Let the delivery thread know that there are new deliveries queued
+    pub fn notify(&self) {
+        if let Some(ref wake) = *self.wake.lock().unwrap() {
+            // The delivery thread only stops when the program exits
+            let _ignore_failure = wake.send(());
+        }
+    }
+}
+
+/// The value of the `X-Sausagewiki-Signature` header: the hex encoded
+/// HMAC-SHA256 of the body, keyed by the shared secret
+pub fn signature(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new(secret.as_bytes());
+    mac.input(body.as_bytes());
+
+    let hex = mac.result().code().iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+
+    format!("sha256={}", hex)
+}
+
+/// POST a single delivery. Resolves to the response status for successful
+/// deliveries and a description of what went wrong otherwise.
+fn deliver(client: &Client<HttpConnector>, handle: &Handle, delivery: &models::WebhookDelivery, secret: &str)
+    -> Box<Future<Item=String, Error=String>>
+{
+    let uri = match delivery.url.parse::<hyper::Uri>() {
+        Ok(x) => x,
+        Err(err) => return Box::new(::futures::failed(format!("Invalid URL: {}", err))),
+    };
+    let timeout = match Timeout::new(TIMEOUT, handle) {
+        Ok(x) => x,
+        Err(err) => return Box::new(::futures::failed(err.to_string())),
+    };
+
+    let mut request = hyper::Request::new(hyper::Method::Post, uri);
+    request.headers_mut().set(ContentType(APPLICATION_JSON.clone()));
+    request.headers_mut().set(ContentLength(delivery.payload.len() as u64));
+    request.headers_mut().set(XSausagewikiDelivery(delivery.id));
+    request.headers_mut().set(XSausagewikiSignature(signature(secret, &delivery.payload)));
+    request.set_body(delivery.payload.clone());
+
+    let response = client.request(request)
+        .map_err(|err| err.to_string());
+    let timeout = timeout
+        .then(|_| Err::<hyper::Response, _>("Timed out".to_owned()));
+
+    Box::new(response.select(timeout)
+        .map(|(response, _)| response)
+        .map_err(|(err, _)| err)
+        .and_then(|response| {
+            let status = response.status();
+            // Read the body so the connection can be reused
+            response.body().concat2()
+                .map_err(|err| err.to_string())
+                .and_then(move |_| match status.is_success() {
+                    true => Ok(status.to_string()),
+                    false => Err(status.to_string()),
+                })
+        }))
+}
+
+/// Start the thread delivering the queued webhook requests. It makes an
+/// attempt at every due delivery whenever an article changes and every
+/// `POLL_INTERVAL`.
+pub fn spawn(state: State, webhooks: Arc<Webhooks>) -> Result<(), Box<::std::error::Error>> {
+    let (wake, woken) = mpsc::channel();
+    *webhooks.wake.lock().unwrap() = Some(wake);
+
+    thread::Builder::new()
+        .name("webhooks".to_owned())
+        .spawn(move || {
+            let mut core = Core::new().expect("Should be able to create an event loop");
+            let handle = core.handle();
+            let client = Client::new(&handle);
+
+            loop {
+                let due = state.get_due_webhook_deliveries(BATCH_SIZE).wait()
+                    .unwrap_or_else(|err| {
+                        eprintln!("Unable to read webhook deliveries: {}", err);
+                        vec![]
+                    });
+
+                // After a failure, the remaining deliveries to the same
+                // endpoint wait for its retry, see get_due_webhook_deliveries
+                let mut failed = HashSet::new();
+
+                for delivery in &due {
+                    if failed.contains(&delivery.url) {
+                        continue;
+                    }
+
+                    let result = core.run(deliver(&client, &handle, delivery, &webhooks.secret));
+                    if result.is_err() {
+                        failed.insert(delivery.url.clone());
+                    }
+
+                    if let Err(err) = state.record_webhook_attempt(delivery.id, result).wait() {
+                        eprintln!("Unable to record webhook delivery {}: {}", delivery.id, err);
+                    }
+                }
+
+                // Keep going while there may be more due deliveries
+                if due.len() as i32 == BATCH_SIZE {
+                    continue;
+                }
+
+                match woken.recv_timeout(POLL_INTERVAL) {
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Accept a single request on a local port and answer it with
+    /// `status`. Returns the URL to send the request to and the raw
+    /// request as it was received.
+    fn stand_in(status: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.to_lowercase().starts_with("content-length:") {
+                    content_length = line[15..].trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+
+            write!(&stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            tx.send(request).unwrap();
+        });
+
+        (url, rx)
+    }
+
+    fn delivery(url: String) -> models::WebhookDelivery {
+        models::WebhookDelivery {
+            id: 7,
+            url,
+            payload: r#"{"event":"create"}"#.to_owned(),
+            created: ::chrono::NaiveDate::from_ymd(2017, 12, 13).and_hms(8, 0, 0),
+            attempts: 0,
+            next_attempt: None,
+            delivered: None,
+            last_result: None,
+        }
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256() {
+        // Test case 2 from RFC 4231
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            signature("Jefe", "what do ya want for nothing?")
+        );
+    }
+
+    #[test]
+    fn delivers_signed_json() {
+        let (url, request) = stand_in("204 No Content");
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let client = Client::new(&handle);
+
+        let result = core.run(deliver(&client, &handle, &delivery(url), "secret"));
+        assert_eq!(Ok("204 No Content".to_owned()), result);
+
+        let request = request.recv().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains("X-Sausagewiki-Delivery: 7\r\n"));
+        assert!(request.contains(&format!("X-Sausagewiki-Signature: {}\r\n", signature("secret", r#"{"event":"create"}"#))));
+        assert!(request.ends_with("\r\n\r\n{\"event\":\"create\"}"));
+    }
+
+    #[test]
+    fn error_status_fails_delivery() {
+        let (url, _request) = stand_in("500 Internal Server Error");
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let client = Client::new(&handle);
+
+        let result = core.run(deliver(&client, &handle, &delivery(url), "secret"));
+        assert_eq!(Err("500 Internal Server Error".to_owned()), result);
+    }
+
+    #[test]
+    fn payload_links() {
+        let webhooks = Webhooks::new(vec![], "secret".to_owned(), Some("https://wiki.example.com/".to_owned()));
+        let revision = models::ArticleRevision {
+            sequence_number: 3,
+            article_id: 1,
+            revision: 2,
+            created: ::chrono::NaiveDate::from_ymd(2017, 12, 13).and_hms(8, 0, 0),
+            slug: "deploys".to_owned(),
+            title: "Deploys".to_owned(),
+            body: "".to_owned(),
+            latest: true,
+            author: Some("alice".to_owned()),
+        };
+
+        let payload: serde_json::Value = serde_json::from_str(&webhooks.payload("update", &revision)).unwrap();
+        assert_eq!("https://wiki.example.com/deploys", payload["link"]);
+        assert_eq!("https://wiki.example.com/_diff/1?from=1&to=2", payload["diff_link"]);
+        assert_eq!("alice", payload["author"]);
+    }
+}
@@ -5,6 +5,7 @@ use std::str::Utf8Error;
 use futures::{Future, finished, failed, done};
 use futures::future::FutureResult;
 use percent_encoding::percent_decode;
+use serde_urlencoded;
 use slug::slugify;
 
 use resources::*;
@@ -55,6 +56,11 @@ pub struct WikiLookup {
     changes_lookup: ChangesLookup,
     diff_lookup: DiffLookup,
     search_lookup: SearchLookup,
+    titles_lookup: TitlesLookup,
+    user_lookup: UserLookup,
+
+    /// The configured public URL of the wiki, ending with a slash
+    base_url: Option<String>,
 }
 
 fn split_one(path: &str) -> Result<(Cow<str>, Option<&str>), Utf8Error> {
@@ -85,12 +91,14 @@ fn map_lookup(map: &HashMap<String, ResourceFn>, path: &str) ->
 }
 
 impl WikiLookup {
-    pub fn new(state: State, show_authors: bool) -> WikiLookup {
-        let changes_lookup = ChangesLookup::new(state.clone(), show_authors);
+    pub fn new(state: State, show_authors: bool, base_url: Option<String>) -> WikiLookup {
+        let changes_lookup = ChangesLookup::new(state.clone(), show_authors, base_url.clone());
         let diff_lookup = DiffLookup::new(state.clone());
         let search_lookup = SearchLookup::new(state.clone());
+        let titles_lookup = TitlesLookup::new(state.clone());
+        let user_lookup = UserLookup::new(state.clone());
 
-        WikiLookup { state, changes_lookup, diff_lookup, search_lookup }
+        WikiLookup { state, changes_lookup, diff_lookup, search_lookup, titles_lookup, user_lookup, base_url }
     }
 
     fn revisions_lookup(&self, path: &str, _query: Option<&str>) -> <Self as Lookup>::Future {
@@ -107,11 +115,50 @@ impl WikiLookup {
             Err(_) => return Box::new(finished(None)),
         };
 
+        let state = self.state.clone();
+
         Box::new(
             self.state.get_article_revision(article_id, revision)
                 .and_then(|article_revision|
                     Ok(article_revision.map(move |x| Box::new(
-                        ArticleRevisionResource::new(x)
+                        ArticleRevisionResource::new(state, x)
+                    ) as BoxResource))
+                )
+        )
+    }
+
+    fn attachment_lookup(&self, path: &str, query: Option<&str>) -> <Self as Lookup>::Future {
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            revision: Option<i32>,
+            width: Option<u32>,
+        }
+
+        let (name, query) = match (|| -> Result<_, <Self as Lookup>::Error> {
+            let (name, tail) = split_one(path)?;
+            if tail.is_some() {
+                return Err("Not found".into());
+            }
+
+            let query: QueryParameters = serde_urlencoded::from_str(query.unwrap_or(""))?;
+
+            Ok((name.into_owned(), query))
+        })() {
+            Ok(x) => x,
+            Err(_) => return Box::new(finished(None)),
+        };
+
+        let attachment: Box<Future<Item=_, Error=_>> = match query.width {
+            Some(width) => Box::new(self.state.get_resized_attachment(name, query.revision, width)),
+            None => Box::new(self.state.get_attachment(name, query.revision)
+                .map(|attachment| attachment.map(|x| (x, None)))),
+        };
+
+        Box::new(
+            attachment
+                .and_then(|attachment|
+                    Ok(attachment.map(move |(x, width)| Box::new(
+                        AttachmentResource::new(x, width)
                     ) as BoxResource))
                 )
         )
@@ -156,6 +203,31 @@ impl WikiLookup {
         Box::new(self.diff_lookup.lookup(article_id, query))
     }
 
+    fn new_article_lookup(&self, query: Option<&str>) -> <Self as Lookup>::Future {
+        #[derive(Deserialize)]
+        struct QueryParameters {
+            title: Option<String>,
+        }
+
+        let query: QueryParameters = match serde_urlencoded::from_str(query.unwrap_or("")) {
+            Ok(x) => x,
+            Err(x) => return Box::new(failed(x.into())),
+        };
+
+        Box::new(finished(Some(
+            Box::new(NewArticleResource::new(self.state.clone(), None, query.title)) as BoxResource
+        )))
+    }
+
+    fn users_lookup(&self, path: &str, _query: Option<&str>) -> <Self as Lookup>::Future {
+        let identity = match split_one(path) {
+            Ok((identity, None)) => identity.into_owned(),
+            _ => return Box::new(finished(None)),
+        };
+
+        Box::new(self.user_lookup.lookup(identity))
+    }
+
     fn reserved_lookup(&self, path: &str, query: Option<&str>) -> <Self as Lookup>::Future {
         let (head, tail) = match split_one(path) {
             Ok(x) => x,
@@ -173,16 +245,34 @@ impl WikiLookup {
                 self.by_id_lookup(tail, query),
             ("_changes", None) =>
                 Box::new(self.changes_lookup.lookup(query)),
+            ("_changes", Some("stream")) =>
+                Box::new(done(self.changes_lookup.lookup_stream(query))),
             ("_diff", Some(tail)) =>
                 self.diff_lookup_f(tail, query),
+            ("_files", None) =>
+                Box::new(finished(Some(Box::new(AttachmentsResource::new(self.state.clone())) as BoxResource))),
+            ("_files", Some(tail)) =>
+                self.attachment_lookup(tail, query),
             ("_new", None) =>
-                Box::new(finished(Some(Box::new(NewArticleResource::new(self.state.clone(), None)) as BoxResource))),
+                self.new_article_lookup(query),
+            ("_opensearch", None) =>
+                Box::new(finished(Some(Box::new(OpenSearchResource::new(self.base_url.clone())) as BoxResource))),
             ("_revisions", Some(tail)) =>
                 self.revisions_lookup(tail, query),
             ("_search", None) =>
                 Box::new(done(self.search_lookup.lookup(query))),
             ("_sitemap", None) =>
                 Box::new(finished(Some(Box::new(SitemapResource::new(self.state.clone())) as BoxResource))),
+            ("_stats", None) =>
+                Box::new(finished(Some(Box::new(StatsResource::new(self.state.clone())) as BoxResource))),
+            ("_titles", None) =>
+                Box::new(done(self.titles_lookup.lookup(query))),
+            ("_users", Some(tail)) =>
+                self.users_lookup(tail, query),
+            ("_watch", None) =>
+                Box::new(finished(Some(Box::new(WatchResource::new(self.state.clone())) as BoxResource))),
+            ("_watchlist", None) =>
+                Box::new(self.changes_lookup.lookup_watchlist(query)),
             _ => Box::new(finished(None)),
         }
     }
@@ -214,7 +304,7 @@ impl WikiLookup {
         Box::new(self.state.lookup_slug(slug.clone())
             .and_then(move |x| Ok(Some(match x {
                 SlugLookup::Miss =>
-                    Box::new(NewArticleResource::new(state, Some(slug))) as BoxResource,
+                    Box::new(NewArticleResource::new(state, Some(slug), None)) as BoxResource,
                 SlugLookup::Hit { article_id, revision } =>
                     Box::new(ArticleResource::new(state, article_id, revision, edit)) as BoxResource,
                 SlugLookup::Redirect(slug) =>